@@ -0,0 +1,115 @@
+/*
+    grampus - a crappy grammar fuzzer
+    Copyright (C) 2022  0xca7
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+    Description:
+        throughput of 'gen' mode, corpora are generated from the
+        grammars in grammars/ with a fixed seed, as the command line
+        does, at random and with k-path coverage. run with
+        `cargo bench`, a grammar name as argument runs only that
+        grammar.
+
+    Notes:
+        no benchmark framework is used, the median of a few rounds
+        is reported.
+
+    Author: 0xca7
+*/
+
+use std::time::{Duration, Instant};
+
+use grampus::corpus::Corpus;
+
+/// the max. number of expansions of a tree, as in main.rs
+const MAX_EXPANSION: usize = 3000;
+/// inputs of each corpus, as in main.rs
+const FOREST_SIZE:   usize = 1000;
+/// corpora generated in one round
+const CORPORA:       usize = 5;
+/// rounds, the median is reported
+const ROUNDS:        usize = 5;
+/// k of the k-path coverage
+const K:             usize = 3;
+
+/// grammar files and their start symbols
+const GRAMMARS: [(&str, &str); 5] = [
+    ("json.txt", "JSON"),
+    ("expr.txt", "start"),
+    ("lisp.txt", "S_EXPRESSION"),
+    ("ini.txt",  "INI"),
+    ("url.txt",  "URL"),
+];
+
+/// generate `CORPORA` corpora, returns the time it took and
+/// the number of bytes generated
+fn round(corpus: &mut Corpus) -> (Duration, usize) {
+    let now = Instant::now();
+    let mut bytes = 0;
+    for _ in 0..CORPORA {
+        corpus.generate();
+        for _ in 0..corpus.len() {
+            bytes += corpus.get_input().len();
+        }
+    }
+    (now.elapsed(), bytes)
+}
+
+fn main() {
+
+    // cargo passes `--bench`, anything else selects grammars
+    let only: Vec<String> = std::env::args().skip(1)
+        .filter(|a| !a.starts_with("--"))
+        .collect();
+
+    println!("{:<10} {:<8} {:>12} {:>12} {:>10}", "grammar", "k-path",
+        "inputs/s", "MB/s", "ms/corpus");
+
+    for (file, start) in GRAMMARS.iter() {
+        if !only.is_empty() && !only.iter().any(|o| file.starts_with(&**o)) {
+            continue;
+        }
+        let path = format!("{}/grammars/{}", env!("CARGO_MANIFEST_DIR"),
+            file);
+        for k in [None, Some(K)] {
+            let mut corpus = match Corpus::new(&path, start, MAX_EXPANSION,
+                FOREST_SIZE) {
+                Ok(corpus) => corpus,
+                Err(e)     => {
+                    println!("[!] {}", e);
+                    break;
+                }
+            };
+            corpus.set_seed(42);
+            // unwrap is safe, `K` is large enough
+            corpus.set_kpath(k).unwrap();
+
+            let mut rounds: Vec<(Duration, usize)> = (0..ROUNDS)
+                .map(|_| round(&mut corpus))
+                .collect();
+            rounds.sort();
+            let (time, bytes) = rounds[ROUNDS / 2];
+            let secs = time.as_secs_f64();
+
+            println!("{:<10} {:<8} {:>12.0} {:>12.2} {:>10.2}", file,
+                k.map_or("-".to_string(), |k| k.to_string()),
+                (CORPORA * FOREST_SIZE) as f64 / secs,
+                bytes as f64 / secs / 1e6,
+                secs * 1e3 / CORPORA as f64);
+        }
+    }
+}
@@ -0,0 +1,804 @@
+/*
+    grampus - a crappy grammar fuzzer
+    Copyright (C) 2022  0xca7
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+    Description:
+        static analysis of a grammar. before fuzzing with a new
+        grammar, it is good to know its shape: how deep and long
+        the shortest derivations are, which languages are finite,
+        how rules recurse and where the grammar is ambiguous.
+
+        ambiguity matters for grampus because `Corpus::generate`
+        deduplicates by tree hash, two different trees for the
+        same string are both kept.
+
+    Notes:
+        string sets are computed by a bounded fixpoint iteration
+        and capped, shortest strings first. for large languages the
+        numbers are only exact up to a smaller length, this is
+        marked in the report.
+
+    Author: 0xca7
+*/
+
+use std::fmt;
+use std::collections::{HashMap, HashSet};
+
+use crate::grammar::Grammar;
+use crate::symbol::Symbol;
+
+/// upper bound of distinct strings kept per symbol
+const MAX_STRINGS: usize = 5000;
+/// derivation counts saturate at this value
+const MAX_COUNT: u64 = 1_000_000;
+/// max. rounds of the fixpoint iteration over string sets
+const MAX_ROUNDS: usize = 64;
+
+/// strings of a language, mapped to their number of derivations
+type Language = HashMap<String, u64>;
+
+/// what is known about the derivations of an alternative
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bounds {
+    /// the minimal cost
+    pub min: Cost,
+    /// the maximal depth, `None` if unbounded
+    pub max_depth: Option<usize>,
+    /// the maximal length, `None` if unbounded
+    pub max_length: Option<usize>,
+}
+
+/// minimal cost to derive a terminal string from a symbol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cost {
+    /// minimal depth of a derivation tree
+    pub depth: usize,
+    /// minimal length of the derived string
+    pub length: usize,
+}
+
+/// the kinds of recursion a non-terminal takes part in
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Recursion {
+    /// A =>+ A ...
+    pub left: bool,
+    /// A =>+ ... A
+    pub right: bool,
+    /// recursion through other non-terminals
+    pub mutual: bool,
+}
+
+/// two alternatives of a rule deriving the same string. if `first`
+/// and `second` are equal, the alternative itself derives `example`
+/// with more than one tree.
+#[derive(Debug, Clone)]
+pub struct Ambiguity {
+    pub first: usize,
+    pub second: usize,
+    pub example: String,
+}
+
+/// the analysis of a single non-terminal
+#[derive(Debug, Clone)]
+pub struct SymbolReport {
+    /// the non-terminal
+    pub symbol: String,
+    /// number of alternatives
+    pub alternatives: usize,
+    /// minimal cost, `None` if the symbol derives no string
+    pub cost: Option<Cost>,
+    /// true if the symbol can be reached from the start symbol
+    pub reachable: bool,
+    /// true if the language of the symbol is finite
+    pub finite: bool,
+    /// distinct strings up to the maximum length
+    pub strings: usize,
+    /// the string set is complete up to this length, it is
+    /// smaller than the maximum length if the set was capped
+    pub exact: usize,
+    /// kinds of recursion
+    pub recursion: Recursion,
+    /// ambiguous alternatives found up to the maximum length
+    pub ambiguities: Vec<Ambiguity>,
+}
+
+/// the analysis of a grammar
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// the start symbol the analysis is done for
+    pub start_symbol: String,
+    /// bound for string lengths
+    pub max_length: usize,
+    /// number of terminals in the grammar
+    pub terminals: usize,
+    /// false if derivation counts did not converge, this
+    /// happens with cyclic rules like `A ::= B` `B ::= A`
+    pub converged: bool,
+    /// per non-terminal reports in the order of the grammar
+    pub symbols: Vec<SymbolReport>,
+}
+
+/// the cost of a single symbol given the costs of non-terminals
+/// known so far, `None` if no cost is known (yet)
+pub fn symbol_cost(g: &Grammar, costs: &[Option<Cost>],
+    symbol: Symbol) -> Option<Cost> {
+    if g.is_terminal(symbol) {
+        Some(Cost { depth: 0, length: g.symbols.text(symbol).len() })
+    } else {
+        costs[symbol]
+    }
+}
+
+/// the cost of an alternative, the depth is one more than
+/// the deepest symbol, the length is the sum of all symbols
+pub fn alternative_cost(g: &Grammar, costs: &[Option<Cost>],
+    alternative: &[Symbol]) -> Option<Cost> {
+
+    let mut cost = Cost { depth: 1, length: 0 };
+
+    for &symbol in alternative {
+        let c = symbol_cost(g, costs, symbol)?;
+        cost.depth = cost.depth.max(c.depth + 1);
+        cost.length += c.length;
+    }
+
+    Some(cost)
+}
+
+/// compute the minimal derivation depth and output length of
+/// every symbol, terminals have a depth of zero. the cost is
+/// `None` for non-terminals which cannot derive a terminal string.
+pub fn min_costs(g: &Grammar) -> Vec<Option<Cost>> {
+
+    let mut costs: Vec<Option<Cost>> = vec![None; g.symbols.len()];
+    for (s, cost) in costs.iter_mut().enumerate() {
+        if g.is_terminal(s) {
+            *cost = symbol_cost(g, &[], s);
+        }
+    }
+    let mut changed = true;
+
+    while changed {
+        changed = false;
+        for &nterm in &g.order {
+            for alternative in &g.productions[nterm] {
+                let c = match alternative_cost(g, &costs, alternative) {
+                    Some(c) => c,
+                    None    => continue,
+                };
+                let entry = costs[nterm].get_or_insert_with(|| {
+                    changed = true;
+                    c
+                });
+                if c.depth < entry.depth {
+                    entry.depth = c.depth;
+                    changed = true;
+                }
+                if c.length < entry.length {
+                    entry.length = c.length;
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    costs
+}
+
+/// the symbols which can derive a non-empty string
+fn non_empty(g: &Grammar, costs: &[Option<Cost>]) -> Vec<bool> {
+
+    let mut result: Vec<bool> = (0..g.symbols.len())
+        .map(|s| g.is_terminal(s) && !g.symbols.text(s).is_empty())
+        .collect();
+    let mut changed = true;
+
+    while changed {
+        changed = false;
+        for &nterm in &g.order {
+            if result[nterm] {
+                continue;
+            }
+            let grows = g.productions[nterm].iter()
+                .filter(|a| alternative_cost(g, costs, a).is_some())
+                .any(|a| a.iter().any(|&s| result[s]));
+            if grows {
+                result[nterm] = true;
+                changed = true;
+            }
+        }
+    }
+
+    result
+}
+
+/// compute the maximal derivation depth of every symbol which
+/// derives a terminal string. the depth is `None` if it is
+/// unbounded, i.e. a recursive rule can be reached.
+pub fn max_depths(g: &Grammar, costs: &[Option<Cost>])
+    -> Vec<Option<usize>> {
+
+    // a derivation without recursion is at most as deep as there
+    // are non-terminals, a deeper one must contain a cycle
+    let bound = g.order.len();
+    let mut depths: Vec<usize> = vec![0; g.symbols.len()];
+
+    for _ in 0..=bound {
+        let mut next = vec![0; g.symbols.len()];
+        for &nterm in g.order.iter().filter(|&&n| costs[n].is_some()) {
+            let depth = g.productions[nterm].iter()
+                .filter(|a| alternative_cost(g, costs, a).is_some())
+                .map(|a| 1 + a.iter().map(|&s| depths[s]).max().unwrap_or(0))
+                .max().unwrap_or(1);
+            next[nterm] = depth.min(bound + 1);
+        }
+        depths = next;
+    }
+
+    (0..g.symbols.len())
+        .map(|s| Some(depths[s])
+            .filter(|&d| costs[s].is_some() && d <= bound))
+        .collect()
+}
+
+/// the symbols with a finite language. a language is infinite
+/// if it can reach a cycle which adds at least one byte per round.
+/// non-terminals which derive no terminal string are finite.
+pub fn finite(g: &Grammar, costs: &[Option<Cost>]) -> Vec<bool> {
+
+    let all = edges(g, costs, true, false);
+    let growing = edges(g, costs, true, true);
+    let reach_all: Vec<HashSet<Symbol>> = (0..g.symbols.len())
+        .map(|n| reach(&all, n)).collect();
+
+    // a non-terminal is pumpable if a growing edge lies on a cycle
+    // through it, i.e. both ends of the edge are in its SCC
+    let in_scc = |n: Symbol, m: Symbol| -> bool {
+        reach_all[n].contains(&m) && reach_all[m].contains(&n)
+    };
+    let pumpable: HashSet<Symbol> = g.order.iter().copied().filter(|&n| {
+        g.order.iter().any(|&u| in_scc(n, u)
+            && growing[u].iter().any(|&v| in_scc(n, v)))
+    }).collect();
+
+    (0..g.symbols.len()).map(|n| costs[n].is_none()
+        || (!pumpable.contains(&n)
+            && !reach_all[n].iter().any(|m| pumpable.contains(m))))
+        .collect()
+}
+
+/// the depths of the trees of every symbol up to `limit`, `sets[s][d]`
+/// is true if `s` has a tree of depth `d`. the depths of a symbol
+/// can have gaps, e.g. `S ::= 'a' | B` `B ::= C` `C ::= S` has trees
+/// of depth 1, 4, 7 and so on.
+pub fn depth_sets(g: &Grammar, costs: &[Option<Cost>], limit: usize)
+    -> Vec<Vec<bool>> {
+
+    let mut sets = vec![vec![false; limit + 1]; g.symbols.len()];
+    for (s, set) in sets.iter_mut().enumerate() {
+        set[0] = g.is_terminal(s);
+    }
+
+    // a tree of depth `d` has children below `d`, at least
+    // one of them of depth `d - 1`
+    for d in 1..=limit {
+        for &nterm in &g.order {
+            let deep = g.productions[nterm].iter().any(|a| {
+                a.iter().all(|&s| costs[s].is_some_and(|c| c.depth < d))
+                    && ((a.is_empty() && d == 1)
+                        || a.iter().any(|&s| sets[s][d - 1]))
+            });
+            sets[nterm][d] = deep;
+        }
+    }
+
+    sets
+}
+
+/// compute the maximal output length of every symbol which
+/// derives a terminal string, `None` if the language is infinite
+pub fn max_lengths(g: &Grammar, costs: &[Option<Cost>])
+    -> Vec<Option<usize>> {
+
+    let finite = finite(g, costs);
+    let mut lengths: Vec<Option<usize>> = (0..g.symbols.len())
+        .map(|s| costs[s].filter(|_| g.is_terminal(s)).map(|c| c.length))
+        .collect();
+    let mut changed = true;
+
+    // the lengths of finite languages only grow until the longest
+    // string is found, cycles in them add no bytes
+    while changed {
+        changed = false;
+        for &nterm in g.order.iter()
+            .filter(|&&n| costs[n].is_some() && finite[n]) {
+            let length = g.productions[nterm].iter()
+                .filter(|a| alternative_cost(g, costs, a).is_some())
+                .map(|a| a.iter().map(|&s| lengths[s].unwrap_or(0))
+                    .sum::<usize>())
+                .max().unwrap_or(0);
+            if lengths[nterm] != Some(length) {
+                lengths[nterm] = Some(length);
+                changed = true;
+            }
+        }
+    }
+
+    lengths
+}
+
+/// edges between non-terminals: A -> B if B occurs in an alternative
+/// of A. with `productive` set, only alternatives deriving a terminal
+/// string are used. with `growing` set, only edges are returned where
+/// the rest of the alternative can derive at least one byte.
+fn edges(g: &Grammar, costs: &[Option<Cost>], productive: bool,
+    growing: bool) -> Vec<HashSet<Symbol>> {
+
+    let mut edges: Vec<HashSet<Symbol>> = vec![HashSet::new(); g.symbols.len()];
+    let non_empty = non_empty(g, costs);
+
+    for &nterm in &g.order {
+        for alternative in &g.productions[nterm] {
+            if productive && alternative_cost(g, costs, alternative).is_none() {
+                continue;
+            }
+            for (i, &symbol) in alternative.iter().enumerate() {
+                if !g.is_non_terminal(symbol) {
+                    continue;
+                }
+                let grows = alternative.iter().enumerate()
+                    .any(|(j, &s)| j != i && non_empty[s]);
+                if growing && !grows {
+                    continue;
+                }
+                edges[nterm].insert(symbol);
+            }
+        }
+    }
+
+    edges
+}
+
+/// all non-terminals reachable from `from` with at least one step
+fn reach(edges: &[HashSet<Symbol>], from: Symbol) -> HashSet<Symbol> {
+
+    let mut seen = HashSet::new();
+    let mut stack: Vec<Symbol> = edges[from].iter().copied().collect();
+
+    while let Some(node) = stack.pop() {
+        if seen.insert(node) {
+            stack.extend(edges[node].iter());
+        }
+    }
+
+    seen
+}
+
+/// edges to non-terminals at the left (or right) end of an alternative,
+/// skipping over symbols which can derive the empty string
+fn edge_ends(g: &Grammar, costs: &[Option<Cost>], left: bool)
+    -> Vec<HashSet<Symbol>> {
+
+    let mut edges: Vec<HashSet<Symbol>> = vec![HashSet::new(); g.symbols.len()];
+
+    for &nterm in &g.order {
+        for alternative in &g.productions[nterm] {
+            let symbols: Vec<Symbol> = if left {
+                alternative.clone()
+            } else {
+                alternative.iter().rev().copied().collect()
+            };
+            for symbol in symbols {
+                if g.is_non_terminal(symbol) && !g.is_terminal(symbol) {
+                    edges[nterm].insert(symbol);
+                }
+                let nullable = symbol_cost(g, costs, symbol)
+                    .is_some_and(|c| c.length == 0);
+                if !nullable {
+                    break;
+                }
+            }
+        }
+    }
+
+    edges
+}
+
+/// the strings of a language grouped by length, each group sorted
+fn by_length(l: &Language, max_length: usize) -> Vec<Vec<(&String, u64)>> {
+
+    let mut groups = vec![Vec::new(); max_length + 1];
+
+    for (s, c) in l {
+        if s.len() <= max_length {
+            groups[s.len()].push((s, *c));
+        }
+    }
+    for group in &mut groups {
+        group.sort();
+    }
+
+    groups
+}
+
+/// keep only the `MAX_STRINGS` shortest strings of a language.
+/// returns the length up to which the language is still complete.
+fn truncate(l: &mut Language, max_length: usize) -> usize {
+
+    if l.len() <= MAX_STRINGS {
+        return max_length;
+    }
+
+    let mut strings: Vec<String> = l.keys().cloned().collect();
+    strings.sort_by(|a, b| a.len().cmp(&b.len()).then(a.cmp(b)));
+    for s in &strings[MAX_STRINGS..] {
+        l.remove(s);
+    }
+
+    strings[MAX_STRINGS].len().saturating_sub(1)
+}
+
+/// concatenate two languages, strings longer than `max_length`
+/// are dropped. the result is built shortest strings first and
+/// capped at `MAX_STRINGS`, returns the length up to which the
+/// result is complete.
+fn concat(a: &Language, b: &Language, max_length: usize)
+    -> (Language, usize) {
+
+    let mut result = Language::new();
+
+    let a = by_length(a, max_length);
+    let b = by_length(b, max_length);
+
+    for total in 0..=max_length {
+        for la in 0..=total {
+            for (sa, ca) in &a[la] {
+                for (sb, cb) in &b[total - la] {
+                    let key = format!("{}{}", sa, sb);
+                    if result.len() >= MAX_STRINGS 
+                        && !result.contains_key(&key) {
+                        return (result, total.saturating_sub(1));
+                    }
+                    let count = result.entry(key).or_insert(0);
+                    *count = (*count + ca.saturating_mul(*cb)).min(MAX_COUNT);
+                }
+            }
+        }
+    }
+
+    (result, max_length)
+}
+
+/// the language of an alternative given the languages of the
+/// non-terminals and the lengths up to which they are complete.
+/// returns the language and the length up to which it is complete.
+fn alternative_language(g: &Grammar, costs: &[Option<Cost>],
+    languages: &HashMap<Symbol, Language>, exact: &[usize],
+    alternative: &[Symbol], max_length: usize) -> (Language, usize) {
+
+    let mut result = Language::new();
+    result.insert(String::new(), 1);
+
+    // the result is complete up to `complete`, its shortest
+    // string has a length of `min_length`
+    let mut complete = max_length;
+    let mut min_length = 0;
+
+    for &symbol in alternative {
+        let (language, e) = if g.is_terminal(symbol) {
+            let mut l = Language::new();
+            l.insert(g.symbols.text(symbol).to_string(), 1);
+            (l, max_length)
+        } else {
+            match languages.get(&symbol) {
+                Some(l) => (l.clone(), exact[symbol]),
+                None    => (Language::new(), max_length),
+            }
+        };
+        let m = symbol_cost(g, costs, symbol).map_or(0, |c| c.length);
+        let (r, c) = concat(&result, &language, max_length);
+        // a string of the concatenation is only complete if both
+        // parts are complete
+        complete = c.min(complete + m).min(e + min_length);
+        min_length += m;
+        result = r;
+    }
+
+    (result, complete.min(max_length))
+}
+
+/// the shortest string (ties broken alphabetically) of a set
+fn shortest<'a, I: Iterator<Item = &'a String>>(strings: I)
+    -> Option<&'a String> {
+    strings.min_by(|a, b| a.len().cmp(&b.len()).then(a.cmp(b)))
+}
+
+/// run the analysis of grammar `g` from `start_symbol`, the string
+/// sets are computed for strings up to `max_length` bytes
+pub fn analyze(g: &Grammar, start_symbol: &str, max_length: usize)
+    -> Report {
+
+    let costs = min_costs(g);
+
+    // languages of the non-terminals up to `max_length`
+    let mut languages: HashMap<Symbol, Language> = HashMap::new();
+    let mut exact: Vec<usize> = vec![max_length; g.symbols.len()];
+    let mut converged = false;
+
+    for _ in 0..MAX_ROUNDS {
+        let mut changed = false;
+        for &nterm in &g.order {
+            let mut language = Language::new();
+            let mut complete = max_length;
+            for alternative in &g.productions[nterm] {
+                let (l, e) = alternative_language(g, &costs, &languages,
+                    &exact, alternative, max_length);
+                complete = complete.min(e);
+                for (s, c) in l {
+                    let count = language.entry(s).or_insert(0);
+                    *count = (*count + c).min(MAX_COUNT);
+                }
+            }
+            complete = complete.min(truncate(&mut language, max_length));
+            if languages.get(&nterm) != Some(&language)
+                || exact[nterm] != complete {
+                languages.insert(nterm, language);
+                exact[nterm] = complete;
+                changed = true;
+            }
+        }
+        if !changed {
+            converged = true;
+            break;
+        }
+    }
+
+    let finite = finite(g, &costs);
+
+    // recursion
+    let full = edges(g, &costs, false, false);
+    let left = edge_ends(g, &costs, true);
+    let right = edge_ends(g, &costs, false);
+    let reach_full: Vec<HashSet<Symbol>> = (0..g.symbols.len())
+        .map(|n| reach(&full, n)).collect();
+
+    let mut reachable = HashSet::new();
+    if let Some(start) = g.non_terminal(start_symbol) {
+        reachable = reach(&full, start);
+        reachable.insert(start);
+    }
+
+    let mut symbols = Vec::new();
+
+    for &nterm in &g.order {
+
+        let recursion = Recursion {
+            left:   reach(&left, nterm).contains(&nterm),
+            right:  reach(&right, nterm).contains(&nterm),
+            mutual: reach_full[nterm].iter().any(|&m| m != nterm
+                && reach_full[m].contains(&nterm)),
+        };
+
+        let finite = finite[nterm];
+
+        // ambiguities between and within alternatives
+        let alternatives: Vec<Language> = g.productions[nterm].iter()
+            .map(|a| alternative_language(g, &costs, &languages, &exact,
+                a, max_length).0)
+            .collect();
+
+        let mut ambiguities = Vec::new();
+        for (i, li) in alternatives.iter().enumerate() {
+            if let Some(s) = shortest(li.iter()
+                .filter(|(_, c)| **c > 1).map(|(s, _)| s)) {
+                ambiguities.push(Ambiguity {
+                    first: i, second: i, example: s.clone()
+                });
+            }
+            for (j, lj) in alternatives.iter().enumerate().skip(i + 1) {
+                if let Some(s) = shortest(li.keys()
+                    .filter(|s| lj.contains_key(*s))) {
+                    ambiguities.push(Ambiguity {
+                        first: i, second: j, example: s.clone()
+                    });
+                }
+            }
+        }
+
+        symbols.push(SymbolReport {
+            symbol:         g.name(nterm).to_string(),
+            alternatives:   g.productions[nterm].len(),
+            cost:           costs[nterm],
+            reachable:      reachable.contains(&nterm),
+            finite,
+            strings:        languages.get(&nterm).map_or(0, |l| l.len()),
+            exact:          exact[nterm],
+            recursion,
+            ambiguities,
+        });
+    }
+
+    Report {
+        start_symbol: start_symbol.to_string(),
+        max_length,
+        terminals: g.productions.iter().flatten().flatten()
+            .filter(|&&s| g.is_terminal(s))
+            .collect::<HashSet<_>>().len(),
+        converged,
+        symbols,
+    }
+}
+
+impl fmt::Display for Recursion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut kinds = Vec::new();
+        if self.left   { kinds.push("left"); }
+        if self.right  { kinds.push("right"); }
+        if self.mutual { kinds.push("mutual"); }
+        if kinds.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", kinds.join(", "))
+        }
+    }
+}
+
+impl fmt::Display for Report {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+
+        writeln!(f, "\n[ Grammar Analysis ]")?;
+        writeln!(f, "+----------------------------------+")?;
+        writeln!(f, "| [start]       {}", self.start_symbol)?;
+        writeln!(f, "| [rules]       {}", self.symbols.len())?;
+        writeln!(f, "| [terminals]   {}", self.terminals)?;
+        writeln!(f, "| [max length]  {}", self.max_length)?;
+        writeln!(f, "+----------------------------------+")?;
+
+        for s in &self.symbols {
+            writeln!(f, "\n{} ({} alternatives)", s.symbol, s.alternatives)?;
+            if !s.reachable {
+                writeln!(f, "    unreachable from {}", self.start_symbol)?;
+            }
+            match s.cost {
+                Some(c) => {
+                    writeln!(f, "    min. depth        {}", c.depth)?;
+                    writeln!(f, "    min. length       {}", c.length)?;
+                },
+                None => writeln!(f, "    derives no terminal string")?,
+            }
+            writeln!(f, "    language          {}",
+                if s.finite { "finite" } else { "infinite" })?;
+            if s.exact < self.max_length {
+                writeln!(f, "    strings <= {:<6} {} (capped, exact up to \
+                    length {})", self.max_length, s.strings, s.exact)?;
+            } else {
+                writeln!(f, "    strings <= {:<6} {}", self.max_length,
+                    s.strings)?;
+            }
+            writeln!(f, "    recursion         {}", s.recursion)?;
+            for a in &s.ambiguities {
+                if a.first == a.second {
+                    writeln!(f, "    ambiguous         alternative {} has \
+                        several trees for {:?}", a.first, a.example)?;
+                } else {
+                    writeln!(f, "    ambiguous         alternatives {} and \
+                        {} both derive {:?}", a.first, a.second, a.example)?;
+                }
+            }
+        }
+
+        if !self.converged {
+            writeln!(f, "\n[!] derivation counts did not converge, the \
+                grammar has cyclic rules and is infinitely ambiguous")?;
+        }
+
+        Ok(())
+    }
+
+}
+
+
+// TESTING
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the report of the non-terminal `name`
+    fn symbol<'a>(report: &'a Report, name: &str) -> &'a SymbolReport {
+        // unwrap is safe, the tests only ask for rules of the grammar
+        report.symbols.iter().find(|s| s.symbol == name).unwrap()
+    }
+
+    #[test]
+    fn test_expr() {
+        let g = Grammar::new("grammars/expr.txt", 1000).unwrap();
+        let n = |name: &str| g.non_terminal(name).unwrap();
+        let costs = min_costs(&g);
+
+        assert_eq!(costs[n("digit")], Some(Cost { depth: 1, length: 1 }));
+        assert_eq!(costs[n("integer")], Some(Cost { depth: 2, length: 1 }));
+        assert_eq!(costs[n("start")], Some(Cost { depth: 6, length: 1 }));
+
+        let finite = finite(&g, &costs);
+        assert!(finite[n("digit")]);
+        assert!(!finite[n("integer")]);
+        assert!(!finite[n("start")]);
+        assert_eq!(max_depths(&g, &costs)[n("digit")], Some(1));
+        assert_eq!(max_depths(&g, &costs)[n("expr")], None);
+        assert_eq!(max_lengths(&g, &costs)[n("digit")], Some(1));
+        assert_eq!(max_lengths(&g, &costs)[n("start")], None);
+
+        let report = analyze(&g, "start", 3);
+        assert!(report.converged);
+        assert_eq!(report.terminals, 17);
+
+        let digit = symbol(&report, "digit");
+        assert_eq!((digit.strings, digit.exact), (10, 3));
+        assert_eq!(digit.recursion.to_string(), "none");
+
+        // 10 + 100 + 1000 strings of up to 3 digits
+        let integer = symbol(&report, "integer");
+        assert_eq!((integer.strings, integer.exact), (1110, 3));
+        assert!(integer.recursion.right && !integer.recursion.left);
+
+        let expr = symbol(&report, "expr");
+        assert!(expr.recursion.right && expr.recursion.mutual);
+        assert!(expr.ambiguities.is_empty());
+        assert_eq!(symbol(&report, "start").strings, expr.strings);
+    }
+
+    #[test]
+    fn test_finite() {
+        let file = crate::util::test_path("finite.txt");
+        std::fs::write(&file, "GREETING ::= WORD '-' WORD | WORD\n\
+            WORD ::= 'hi' | 'yo' | 'h' 'i'\n\
+            EXTRA ::= 'x'\n").unwrap();
+        let g = Grammar::new(file.to_str().unwrap(), 1000).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        let n = |name: &str| g.non_terminal(name).unwrap();
+        let costs = min_costs(&g);
+
+        assert_eq!(costs[n("GREETING")], Some(Cost { depth: 2, length: 2 }));
+        assert!(finite(&g, &costs)[n("GREETING")]);
+        assert_eq!(max_depths(&g, &costs)[n("GREETING")], Some(2));
+        assert_eq!(max_lengths(&g, &costs)[n("GREETING")], Some(5));
+
+        let report = analyze(&g, "GREETING", 5);
+        assert!(report.converged);
+
+        // "hi" and "yo", and the 4 pairs of them
+        let greeting = symbol(&report, "GREETING");
+        assert_eq!((greeting.strings, greeting.exact), (6, 5));
+        assert!(greeting.finite && greeting.reachable);
+        assert_eq!(greeting.recursion.to_string(), "none");
+        let ambiguities: Vec<(usize, usize, &str)> = greeting.ambiguities
+            .iter().map(|a| (a.first, a.second, &a.example[..])).collect();
+        assert_eq!(ambiguities, vec![(0, 0, "hi-hi"), (1, 1, "hi")]);
+
+        let word = symbol(&report, "WORD");
+        assert_eq!(word.strings, 2);
+        assert_eq!(word.ambiguities.len(), 1);
+        assert_eq!((word.ambiguities[0].first, word.ambiguities[0].second),
+            (0, 2));
+        assert_eq!(word.ambiguities[0].example, "hi");
+
+        assert!(!symbol(&report, "EXTRA").reachable);
+    }
+}
@@ -0,0 +1,404 @@
+/*
+    grampus - a crappy grammar fuzzer
+    Copyright (C) 2022  0xca7
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+    Description:
+        corpus minimization. each input covers a set of features,
+        either the alternatives of the grammar used by its syntax
+        tree or the behaviour of the target on it, the exit status
+        and signal and the output. a small subset of the inputs which
+        covers all features is kept, the others are dropped.
+
+        the syntax tree of an input is taken from a saved corpus if
+        it has the input, else the input is parsed. a target which
+        runs longer than the timeout is killed, its behaviour is
+        the timeout.
+
+    Notes:
+        finding the smallest subset is NP-hard, the subset is chosen
+        greedily like afl-cmin: the input which covers the most
+        features not covered yet is kept first, the smaller one if
+        inputs cover as many.
+
+    Author: 0xca7
+*/
+
+use std::thread;
+use std::fs::{self, File};
+use std::time::{Duration, Instant};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::collections::{HashMap, HashSet};
+use std::io::{BufWriter, Read, Write};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+
+extern crate fnv;
+use fnv::FnvHash;
+
+use crate::grammar::Grammar;
+use crate::parser::Parser;
+use crate::symbol::Symbol;
+use crate::syntax_tree::SyntaxTree;
+
+/// the file the report of a minimization is written to
+pub const CMIN_FILE: &str = "cmin.txt";
+
+/// milliseconds between two checks if the target exited
+const POLL_INTERVAL: u64 = 10;
+
+/// the signal which kills a process, see kill(2)
+const SIGKILL: i32 = 9;
+
+extern "C" {
+    /// send `signal` to the process `pid`, or the process group
+    /// `-pid`, see kill(2)
+    fn kill(pid: i32, signal: i32) -> i32;
+}
+
+/// kill the process group `group`, a target and its children
+/// WARNING: contains unsafe code
+fn kill_group(group: u32) {
+    // kill(2) does not touch memory, it only fails if the
+    // group is gone already
+    unsafe {
+        kill(-(group as i32), SIGKILL);
+    }
+}
+
+/// what the kept inputs have to cover
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CminKind {
+    /// the alternatives used by the syntax trees
+    Grammar,
+    /// the exit status, signal and output of the target
+    Behaviour,
+}
+
+impl CminKind {
+
+    /// parse a kind of coverage from its name
+    pub fn from_name(name: &str) -> Option<CminKind> {
+        match name {
+            "grammar"   => Some(CminKind::Grammar),
+            "behaviour" => Some(CminKind::Behaviour),
+            _           => None,
+        }
+    }
+
+}
+
+/// something an input covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    /// an alternative of a rule is used by the syntax tree
+    Alternative(Symbol, usize),
+    /// the target exits with a status or is killed by a signal
+    Status(Option<i32>, Option<i32>),
+    /// the target writes an output with this hash
+    Output(u64),
+    /// the target runs longer than the timeout and is killed
+    Timeout,
+}
+
+/// an input of the corpus and what it covers
+#[derive(Debug, Clone)]
+pub struct Input {
+    /// the file of the input
+    pub file:     PathBuf,
+    /// the size of the file in bytes
+    pub size:     usize,
+    /// the features covered, an error if the input could not
+    /// be measured, e.g. if it did not parse
+    pub features: Result<HashSet<Feature>, String>,
+}
+
+/// the alternatives used by `tree`
+fn alternatives(g: &Grammar, tree: &SyntaxTree) -> HashSet<Feature> {
+    tree.preorder(SyntaxTree::ROOT)
+        .filter_map(|id| {
+            let children = tree.child_values(id)?;
+            let nterm = tree.value(id);
+            g.productions[nterm].iter().position(|a| *a == children)
+                .map(|alt| Feature::Alternative(nterm, alt))
+        })
+        .collect()
+}
+
+/// the behaviour of `target` on the input `file`. if it runs longer
+/// than `timeout`, it is killed and only the timeout is covered.
+fn behaviour(target: &str, file: &Path, timeout: Duration)
+    -> Result<HashSet<Feature>, String> {
+
+    // the target and its children are in their own process
+    // group, so they are killed together
+    let mut child = Command::new(target)
+        .arg(file)
+        .process_group(0)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+
+    // the output is read meanwhile, else a target with a lot
+    // of output blocks on a full pipe. unwrap is safe, stdout
+    // is piped
+    let mut stdout = child.stdout.take().unwrap();
+    let reader = thread::spawn(move || {
+        let mut output = Vec::new();
+        stdout.read_to_end(&mut output).map(|_| output)
+    });
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+            break status;
+        }
+        if start.elapsed() >= timeout {
+            // the group is killed before the target is waited for,
+            // so its id is not taken by another group meanwhile
+            kill_group(child.id());
+            child.wait().map_err(|e| e.to_string())?;
+            // unwrap is safe, the reader does not panic
+            let _ = reader.join().unwrap();
+            return Ok([Feature::Timeout].iter().copied().collect());
+        }
+        thread::sleep(Duration::from_millis(POLL_INTERVAL));
+    };
+
+    // children of the target which are still running may keep
+    // the output open, else the reader would wait for them
+    if !reader.is_finished() {
+        thread::sleep(Duration::from_millis(POLL_INTERVAL));
+        if !reader.is_finished() {
+            kill_group(child.id());
+        }
+    }
+
+    // unwrap is safe, the reader does not panic
+    let output = reader.join().unwrap().map_err(|e| e.to_string())?;
+    let mut fnv = FnvHash::new();
+    Ok([
+        Feature::Status(status.code(), status.signal()),
+        Feature::Output(fnv.hash(&output)),
+    ].iter().copied().collect())
+}
+
+/// read the `files` and measure what they cover, the syntax trees
+/// of `start_symbol` or the behaviour of `target`, which is killed
+/// after `timeout`. the tree of an input is looked up in `trees`,
+/// e.g. those of a saved corpus, and parsed if it is not there.
+pub fn measure(g: &Grammar, start_symbol: &str, files: &[PathBuf],
+    kind: CminKind, target: Option<&str>, timeout: Duration,
+    trees: &HashMap<&[u8], &SyntaxTree>) -> Vec<Input> {
+
+    let parser = Parser::new(g);
+
+    files.iter().map(|file| {
+        let input = fs::read(file).map_err(|e| e.to_string());
+        let features = input.as_ref().map_err(|e| e.clone())
+            .and_then(|input| match (kind, target) {
+                (CminKind::Grammar, _) => match trees.get(&input[..]) {
+                    Some(tree) => Ok(alternatives(g, tree)),
+                    None => parser.parse(start_symbol, input)
+                        .map(|tree| alternatives(g, &tree))
+                        .map_err(|e| e.to_string()),
+                },
+                (CminKind::Behaviour, Some(target)) => behaviour(target,
+                    file, timeout),
+                (CminKind::Behaviour, None) => Err("no target".to_string()),
+            });
+        Input {
+            file: file.clone(),
+            size: input.map_or(0, |i| i.len()),
+            features,
+        }
+    }).collect()
+}
+
+/// the indices of the inputs to keep, so all features of `inputs`
+/// are covered, in the order they were chosen
+pub fn minimize(inputs: &[Input]) -> Vec<usize> {
+
+    let mut covered: HashSet<Feature> = HashSet::new();
+    let mut kept = Vec::new();
+
+    loop {
+        let best = inputs.iter().enumerate()
+            .filter_map(|(i, input)| input.features.as_ref().ok()
+                .map(|f| (i, f.difference(&covered).count(), input.size)))
+            .filter(|&(_, new, _)| new > 0)
+            .max_by_key(|&(i, new, size)| (new, std::cmp::Reverse((size, i))));
+        match best {
+            Some((i, _, _)) => {
+                // unwrap is safe, only measured inputs are chosen
+                covered.extend(inputs[i].features.as_ref().unwrap());
+                kept.push(i);
+            },
+            None => return kept,
+        }
+    }
+}
+
+/// copy the `kept` inputs to the directory `dir`, which must not
+/// exist or be empty
+pub fn write(inputs: &[Input], kept: &[usize], dir: &str)
+    -> std::io::Result<()> {
+
+    fs::create_dir_all(dir)?;
+    if fs::read_dir(dir)?.next().is_some() {
+        return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists,
+            format!("{} is not empty", dir)));
+    }
+
+    for &i in kept {
+        // unwrap is safe, read_dir only yields files with names
+        let name = inputs[i].file.file_name().unwrap();
+        fs::copy(&inputs[i].file, Path::new(dir).join(name))?;
+    }
+
+    Ok(())
+}
+
+/// write a report of the kept inputs and why the others were
+/// dropped to `path`
+pub fn write_report(inputs: &[Input], kept: &[usize], path: &str)
+    -> std::io::Result<()> {
+
+    let mut w = BufWriter::new(File::create(path)?);
+
+    let features: HashSet<Feature> = inputs.iter()
+        .filter_map(|input| input.features.as_ref().ok())
+        .flatten()
+        .copied()
+        .collect();
+
+    writeln!(w, "[ Corpus Minimization ]")?;
+    writeln!(w, "kept {} of {} inputs, {} features", kept.len(),
+        inputs.len(), features.len())?;
+
+    writeln!(w, "\nkept, in the order chosen:")?;
+    let mut covered: HashSet<Feature> = HashSet::new();
+    for &i in kept {
+        // unwrap is safe, only measured inputs are kept
+        let f = inputs[i].features.as_ref().unwrap();
+        let new = f.difference(&covered).count();
+        covered.extend(f);
+        writeln!(w, "    {} ({} bytes, {} new features)",
+            inputs[i].file.display(), inputs[i].size, new)?;
+    }
+
+    writeln!(w, "\ndropped:")?;
+    let kept: HashSet<usize> = kept.iter().copied().collect();
+    for (i, input) in inputs.iter().enumerate() {
+        if kept.contains(&i) {
+            continue;
+        }
+        match &input.features {
+            Ok(_)  => writeln!(w, "    {}: covered by the kept inputs",
+                input.file.display())?,
+            Err(e) => writeln!(w, "    {}: {}", input.file.display(), e)?,
+        }
+    }
+
+    Ok(())
+}
+
+
+// TESTING
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minimize() {
+        let input = |name: &str, size: usize, features: &[Feature]| Input {
+            file: PathBuf::from(name),
+            size,
+            features: Ok(features.iter().copied().collect()),
+        };
+        let (a, b, c) = (Feature::Alternative(0, 0),
+            Feature::Alternative(0, 1), Feature::Output(7));
+        let inputs = vec![
+            input("large", 100, &[a, b]),
+            input("small", 10, &[a, b]),
+            input("only", 50, &[c]),
+            input("covered", 1, &[a]),
+            Input {
+                file: PathBuf::from("broken"),
+                size: 1,
+                features: Err("syntax error".to_string()),
+            },
+        ];
+        // the smaller of the two covering a and b, then c
+        assert_eq!(minimize(&inputs), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_measure() {
+        let dir = crate::util::test_path("cmin");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let file = |name: &str, content: &str| {
+            let path = dir.join(name);
+            fs::write(&path, content).unwrap();
+            path
+        };
+
+        // a target which hangs on some inputs, or whose child
+        // hangs and keeps the output open
+        let target = file("target.sh", "#!/bin/sh\n\
+            grep -q hang \"$1\" && exec sleep 10\n\
+            grep -q fork \"$1\" && sleep 10\necho done\n");
+        fs::set_permissions(&target,
+            std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+        let files = [file("hang", "hang"), file("fork", "fork"),
+            file("run", "run")];
+        let g = Grammar::new("grammars/expr.txt", 1000).unwrap();
+        let start = Instant::now();
+        let inputs = measure(&g, "start", &files, CminKind::Behaviour,
+            target.to_str(), Duration::from_millis(200), &HashMap::new());
+        // the child is killed with the target, else the output
+        // would be read until it exits
+        assert!(start.elapsed() < Duration::from_secs(5));
+        let timeout: HashSet<Feature> = [Feature::Timeout].iter().copied()
+            .collect();
+        assert_eq!(inputs[0].features, Ok(timeout.clone()));
+        assert_eq!(inputs[1].features, Ok(timeout));
+        assert!(inputs[2].features.as_ref().unwrap()
+            .contains(&Feature::Status(Some(0), None)));
+
+        // a tree of a saved corpus is used, even if the input
+        // does not parse, e.g. in another layout
+        let spaced = file("spaced", "1 + 2");
+        let parser = Parser::new(&g);
+        let tree = parser.parse("start", b"1+2").unwrap();
+        let trees: HashMap<&[u8], &SyntaxTree> =
+            [(&b"1 + 2"[..], &tree)].iter().copied().collect();
+        let files = [spaced];
+        let inputs = measure(&g, "start", &files, CminKind::Grammar,
+            None, Duration::from_secs(1), &HashMap::new());
+        assert!(inputs[0].features.is_err());
+        let inputs = measure(&g, "start", &files, CminKind::Grammar,
+            None, Duration::from_secs(1), &trees);
+        assert_eq!(inputs[0].features, Ok(alternatives(&g, &tree)));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -26,85 +26,486 @@
         0xca7
 */
 
-use std::fs::File;
 use std::collections::HashSet;
-use std::io::{BufWriter, Write};
+use std::sync::{Arc, Mutex};
 
 extern crate xshift;
 use xshift::XorShift64;
 
+extern crate fnv;
+use fnv::FnvHash;
+
 use crate::util::generate_seed;
 use crate::grammar::Grammar;
-use crate::syntax_tree::TreeNode;
+use crate::symbol::Symbol;
+use crate::syntax_tree::{SyntaxTree, NodeId};
+use crate::dictionary::{Dictionary, DICT_SYMBOL};
+use crate::layout::LayoutKind;
+use crate::analysis::{Bounds, Cost};
+use crate::stats::Distribution;
+use crate::uniform::{self, Counts};
+use crate::enumerate::Enumerator;
+use crate::coverage::{self, CoverageKind, GrammarCoverage};
+use crate::kpath::{self, KPaths};
+use crate::vm::Program;
+use crate::decision::{self, Decisions};
+use crate::parser::{self, Parser};
+use crate::known::Known;
+use crate::snapshot::{Snapshot, Entry};
+
+/// max. depth of a derivation tree until which alternatives are
+/// chosen at random, deeper only minimal-cost alternatives are used.
+/// this bounds the depth of trees which need not be deeper.
+pub(crate) const MAX_DEPTH: usize = 256;
+
+/// number of duplicate trees in a row after which the
+/// generation of a corpus stops, see `capacity`
+const MAX_RETRIES: usize = 1000;
+
+/// number of partial derivations expanded to count the
+/// trees of a bounded language, see `capacity`
+const MAX_ENUMERATED: usize = 100_000;
+
+/// number of known inputs in a row after which a known
+/// input is taken, see `set_known`
+const MAX_REPEATS: usize = 100;
+
+/// number of known inputs taken in a row after which known
+/// inputs are taken right away, until a new one turns up
+const MAX_EXHAUSTED: usize = 10;
+
+/// a range of sizes, e.g. of the depth or the output length of a tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Range {
+    /// the size is at least this
+    min: usize,
+    /// the size is at most this
+    max: usize,
+}
+
+impl Range {
+
+    /// no requirements on the size
+    fn any() -> Range {
+        Range { min: 0, max: usize::MAX }
+    }
+
+    /// the range as `min..max`, without `max` if unbounded
+    fn to_param(self) -> String {
+        match self.max {
+            usize::MAX => format!("{}..", self.min),
+            max        => format!("{}..{}", self.min, max),
+        }
+    }
+
+    /// true if sizes between `min` and `max` can be in this range,
+    /// `max` is `None` if unbounded
+    fn overlaps(&self, min: usize, max: Option<usize>) -> bool {
+        min <= self.max && max.is_none_or(|m| m >= self.min)
+    }
+
+}
+
+/// the shape a (sub-)tree should have
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Target {
+    /// range of the depth of the tree
+    depth:  Range,
+    /// range of the output length of the tree in bytes
+    length: Range,
+}
+
+impl Target {
+
+    /// true if a tree with `bounds` can have this shape
+    fn fits(&self, bounds: &Bounds) -> bool {
+        self.depth.overlaps(bounds.min.depth, bounds.max_depth)
+            && self.length.overlaps(bounds.min.length, bounds.max_length)
+    }
+
+}
+
+/// choose one of `candidates` at random, `None` if there are none
+pub(crate) fn choose(rand: &mut XorShift64, candidates: &[usize])
+    -> Option<usize> {
+    match candidates.len() {
+        0 => None,
+        n => Some(candidates[rand.rand() as usize % n]),
+    }
+}
+
+/// choose one of `candidates` with the given `probabilities`,
+/// at random if none of them is likely
+pub(crate) fn choose_weighted(rand: &mut XorShift64, candidates: &[usize],
+    probabilities: &[f64]) -> Option<usize> {
+
+    let total: f64 = candidates.iter().map(|&i| probabilities[i]).sum();
+    if total <= 0.0 {
+        return choose(rand, candidates);
+    }
+
+    let mut r = rand.rand() as f64 / u64::MAX as f64 * total;
+    for &i in candidates {
+        if r < probabilities[i] {
+            return Some(i);
+        }
+        r -= probabilities[i];
+    }
+    candidates.iter().rev().copied().find(|&i| probabilities[i] > 0.0)
+}
+
+/// choose one of the indices below `n` for which `f` is true at
+/// random, like `choose` on the collected indices but without
+/// collecting them
+fn choose_where(rand: &mut XorShift64, n: usize, f: impl Fn(usize) -> bool)
+    -> Option<usize> {
+    match (0..n).filter(|&i| f(i)).count() {
+        0     => None,
+        count => (0..n).filter(|&i| f(i))
+            .nth(rand.rand() as usize % count),
+    }
+}
+
+/// min. and max. depth and length of the trees of `symbol`
+fn size(g: &Grammar, symbol: Symbol) -> Bounds {
+    Bounds {
+        min:        g.costs[symbol].unwrap_or(Cost { depth: 0, length: 0 }),
+        max_depth:  g.max_depths[symbol],
+        max_length: g.max_lengths[symbol],
+    }
+}
+
+/// a non-terminal being derived by `derive`
+struct Frame {
+    /// the node of the non-terminal
+    node:     NodeId,
+    /// the shape its tree should have
+    target:   Target,
+    /// the non-terminal and its chosen alternative
+    value:    Symbol,
+    alt:      usize,
+    /// the child which has to reach the min. depth
+    deep:     Option<usize>,
+    /// the child which has to make up for the missing length
+    long:     Option<usize>,
+    /// the next child to derive
+    next:     usize,
+    /// the length of the children derived so far
+    length:   usize,
+    /// the children after the next one add at least `min_rest`
+    /// and, unless `unbounded` of them can grow without limit,
+    /// at most `max_rest` to the length
+    min_rest:  usize,
+    max_rest:  usize,
+    unbounded: usize,
+}
+
+impl Frame {
+
+    /// the symbols of the chosen alternative in `g`
+    fn alternative<'g>(&self, g: &'g Grammar) -> &'g [Symbol] {
+        &g.productions[self.value][self.alt]
+    }
+
+    /// move on to the next child, the index of the child is returned.
+    /// the children after it are taken into account in `child_target`.
+    fn next_child(&mut self, g: &Grammar) -> usize {
+        let i = self.next;
+        let size = size(g, self.alternative(g)[i]);
+        self.min_rest -= size.min.length;
+        match size.max_length {
+            Some(max) => self.max_rest -= max,
+            None      => self.unbounded -= 1,
+        }
+        self.next += 1;
+        i
+    }
+
+    /// the shape of child `i`, the children before it are derived
+    /// and `next_child` returned `i`
+    fn child_target(&self, i: usize) -> Target {
+
+        // what the children after this one add at least
+        // and at most
+        let min_rest = self.min_rest;
+        let max_rest = Some(self.max_rest).filter(|_| self.unbounded == 0);
+
+        let target = &self.target;
+        let min_length = target.length.min.saturating_sub(self.length);
+        Target {
+            depth: Range {
+                min: if self.deep == Some(i) {
+                    target.depth.min.saturating_sub(1)
+                } else {
+                    0
+                },
+                max: target.depth.max.saturating_sub(1),
+            },
+            length: Range {
+                min: if self.long == Some(i) {
+                    min_length.saturating_sub(min_rest)
+                } else {
+                    max_rest.map_or(0, |m| min_length.saturating_sub(m))
+                },
+                max: target.length.max.saturating_sub(self.length)
+                    .saturating_sub(min_rest),
+            },
+        }
+    }
+
+}
+
+/// expand the non-terminal `node` of `tree` by an alternative which
+/// fits the `target` shape at `depth`, see `derive`. `kpaths` come
+/// with the last k non-terminals above `node`. `candidates` is
+/// reused for each node, so no memory is allocated.
+#[allow(clippy::too_many_arguments)]
+fn expand(rand: &mut XorShift64, tree: &mut SyntaxTree, node: NodeId,
+    g: &mut Grammar, coverage: &mut GrammarCoverage,
+    kpaths: Option<(&KPaths, &[Symbol])>, depth: usize,
+    target: Target, candidates: &mut Vec<usize>) -> Frame {
+
+    let value = tree.value(node);
+
+    // keep track of non-terminals in derivation
+    g.no_non_terminals += 1;
+    
+    // there are no alternatives if there is a syntax error in
+    // the grammar, thus check this here and exit if there is
+    // a problem
+    let derivation = &g.productions[value];
+    if derivation.is_empty() {
+        println!("unidentified symbol {}, aborting", g.name(value));
+        std::process::exit(1);
+    }
+    let bounds = &g.bounds[value];
+
+    // without a min. size, all alternatives fit if the
+    // largest of them fits
+    candidates.clear();
+    let (viable, largest) = &g.viable[value];
+    if target.depth.min <= 1 && target.length.min == 0
+        && largest.depth <= target.depth.max
+        && largest.length <= target.length.max {
+        candidates.extend(viable);
+    } else {
+        candidates.extend((0..derivation.len())
+            .filter(|&i| bounds[i].is_some_and(|b| target.fits(&b))
+                && (target.depth.min <= 1 || g.alternative_has_depth(
+                    &derivation[i], target.depth.min, target.depth.max))));
+    }
+    let random = !candidates.is_empty()
+        && g.no_non_terminals < g.max_non_terminals
+        && depth < MAX_DEPTH;
+
+    if !random {
+        // if we have reached the max, we choose one of the
+        // least-cost expansions, regardless of the target. the
+        // target only steers random choices, the cheapest fitting
+        // ones can be a cycle which never ends
+        candidates.clear();
+        candidates.extend(0..derivation.len());
+        let cost = |i: &usize| bounds[*i].map(|b| (b.min.depth,
+            b.min.length));
+        let best = candidates.iter().filter_map(cost).min();
+        candidates.retain(|i| cost(i) == best);
+    }
+
+    if let Some((kpaths, path)) = kpaths.filter(|_| random) {
+        let mut path = path.to_vec();
+        path.push(value);
+        *candidates = kpaths.prefer(&path, candidates);
+    }
+
+    // if we haven't reached the limits, we can expand
+    // at random among all fitting alternatives
+    let ridx = match g.probabilities[value].as_ref().filter(|_| random) {
+        Some(probabilities) => choose_weighted(rand, candidates,
+            probabilities),
+        None => choose(rand, candidates),
+    }.unwrap();
+    coverage.record(value, ridx, !random);
+
+    // one of the children which has a tree of the min. depth
+    // has to reach it, one which can grow has to make up
+    // for the missing length. without a min. depth or length,
+    // there is nothing to reach and no child is chosen.
+    let alternative = &derivation[ridx];
+    let deep = if target.depth.min > 1 {
+        choose_where(rand, alternative.len(),
+            |i| g.has_depth(alternative[i], target.depth.min - 1,
+                target.depth.max.saturating_sub(1)))
+    } else {
+        None
+    };
+    let long = if target.length.min > 0 {
+        choose_where(rand, alternative.len(), |i| {
+            let size = size(g, alternative[i]);
+            size.max_length != Some(size.min.length)
+        })
+    } else {
+        None
+    };
+
+    // the length the children add at least and at most
+    let mut min_rest = 0;
+    let mut max_rest = 0;
+    let mut unbounded = 0;
+    for &s in alternative {
+        let size = size(g, s);
+        min_rest += size.min.length;
+        match size.max_length {
+            Some(max) => max_rest += max,
+            None      => unbounded += 1,
+        }
+    }
+
+    // derive the child node 
+    tree.expand(node, alternative);
+
+    Frame {
+        node,
+        target,
+        value,
+        alt:    ridx,
+        deep,
+        long,
+        next:   0,
+        length: 0,
+        min_rest,
+        max_rest,
+        unbounded,
+    }
+}
 
 /// derive a random sentence from a grammar resulting in a 
-/// derivation tree, function is recursive
-fn derive(rand: &mut XorShift64, tree: &mut TreeNode, 
-    g: &mut Grammar) {
-
-    // if the value we are currently at is a non-terminal,  
-    // we have to expand it
-    if !g.is_terminal(&tree.value) {
-
-        // keep track of non-terminals in derivation
-        g.no_non_terminals += 1;
-        
-        // unwrap will fail if there is a syntax error in
-        // the grammar, thus check this here and exit if there is
-        // a problem
-        let derivation = match g.productions.get(&tree.value) {
-            Some(value) => value,   
-            None        => {
-                print!("unidentified symbol {}, aborting\n", 
-                    tree.value);
-                std::process::exit(1);
-            }
-        };
+/// derivation tree, the root of `tree` is expanded. the tree is
+/// derived depth-first with an explicit stack, not by recursion.
+/// returns the length of the derived sentence without layout.
+/// alternatives are chosen at random among those which fit the
+/// `target` shape of the tree, see `Grammar::bounds`. one child is
+/// chosen to reach the min. depth and one to make up for the missing
+/// length, all children stay below the max. depth and length. if
+/// nothing fits, e.g. if the grammar has no tree of the requested
+/// shape, the cheapest alternative is chosen.
+/// after `max_non_terminals` or `MAX_DEPTH`, the cheapest
+/// alternatives are chosen regardless of the target, each of them
+/// strictly decreases the minimal depth of the remaining derivation.
+/// thus, a tree is never deeper than `MAX_DEPTH` plus the largest
+/// minimal depth of a non-terminal.
+/// the random choice follows the probabilities of the grammar, if
+/// it has some for the rule, see `%prob`.
+/// each choice is counted in `coverage`. with `kpaths`, the random
+/// choice is among the alternatives preferred by `KPaths::prefer`.
+fn derive(rand: &mut XorShift64, tree: &mut SyntaxTree, 
+    g: &mut Grammar, coverage: &mut GrammarCoverage,
+    kpaths: Option<&KPaths>, target: Target) -> usize {
 
-        let mut ridx = 0;
+    // terminals are leaves of the tree
+    let root = SyntaxTree::ROOT;
+    if g.is_terminal(tree.value(root)) {
+        return g.symbols.text(tree.value(root)).len();
+    }
 
-        // if we haven't reached the max. non-terminals, 
-        // we can expand at random
-        if g.no_non_terminals < g.max_non_terminals {
-            // generate a random number [0; num_productions]
-            ridx = rand.rand() as usize % derivation.len();
-        } else {
-            // if we have reached the max, we choose the least-cost
-            // expansion
-            let mut max_item = usize::MIN;
-            let mut min_item = usize::MAX;
-
-            // here we get the min and max length of possible expansions
-            for (i, item) in derivation.iter().enumerate() {
-                if item.len() < min_item {
-                    min_item = item.len();
-                    ridx = i
-                }
-                if item.len() > max_item {
-                    max_item = item.len()
-                }
-            }
+    // the non-terminals from the root to the one being derived
+    let mut path: Vec<Symbol> = Vec::new();
+    let window = |path: &[Symbol], k: &KPaths| path.len().saturating_sub(k.k());
+    let mut candidates = Vec::new();
+    let mut stack = vec![expand(rand, tree, root, g, coverage,
+        kpaths.map(|k| (k, &path[window(&path, k)..])), 0, target,
+        &mut candidates)];
+    if kpaths.is_some() {
+        path.push(tree.value(root));
+    }
 
-            // if all expansions have the same length, 
-            // choose a random one
-            if max_item == min_item {
-                ridx = rand.rand() as usize % derivation.len();
-            }
+    while let Some(frame) = stack.last_mut() {
 
+        // all children are derived, pass the length on to the parent
+        if frame.next == frame.alternative(g).len() {
+            let length = frame.length;
+            stack.pop();
+            path.pop();
+            match stack.last_mut() {
+                Some(parent) => parent.length += length,
+                None         => return length,
+            }
+            continue;
         }
+        let i = frame.next_child(g);
 
-        // derive the child node 
-        for item in &derivation[ridx] {
-            tree.insert_child(item);
+        // now, derive further
+        let child = tree.children(frame.node).unwrap().start + i;
+        if g.is_terminal(tree.value(child)) {
+            frame.length += g.symbols.text(tree.value(child)).len();
+            continue;
+        }
+        let child_target = frame.child_target(i);
+        let child_frame = expand(rand, tree, child, g, coverage,
+            kpaths.map(|k| (k, &path[window(&path, k)..])), stack.len(),
+            child_target, &mut candidates);
+        if kpaths.is_some() {
+            path.push(tree.value(child));
         }
-            
-        // now, derive further (unwrap safe, tree has children)
-        for child in tree.children.as_mut().unwrap() {
-            derive(rand, child, g);
+        stack.push(child_frame);
+    }
+
+    0
+}
+
+/// how the syntax trees of a corpus are generated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// alternatives are chosen at random, see `derive`
+    Random,
+    /// trees are sampled uniformly among all trees of a length
+    Uniform,
+    /// all trees in breadth-first order, see `Enumerator`
+    Enumerate,
+}
+
+impl Strategy {
+
+    /// parse a strategy from its name
+    pub fn from_name(name: &str) -> Option<Strategy> {
+        match name {
+            "random"  => Some(Strategy::Random),
+            "uniform" => Some(Strategy::Uniform),
+            "enumerate" => Some(Strategy::Enumerate),
+            _         => None,
         }
+    }
 
-    } // if it is a non-terminal
+}
+
+/// a corpus which generates its inputs on demand, see
+/// `Corpus::set_stream`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stream {
+    /// the max. number of inputs kept for recycling
+    pub cache:   usize,
+    /// the probability that `get_input` returns a kept
+    /// input instead of a new one
+    pub recycle: f64,
+}
 
+/// the hashes of the inputs kept by a streaming corpus
+#[derive(Debug, Clone, Default)]
+struct Cache {
+    /// the hash of each kept input
+    hashes: Vec<u64>,
+    /// the same hashes, to find duplicates
+    known:  HashSet<u64>,
+    /// the input replaced next once the cache is full
+    next:   usize,
+}
+
+/// the inputs of a loaded corpus, see `Corpus::load`
+#[derive(Debug, Clone, Default)]
+struct Saved {
+    /// the inputs with a syntax tree
+    trees:  Vec<(String, SyntaxTree)>,
+    /// the inputs without one and their depths
+    inputs: Vec<(String, usize)>,
 }
 
 /// the corpus for fuzzing
@@ -113,46 +514,696 @@ pub struct Corpus {
     /// number of trees in forest
     forest_size:    usize,
     /// the syntax trees 
-    forest:         Vec<TreeNode>,
+    forest:         Vec<SyntaxTree>,
     /// random number generator
     prng:           XorShift64,
+    /// the seed of `prng`, if it was set
+    prng_seed:      Option<u64>,
     /// the `Grammar` used to generate syntax trees from
     grammar:        Grammar,
     /// start symbol of the grammar
     start_symbol:   String,
+    /// the file the grammar is read from
+    grammar_file:   String,
+    /// terminals of `@DICT`, kept to re-apply them to a new grammar
+    dictionary:     Vec<String>,
+    /// layout overriding the grammar file, see `set_layout`
+    layout:         Option<LayoutKind>,
+    /// the probabilities of the grammar are inverted, see `set_uncommon`
+    uncommon:       bool,
+    /// the depth range of the syntax trees, see `set_depth`
+    depth:          Range,
+    /// the length range of the inputs, see `set_length`
+    length:         Range,
+    /// spread the lengths of the inputs evenly over `length`
+    spread:         bool,
+    /// how syntax trees are generated, see `set_strategy`
+    strategy:       Strategy,
+    /// derivation counts for `Strategy::Uniform`
+    counts:         Option<Counts>,
+    /// the seed of each corpus covers the grammar, see `set_coverage`
+    coverage:       Option<CoverageKind>,
+    /// the number of seed trees and the goals they cover
+    seed:           (usize, usize),
+    /// how often each alternative was chosen
+    expansions:     GrammarCoverage,
+    /// the k-paths covered so far, see `set_kpath`
+    kpaths:         Option<KPaths>,
+    /// number of trees to generate, less than `forest_size` if
+    /// there are fewer, `None` if unknown, see `capacity`
+    capacity:       Option<usize>,
+    /// the number of trees of the language within the bounds, if
+    /// the enumeration in `capacity` counted all of them
+    counted:        Option<usize>,
     /// the inputs for fuzzing, derived from syntax trees
     inputs:         Vec<String>,
+    /// generate with the compiled grammar, see `set_vm`
+    vm:             bool,
+    /// the compiled grammar, `None` until it is needed
+    program:        Option<Program>,
+    /// the depths of the inputs generated by `program`,
+    /// which have no syntax trees
+    depths:         Vec<usize>,
+    /// the seed inputs parsed from files, see `add_seed_dir`
+    seed_inputs:    Vec<Vec<u8>>,
+    /// the syntax trees of `seed_inputs`
+    seed_trees:     Vec<SyntaxTree>,
+    /// generate the inputs on demand, see `set_stream`
+    stream:         Option<Stream>,
+    /// the inputs kept by a streaming corpus
+    cache:          Cache,
+    /// the inputs of earlier corpora and runs, shared by
+    /// all clones, see `set_known`
+    known:          Option<Arc<Mutex<Known>>>,
+    /// the number of known inputs taken in a row, see `is_repeat`
+    exhausted:      usize,
+    /// the inputs generated and how many of them were
+    /// known, see `take_repeats`
+    repeats:        (usize, usize),
+    /// a loaded corpus, the first one `generate` returns
+    saved:          Option<Saved>,
 }
 
 impl Corpus {
     
-    /// create a corpus instance 
-    pub fn new(grammar_file: &String, start_symbol: &String,
-        max_expansion: usize, forest_size: usize) -> Corpus {
+    /// create a corpus instance, the error is returned if
+    /// the grammar file can not be parsed
+    pub fn new(grammar_file: &str, start_symbol: &str,
+        max_expansion: usize, forest_size: usize) -> Result<Corpus, String> {
 
         let prng = match XorShift64::new(generate_seed()) {
             Some(rng)   => rng,
             None        => panic!("invalid PRNG seed\n"),
         };
 
-        let g = match Grammar::new(grammar_file, max_expansion) {
-            Some(grammar) => grammar,
-            None            => panic!("could not init grammar\n"),
-        };
+        let g = Grammar::load(grammar_file, max_expansion)?;
 
         let forest = Vec::with_capacity(forest_size);
 
-        Corpus {
-            forest_size:    forest_size,
-            forest:         forest,
-            prng:           prng,
+        Ok(Corpus {
+            forest_size,
+            forest,
+            prng,
+            prng_seed:      None,
             grammar:        g,
-            start_symbol:   start_symbol.clone(),
+            start_symbol:   start_symbol.to_string(),
+            grammar_file:   grammar_file.to_string(),
+            dictionary:     Vec::new(),
+            layout:         None,
+            uncommon:       false,
+            depth:          Range::any(),
+            length:         Range::any(),
+            spread:         false,
+            strategy:       Strategy::Random,
+            counts:         None,
+            coverage:       None,
+            seed:           (0, 0),
+            expansions:     GrammarCoverage::new(),
+            kpaths:         None,
+            capacity:       None,
+            counted:        None,
             inputs:         Vec::new(),
-        }
+            vm:             false,
+            program:        None,
+            depths:         Vec::new(),
+            seed_inputs:    Vec::new(),
+            seed_trees:     Vec::new(),
+            stream:         None,
+            cache:          Cache::default(),
+            known:          None,
+            exhausted:      0,
+            repeats:        (0, 0),
+            saved:          None,
+        })
     
     } // pub fn new
 
+    /// seed the PRNG, the same seed, grammar and options
+    /// generate the same corpora
+    pub fn set_seed(&mut self, seed: u64) {
+        self.prng = match XorShift64::new(seed) {
+            Some(rng)   => rng,
+            None        => panic!("invalid PRNG seed\n"),
+        };
+        self.prng_seed = Some(seed);
+    }
+
+    /// the number of inputs of each corpus
+    pub fn set_size(&mut self, size: usize) {
+        self.forest_size = size;
+        self.capacity = None;
+    }
+
+    /// make the strings of `dict` available as `@DICT`
+    /// if the grammar makes use of it
+    pub fn set_dictionary(&mut self, dict: &Dictionary) {
+        self.dictionary = dict.terminals();
+        if self.grammar.references(DICT_SYMBOL) {
+            self.grammar.add_dictionary(&self.dictionary);
+            self.program = None;
+        }
+    }
+
+    /// override the layout set in the grammar file
+    pub fn set_layout(&mut self, kind: LayoutKind) {
+        self.layout = Some(kind);
+        self.grammar.layout.kind = kind;
+    }
+
+    /// generate uncommon inputs, the probabilities of the grammar
+    /// are inverted, see `Grammar::invert_probabilities`
+    pub fn set_uncommon(&mut self) {
+        if !self.uncommon {
+            self.uncommon = true;
+            self.grammar.invert_probabilities();
+            self.program = None;
+        }
+    }
+
+    /// generate syntax trees with a depth between `min` and `max`.
+    /// an error is returned if the start symbol has no such tree.
+    pub fn set_depth(&mut self, min: Option<usize>, max: Option<usize>)
+        -> Result<(), String> {
+
+        let range = Range {
+            min: min.unwrap_or(0),
+            max: max.unwrap_or(usize::MAX),
+        };
+
+        // the costs exist, the grammar was validated
+        let start = self.start();
+        let shallowest = self.grammar.costs[start].map_or(0, |c| c.depth);
+        let deepest = self.grammar.max_depths[start];
+
+        if range.min > range.max {
+            return Err(format!("min. depth {} is larger than max. depth {}",
+                range.min, range.max));
+        }
+        if shallowest > range.max {
+            return Err(format!("the shallowest tree of {} has depth {}",
+                self.start_symbol, shallowest));
+        }
+        if let Some(deepest) = deepest.filter(|&d| d < range.min) {
+            return Err(format!("the deepest tree of {} has depth {}",
+                self.start_symbol, deepest));
+        }
+        let limit = range.min + self.grammar.order.len();
+        self.grammar.set_depth_limit(limit);
+        if !self.grammar.has_depth(start, range.min, range.max) {
+            return Err(format!("{} has no tree with a depth between {} and {}",
+                self.start_symbol, range.min, range.max));
+        }
+
+        self.depth = range;
+        self.capacity = None;
+        Ok(())
+    }
+
+    /// generate inputs with a length between `min` and `max` bytes,
+    /// the layout is not included. with `spread`, the lengths are
+    /// spread evenly over the range, this requires `max`. an error is
+    /// returned if the start symbol has no input of such a length.
+    pub fn set_length(&mut self, min: Option<usize>, max: Option<usize>,
+        spread: bool) -> Result<(), String> {
+
+        let range = Range {
+            min: min.unwrap_or(0),
+            max: max.unwrap_or(usize::MAX),
+        };
+
+        // the costs exist, the grammar was validated
+        let start = self.start();
+        let shortest = self.grammar.costs[start].map_or(0, |c| c.length);
+        let longest = self.grammar.max_lengths[start];
+
+        if range.min > range.max {
+            return Err(format!("min. length {} is larger than max. length {}",
+                range.min, range.max));
+        }
+        if spread && max.is_none() {
+            return Err("spreading the lengths requires a max. length"
+                .to_string());
+        }
+        if shortest > range.max {
+            return Err(format!("the shortest input of {} has {} bytes",
+                self.start_symbol, shortest));
+        }
+        if let Some(longest) = longest.filter(|&l| l < range.min) {
+            return Err(format!("the longest input of {} has {} bytes",
+                self.start_symbol, longest));
+        }
+
+        self.length = range;
+        self.spread = spread;
+        self.capacity = None;
+        Ok(())
+    }
+
+    /// the length range of the `n`-th input. with `spread`, the range
+    /// is split into `forest_size` slices of (almost) equal width
+    fn length_of(&self, n: usize) -> Range {
+        if !self.spread {
+            return self.length;
+        }
+        let width = self.length.max - self.length.min + 1;
+        let slice = |n: usize| self.length.min + width * n / self.forest_size;
+        Range {
+            min: slice(n),
+            max: slice(n + 1).max(slice(n) + 1) - 1,
+        }
+    }
+
+    /// the lengths of the inputs of the corpus
+    pub fn lengths(&self) -> Distribution {
+        let mut lengths = Distribution::new();
+        for input in &self.inputs {
+            lengths.add(input.len());
+        }
+        lengths
+    }
+
+    /// the depths of the syntax trees of the corpus
+    pub fn depths(&self) -> Distribution {
+        let mut depths = Distribution::new();
+        for tree in &self.forest {
+            depths.add(tree.depth());
+        }
+        for &depth in &self.depths {
+            depths.add(depth);
+        }
+        depths
+    }
+
+    /// the file the grammar of the corpus was read from
+    pub fn grammar_file(&self) -> &str {
+        &self.grammar_file
+    }
+
+    /// re-read the grammar file and validate it. the dictionary and
+    /// layout of the corpus are applied to the new grammar. if the
+    /// grammar is invalid, all errors are returned.
+    pub fn load_grammar(&self) -> Result<Grammar, Vec<String>> {
+
+        let mut g = Grammar::load(&self.grammar_file,
+            self.grammar.max_non_terminals).map_err(|e| vec![e])?;
+        g.validate(&self.start_symbol)?;
+
+        if g.references(DICT_SYMBOL) {
+            g.add_dictionary(&self.dictionary);
+        }
+        if let Some(kind) = self.layout {
+            g.layout.kind = kind;
+        }
+        if self.uncommon {
+            g.invert_probabilities();
+        }
+
+        Ok(g)
+    }
+
+    /// replace the grammar, the next call to `generate` uses it.
+    /// the coverage is kept for the rules of the new grammar.
+    pub fn set_grammar(&mut self, grammar: Grammar) {
+        self.expansions = self.expansions.with_grammar(&self.grammar,
+            &grammar);
+        self.grammar = grammar;
+        self.grammar.set_depth_limit(self.depth.min + self.grammar.order.len());
+        self.capacity = None;
+        self.program = None;
+        // the trees of a loaded corpus are those of the old grammar
+        self.saved = None;
+        if self.counts.is_some() {
+            self.counts = Some(Counts::new(&self.grammar,
+                self.uniform_length()));
+        }
+        if let Some(kpaths) = &self.kpaths {
+            self.kpaths = Some(kpaths.with_grammar(&self.grammar));
+        }
+
+        // the seed inputs not in the new language are dropped
+        let parser = Parser::new(&self.grammar);
+        self.seed_trees.clear();
+        for input in std::mem::take(&mut self.seed_inputs) {
+            if let Ok(tree) = parser.parse(&self.start_symbol, &input) {
+                self.seed_inputs.push(input);
+                self.seed_trees.push(tree);
+            }
+        }
+    }
+
+    /// parse the files in the directory `dir`, each corpus starts
+    /// with their syntax trees. the files have to be in the `none`
+    /// layout. returns the number of files parsed and the files
+    /// which could not be read or parsed with the reason.
+    pub fn add_seed_dir(&mut self, dir: &str)
+        -> std::io::Result<(usize, Vec<(String, String)>)> {
+        let inputs = parser::parse_dir(&self.grammar, &self.start_symbol,
+            dir)?;
+        let parsed = inputs.parsed.len();
+        for (_, input, tree) in inputs.parsed {
+            self.seed_inputs.push(input);
+            self.seed_trees.push(tree);
+        }
+        Ok((parsed, inputs.rejected))
+    }
+
+    /// the max. length of uniformly sampled trees
+    fn uniform_length(&self) -> usize {
+        if self.length.max == usize::MAX {
+            uniform::DEFAULT_LENGTH
+        } else {
+            self.length.max
+        }
+    }
+
+    /// choose how syntax trees are generated. uniform sampling
+    /// needs a max. length of at most `uniform::MAX_LENGTH`, the
+    /// depth range is ignored by it. an error is returned if the
+    /// start symbol has no tree of a length in the range.
+    pub fn set_strategy(&mut self, strategy: Strategy)
+        -> Result<(), String> {
+
+        self.strategy = strategy;
+        self.counts = None;
+
+        if strategy == Strategy::Uniform {
+            let max = self.uniform_length();
+            if max > uniform::MAX_LENGTH {
+                return Err(format!("uniform sampling supports lengths up \
+                    to {} bytes", uniform::MAX_LENGTH));
+            }
+            let counts = Counts::new(&self.grammar, max);
+            if counts.lengths(self.start(), self.length.min, max)
+                .is_empty() {
+                return Err(format!("{} has no input between {} and {} bytes",
+                    self.start_symbol, self.length.min, max));
+            }
+            self.counts = Some(counts);
+        }
+
+        Ok(())
+    }
+
+    /// start each corpus with a seed which covers every alternative
+    /// (or pair of alternatives) of the grammar, the rest of the
+    /// corpus is generated by the strategy
+    pub fn set_coverage(&mut self, kind: Option<CoverageKind>) {
+        self.coverage = kind;
+    }
+
+    /// how often each alternative was chosen since the last call,
+    /// the counts are reset
+    pub fn take_coverage(&mut self) -> GrammarCoverage {
+        std::mem::take(&mut self.expansions)
+    }
+
+    /// prefer alternatives which cover new paths of `k` nested
+    /// non-terminals, see `KPaths`. `None` chooses at random.
+    /// an error is returned if `k` is too small.
+    pub fn set_kpath(&mut self, k: Option<usize>) -> Result<(), String> {
+        self.kpaths = match k {
+            Some(k) if k < kpath::MIN_K => {
+                return Err(format!("k-paths need k of at least {}",
+                    kpath::MIN_K));
+            },
+            Some(k) => Some(KPaths::new(&self.grammar, self.start(), k)),
+            None    => None,
+        };
+        Ok(())
+    }
+
+    /// generate with the grammar compiled for a virtual machine,
+    /// see `Program`, which is much faster than deriving syntax
+    /// trees. the corpus then has inputs, but no trees. options which
+    /// need syntax trees fall back to them, an error is returned if
+    /// such an option is set.
+    pub fn set_vm(&mut self, vm: bool) -> Result<(), String> {
+        self.vm = vm;
+        match self.needs_trees().filter(|_| vm) {
+            Some(option) => Err(format!("{} need syntax trees, the vm \
+                does not support them", option)),
+            None         => Ok(()),
+        }
+    }
+
+    /// the first option set which needs syntax trees, if any
+    fn needs_trees(&self) -> Option<&'static str> {
+        if self.strategy != Strategy::Random {
+            Some("strategies other than random")
+        } else if self.coverage.is_some() {
+            Some("coverage seeds")
+        } else if self.kpaths.is_some() {
+            Some("k-paths")
+        } else if self.grammar.layout.kind != LayoutKind::None {
+            Some("layouts")
+        } else if self.depth != Range::any() || self.length != Range::any() {
+            Some("depth and length ranges")
+        } else {
+            None
+        }
+    }
+
+    /// generate the inputs on demand in `get_input` instead of
+    /// up front in `generate`, the last `cache` inputs are kept and
+    /// recycled, see `Stream`. `None` generates them up front. an
+    /// error is returned if an option needs all inputs up front.
+    pub fn set_stream(&mut self, stream: Option<Stream>)
+        -> Result<(), String> {
+
+        if let Some(stream) = stream {
+            if stream.cache == 0 {
+                return Err("the cache needs room for an input".to_string());
+            }
+            if !(0.0..=1.0).contains(&stream.recycle) {
+                return Err(format!("the recycling probability {} is not \
+                    between 0 and 1", stream.recycle));
+            }
+            if self.strategy == Strategy::Enumerate {
+                return Err("enumerated inputs can not be streamed"
+                    .to_string());
+            }
+            if self.coverage.is_some() {
+                return Err("coverage seeds can not be streamed".to_string());
+            }
+        }
+
+        self.stream = stream;
+        self.inputs.clear();
+        self.depths.clear();
+        self.cache = Cache::default();
+        Ok(())
+    }
+
+    /// avoid the inputs in `known`, which were generated by
+    /// earlier corpora or runs, new inputs are put into it. the
+    /// store is shared by all clones of the corpus. known inputs
+    /// are only taken if no new ones are found. `None` only avoids
+    /// duplicates within a corpus.
+    pub fn set_known(&mut self, known: Option<Known>) {
+        self.known = known.map(|known| Arc::new(Mutex::new(known)));
+    }
+
+    /// the number of inputs in the store, if there is one
+    pub fn known(&self) -> Option<usize> {
+        // unwrap is safe, the lock is only poisoned if a worker
+        // panicked, then grampus is done anyway
+        self.known.as_ref().map(|known| known.lock().unwrap().len())
+    }
+
+    /// the number of inputs generated since the last call and how
+    /// many of them were known already, the counts are reset
+    pub fn take_repeats(&mut self) -> (usize, usize) {
+        std::mem::take(&mut self.repeats)
+    }
+
+    /// true if `input` is in the store
+    fn is_known(&self, input: &str) -> bool {
+        self.known.as_ref()
+            .is_some_and(|known| known.lock().unwrap().contains(input))
+    }
+
+    /// true if `input` is known and another one should be tried.
+    /// after `MAX_REPEATS` known ones in a row, counted in `repeats`,
+    /// a known input is taken. once `MAX_EXHAUSTED` were taken in a
+    /// row, the language seems to be used up, known inputs are taken
+    /// right away until a new one turns up.
+    fn is_repeat(&mut self, input: &str, repeats: &mut usize) -> bool {
+        if !self.is_known(input) {
+            self.exhausted = 0;
+            return false;
+        }
+        if self.exhausted >= MAX_EXHAUSTED {
+            return false;
+        }
+        *repeats += 1;
+        if *repeats < MAX_REPEATS {
+            return true;
+        }
+        self.exhausted += 1;
+        false
+    }
+
+    /// put a generated `input` into the store and count it
+    fn remember(&mut self, input: &str) {
+        let known = match &self.known {
+            Some(known) => known,
+            None        => return,
+        };
+        self.repeats.0 += 1;
+        match known.lock().unwrap().insert(input) {
+            Ok(true)  => (),
+            Ok(false) => self.repeats.1 += 1,
+            Err(e)    => println!("[!] could not write the known inputs: {}",
+                e),
+        }
+    }
+
+    /// the k-paths covered by all trees so far, if enabled
+    pub fn kpaths(&self) -> Option<&KPaths> {
+        self.kpaths.as_ref()
+    }
+
+    /// the number of trees in the seed of the last corpus
+    /// and the number of goals they cover
+    pub fn seed(&self) -> (usize, usize) {
+        self.seed
+    }
+
+    /// enumerate the trees within the max. depth and length
+    fn enumerator(&self) -> Enumerator<'_> {
+        let bound = |max: usize| if max == usize::MAX { None } else { Some(max) };
+        Enumerator::new(&self.grammar, self.start(),
+            bound(self.depth.max), bound(self.length.max))
+    }
+
+    /// true if `tree` is at least as deep and long as required
+    fn is_large_enough(&self, tree: &SyntaxTree) -> bool {
+        let mut s = String::new();
+        tree.build(&self.grammar.symbols, &mut s);
+        tree.depth() >= self.depth.min && s.len() >= self.length.min
+    }
+
+    /// the number of trees to generate. if the language is finite
+    /// or bounded by the max. depth or length, it may have fewer
+    /// trees than `forest_size`. they are counted by enumeration,
+    /// if that is done within `MAX_ENUMERATED` derivations. else, the
+    /// corpus is capped at the trees found once `MAX_RETRIES` trees
+    /// in a row were duplicates, see `generate`.
+    fn capacity(&mut self) -> usize {
+
+        if let Some(capacity) = self.capacity {
+            return capacity;
+        }
+
+        let finite = self.grammar.max_lengths[self.start()].is_some();
+        let bounded = finite || self.depth.max != usize::MAX
+            || self.length.max != usize::MAX;
+
+        let mut capacity = self.forest_size;
+        self.counted = None;
+        if bounded && !self.spread {
+            let mut trees = self.enumerator().with_budget(MAX_ENUMERATED);
+            let n = trees.by_ref()
+                .filter(|t| self.is_large_enough(t))
+                .take(self.forest_size)
+                .count();
+            if !trees.truncated() {
+                capacity = n;
+                // else, there may be more than `forest_size`
+                self.counted = Some(n).filter(|&n| n < self.forest_size);
+            }
+        }
+
+        self.capacity = Some(capacity);
+        capacity
+    }
+
+    /// the number of inputs in the corpus
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    /// true if the corpus has no inputs
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    /// the input of `tree`
+    fn render(&mut self, tree: &SyntaxTree) -> String {
+        let mut input = String::new();
+        tree.build_with(&self.grammar.symbols, &self.grammar.layout,
+            Some(&mut self.prng), &mut input);
+        input
+    }
+
+    /// add `tree` and its input to the corpus
+    fn push(&mut self, tree: SyntaxTree) {
+        let input = self.render(&tree);
+        self.push_input(tree, input);
+    }
+
+    /// add `tree` and its rendered `input` to the corpus
+    fn push_input(&mut self, tree: SyntaxTree, input: String) {
+        if let Some(kpaths) = self.kpaths.as_mut() {
+            kpaths.cover(&tree);
+        }
+        self.remember(&input);
+        self.forest.push(tree);
+        self.inputs.push(input);
+    }
+
+    /// a new syntax tree with the `target` shape and the length of
+    /// its input without layout. the tree can miss the shape if no
+    /// alternative fits, see `derive`, or if it is sampled uniformly,
+    /// which only follows the length.
+    fn new_tree(&mut self, target: Target) -> (SyntaxTree, usize) {
+
+        // choose a length, then one of its trees uniformly
+        let start = self.start();
+        if let Some(counts) = &self.counts {
+            let lengths = counts.lengths(start,
+                target.length.min, target.length.max);
+            if !lengths.is_empty() {
+                let n = lengths[self.prng.rand() as usize % lengths.len()];
+                if let Some(tree) = counts.sample(&mut self.prng,
+                    &self.grammar, start, n) {
+                    self.expansions.record_tree(&self.grammar, &tree);
+                    return (tree, n);
+                }
+            }
+        }
+
+        let mut tree = SyntaxTree::new(start);
+        let length = derive(&mut self.prng, &mut tree, &mut self.grammar,
+            &mut self.expansions, self.kpaths.as_ref(), target);
+        self.grammar.no_non_terminals = 0;
+        (tree, length)
+    }
+
+    /// true if a tree of `depth` with an input of `length` bytes,
+    /// without layout, is within the depth and length range
+    fn in_range(&self, depth: usize, length: usize) -> bool {
+        (self.depth.min..=self.depth.max).contains(&depth)
+            && (self.length.min..=self.length.max).contains(&length)
+    }
+
+    /// the grammar the corpus is generated from
+    pub fn grammar(&self) -> &Grammar {
+        &self.grammar
+    }
+
+    /// the start symbol in the grammar, it has to be defined
+    fn start(&self) -> Symbol {
+        match self.grammar.non_terminal(&self.start_symbol) {
+            Some(start) => start,
+            None        => {
+                println!("unidentified symbol {}, aborting",
+                    self.start_symbol);
+                std::process::exit(1);
+            }
+        }
+    }
+
     /// generate the corpus
     pub fn generate(&mut self) {
 
@@ -162,62 +1213,699 @@ impl Corpus {
         // clear inputs
         self.forest.clear();
         self.inputs.clear();
+        self.depths.clear();
+        self.cache = Cache::default();
+        self.exhausted = 0;
+
+        // a loaded corpus comes first, see `load`
+        if let Some(saved) = self.saved.take() {
+            return self.restore(saved);
+        }
+
+        // a streaming corpus only starts with the parsed
+        // seed inputs, see `get_input`
+        if let Some(stream) = self.stream {
+            for i in 0..self.seed_trees.len() {
+                let tree = self.seed_trees[i].clone();
+                self.expansions.record_tree(&self.grammar, &tree);
+                let input = self.render(&tree);
+                self.remember(&input);
+                self.keep(input, tree.depth(), stream.cache);
+            }
+            return;
+        }
+
+        // the parsed seed inputs come first
+        for i in 0..self.seed_trees.len() {
+            let tree = self.seed_trees[i].clone();
+            if self.len() < self.forest_size && hashes.insert(tree.hash()) {
+                self.expansions.record_tree(&self.grammar, &tree);
+                self.push(tree);
+            }
+        }
+
+        if self.vm && self.needs_trees().is_none() {
+            return self.run_program(&mut hashes);
+        }
+
+        // the seed covers the grammar, the remaining
+        // trees are generated by the strategy
+        self.seed = (0, 0);
+        if let Some(kind) = self.coverage {
+            let start = self.start();
+            let (trees, goals) = coverage::seed(&self.grammar,
+                &mut self.prng, start, kind);
+            self.seed = (trees.len(), goals);
+            for tree in trees {
+                self.expansions.record_tree(&self.grammar, &tree);
+                if self.len() < self.forest_size && hashes.insert(tree.hash()) {
+                    self.push(tree);
+                }
+            }
+        }
+
+        // the first trees in breadth-first order
+        if self.strategy == Strategy::Enumerate {
+            let trees: Vec<SyntaxTree> = self.enumerator()
+                .filter(|t| self.is_large_enough(t))
+                .filter(|t| hashes.insert(t.hash()))
+                .take(self.forest_size - self.inputs.len())
+                .collect();
+            for tree in trees {
+                self.expansions.record_tree(&self.grammar, &tree);
+                self.push(tree);
+            }
+            return;
+        }
 
         // generate a syntax tree for each tree in `forest`
         // and make sure there are no duplicates
-        for _ in 0..self.forest_size {
+        for n in self.len()..self.capacity() {
     
-            loop {
-                let mut tree = TreeNode::new(&self.start_symbol);
-                derive(&mut self.prng, &mut tree, &mut self.grammar);
-                self.grammar.no_non_terminals = 0;
+            let target = Target {
+                depth:  self.depth,
+                length: self.length_of(n),
+            };
+
+            // trees which hash the same may still be missing, stop
+            // if no new one is found
+            let mut retries = 0;
+            let mut repeats = 0;
+            while retries < MAX_RETRIES {
+                let (tree, length) = self.new_tree(target);
+                if !self.in_range(tree.depth(), length) {
+                    retries += 1;
+                    continue;
+                }
 
                 // don't generate duplicates 
                 let hash = tree.hash();
                 // check if the list of hashes already 
                 // contains the generated input
                 if !hashes.contains(&hash) {
-                    let mut input = String::new();
+                    // nor inputs of earlier corpora, if possible
+                    let input = self.render(&tree);
+                    if self.is_repeat(&input, &mut repeats) {
+                        continue;
+                    }
                     hashes.insert(hash);
-                    tree.build(&mut input);
-                    // NOTE: whitespace replacement can take place here,
-                    // but I don't like it...
-                    input = input.replace("\\n", "\n");
-                    self.forest.push(tree);
-                    self.inputs.push(input);
+                    self.push_input(tree, input);
                     break;
                 }
+                retries += 1;
             } // loop
 
+            // a spread slice may just be hard to hit, try the next one.
+            // else, no new tree turns up, the corpus is capped at the
+            // trees found so far
+            if retries == MAX_RETRIES && !self.spread {
+                self.capacity = Some(self.len());
+                break;
+            }
+
         } // for
 
     } // pub fn generate
 
-    /// get a random input for fuzzing
+    /// generate the corpus with the compiled grammar, inputs which
+    /// hash the same as one in `hashes` are duplicates
+    fn run_program(&mut self, hashes: &mut HashSet<u64>) {
+
+        let start = self.start();
+        let capacity = self.capacity();
+        // taken out while running, the corpus is needed as well
+        let mut program = self.program.take()
+            .unwrap_or_else(|| Program::compile(&self.grammar));
+        let mut fnv = FnvHash::new();
+        let mut input = String::new();
+
+        for _ in 0..capacity {
+            let mut retries = 0;
+            let mut repeats = 0;
+            while retries < MAX_RETRIES {
+                let depth = program.run(&mut self.prng, start,
+                    &mut self.expansions, &mut input);
+                let hash = fnv.hash(input.as_bytes());
+                if hashes.contains(&hash) {
+                    retries += 1;
+                    continue;
+                }
+                if self.is_repeat(&input, &mut repeats) {
+                    continue;
+                }
+                hashes.insert(hash);
+                self.remember(&input);
+                self.inputs.push(input.clone());
+                self.depths.push(depth);
+                break;
+            }
+            if retries == MAX_RETRIES {
+                break;
+            }
+        }
+
+        self.program = Some(program);
+    }
+
+    /// a new input of a streaming corpus and the depth of its tree
+    fn new_input(&mut self) -> (String, usize) {
+
+        let start = self.start();
+        let mut input = String::new();
+
+        if self.vm && self.needs_trees().is_none() {
+            let grammar = &self.grammar;
+            let program = self.program
+                .get_or_insert_with(|| Program::compile(grammar));
+            let depth = program.run(&mut self.prng, start,
+                &mut self.expansions, &mut input);
+            return (input, depth);
+        }
+
+        // a random slice of a spread length range, trees out of
+        // the ranges are only taken if no other turns up
+        let mut retries = 0;
+        let tree = loop {
+            let n = self.prng.rand() as usize % self.forest_size;
+            let (tree, length) = self.new_tree(Target {
+                depth:  self.depth,
+                length: self.length_of(n),
+            });
+            retries += 1;
+            if self.in_range(tree.depth(), length) || retries == MAX_RETRIES {
+                break tree;
+            }
+        };
+        tree.build_with(&self.grammar.symbols, &self.grammar.layout,
+            Some(&mut self.prng), &mut input);
+        if let Some(kpaths) = self.kpaths.as_mut() {
+            kpaths.cover(&tree);
+        }
+        (input, tree.depth())
+    }
+
+    /// a new input of a streaming corpus which is not known,
+    /// if one is found, see `is_repeat`
+    fn next_input(&mut self) -> (String, usize) {
+        let mut repeats = 0;
+        loop {
+            let (input, depth) = self.new_input();
+            if !self.is_repeat(&input, &mut repeats) {
+                self.remember(&input);
+                return (input, depth);
+            }
+        }
+    }
+
+    /// keep `input` of a streaming corpus, the oldest input is
+    /// replaced once `size` inputs are kept. false if an input
+    /// which hashes the same is kept already.
+    fn keep(&mut self, input: String, depth: usize, size: usize) -> bool {
+
+        let hash = FnvHash::new().hash(input.as_bytes());
+        if !self.cache.known.insert(hash) {
+            return false;
+        }
+
+        if self.inputs.len() < size {
+            self.inputs.push(input);
+            self.depths.push(depth);
+            self.cache.hashes.push(hash);
+            return true;
+        }
+
+        let i = self.cache.next;
+        self.cache.known.remove(&self.cache.hashes[i]);
+        self.inputs[i] = input;
+        self.depths[i] = depth;
+        self.cache.hashes[i] = hash;
+        self.cache.next = (i + 1) % size;
+        true
+    }
+
+    /// the corpus with its syntax trees and the parameters it was
+    /// generated with, see `Snapshot`
+    pub fn snapshot(&self) -> Snapshot {
+
+        let trees = self.forest.len();
+        let entries = self.inputs.iter().enumerate()
+            .map(|(i, input)| match self.forest.get(i) {
+                Some(tree) => Entry {
+                    input:     input.clone(),
+                    depth:     tree.depth(),
+                    decisions: decision::encode(&self.grammar, tree),
+                },
+                None => Entry {
+                    input:     input.clone(),
+                    depth:     self.depths[i - trees],
+                    decisions: None,
+                },
+            })
+            .collect();
+
+        let lower = |option: String| option.to_lowercase();
+        let params = vec![
+            ("grammar", self.grammar_file.clone()),
+            ("seed", self.prng_seed.map_or("-".to_string(),
+                |seed| format!("{:#x}", seed))),
+            ("size", self.forest_size.to_string()),
+            ("strategy", lower(format!("{:?}", self.strategy))),
+            ("depth", self.depth.to_param()),
+            ("length", self.length.to_param()),
+            ("spread", self.spread.to_string()),
+            ("coverage", self.coverage.map_or("-".to_string(),
+                |kind| lower(format!("{:?}", kind)))),
+            ("kpath", self.kpaths.as_ref().map_or("-".to_string(),
+                |kpaths| kpaths.k().to_string())),
+            ("layout", lower(format!("{:?}", self.grammar.layout.kind))),
+            ("uncommon", self.uncommon.to_string()),
+            ("vm", self.vm.to_string()),
+            ("stream", self.stream.map_or("-".to_string(),
+                |s| format!("{} {}", s.cache, s.recycle))),
+        ];
+
+        Snapshot {
+            fingerprint:  self.grammar.fingerprint(),
+            start_symbol: self.start_symbol.clone(),
+            params:       params.into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+            entries,
+        }
+    }
+
+    /// save the corpus with its syntax trees to the file at `path`
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        self.snapshot().write(path)
+    }
+
+    /// load a corpus saved to the file at `path`, the next call of
+    /// `generate` returns it instead of a new one. the trees are
+    /// derived from their decisions again if the grammar has the
+    /// same fingerprint and start symbol, otherwise the inputs are
+    /// parsed and those not in the language are dropped. returns the
+    /// saved corpus and the number of inputs dropped.
+    pub fn load(&mut self, path: &str)
+        -> std::io::Result<(Snapshot, usize)> {
+
+        let snapshot = Snapshot::read(path)?;
+        let start = self.start();
+        let same = snapshot.fingerprint == self.grammar.fingerprint()
+            && snapshot.start_symbol == self.start_symbol;
+        let parser = Parser::new(&self.grammar);
+
+        let mut saved = Saved::default();
+        let mut dropped = 0;
+        for entry in &snapshot.entries {
+            let tree = match (&entry.decisions, same) {
+                (Some(decisions), true) => Some(decision::derive(
+                    &self.grammar, start, &mut Decisions::new(decisions))),
+                _ => parser.parse(&self.start_symbol, entry.input.as_bytes())
+                    .ok(),
+            };
+            match tree {
+                Some(tree) => saved.trees.push((entry.input.clone(), tree)),
+                // not in the `none` layout, but in the language
+                None if same => saved.inputs.push((entry.input.clone(),
+                    entry.depth)),
+                None => dropped += 1,
+            }
+        }
+
+        self.saved = Some(saved);
+        Ok((snapshot, dropped))
+    }
+
+    /// keep the `i`-th of `n` parts of a loaded corpus, so `n` clones
+    /// of the corpus restore each of its inputs only once, see `load`
+    pub fn share_saved(&mut self, i: usize, n: usize) {
+        if let Some(saved) = self.saved.as_mut() {
+            let part = |len: usize| len * i / n..len * (i + 1) / n;
+            let trees = part(saved.trees.len());
+            saved.trees = saved.trees.drain(trees).collect();
+            let inputs = part(saved.inputs.len());
+            saved.inputs = saved.inputs.drain(inputs).collect();
+        }
+    }
+
+    /// the number of trees of the language within the bounds if
+    /// there are fewer than `forest_size` and the enumeration counted
+    /// all of them, see `capacity`. a corpus may have fewer trees for
+    /// other reasons, e.g. if it is loaded or avoids known inputs.
+    pub fn language_size(&mut self) -> Option<usize> {
+        self.capacity();
+        self.counted
+    }
+
+    /// true if the next corpus is a loaded one, see `load`
+    pub fn is_loaded(&self) -> bool {
+        self.saved.is_some()
+    }
+
+    /// the inputs of a loaded corpus with their syntax trees, see
+    /// `load`, until they become the corpus
+    pub fn saved_trees(&self) -> &[(String, SyntaxTree)] {
+        self.saved.as_ref().map_or(&[], |saved| &saved.trees)
+    }
+
+    /// make the `saved` inputs the corpus, the inputs with trees
+    /// come first. a streaming corpus keeps them for recycling.
+    fn restore(&mut self, saved: Saved) {
+
+        let cache = self.stream.map(|stream| stream.cache);
+
+        for (input, tree) in saved.trees {
+            self.expansions.record_tree(&self.grammar, &tree);
+            if let Some(kpaths) = self.kpaths.as_mut() {
+                kpaths.cover(&tree);
+            }
+            match cache {
+                Some(size) => {
+                    self.keep(input, tree.depth(), size);
+                },
+                None => {
+                    self.forest.push(tree);
+                    self.inputs.push(input);
+                },
+            }
+        }
+
+        for (input, depth) in saved.inputs {
+            match cache {
+                Some(size) => {
+                    self.keep(input, depth, size);
+                },
+                None => {
+                    self.inputs.push(input);
+                    self.depths.push(depth);
+                },
+            }
+        }
+    }
+
+    /// the input derived with the choices of `bytes`, see
+    /// `decision::derive`. a byte fuzzer which mutates the bytes
+    /// generates inputs of the grammar.
+    pub fn decode(&mut self, bytes: &[u8]) -> String {
+        let tree = decision::derive(&self.grammar, self.start(),
+            &mut Decisions::new(bytes));
+        let mut input = String::new();
+        tree.build_with(&self.grammar.symbols, &self.grammar.layout,
+            Some(&mut self.prng), &mut input);
+        input
+    }
+
+    /// the choices which derive the `n`-th syntax tree of the corpus
+    /// again, see `decision::encode`. `None` if the corpus has no
+    /// such tree, e.g. if it was generated by the vm.
+    pub fn encode(&self, n: usize) -> Option<Vec<u8>> {
+        decision::encode(&self.grammar, self.forest.get(n)?)
+    }
+
+    /// get a random input for fuzzing. a streaming corpus
+    /// returns a new one unless a kept one is recycled.
     pub fn get_input(&mut self) -> String {
+
+        if let Some(stream) = self.stream {
+            let r = self.prng.rand() as f64 / u64::MAX as f64;
+            if self.inputs.is_empty() || r >= stream.recycle {
+                let (input, depth) = self.next_input();
+                self.keep(input.clone(), depth, stream.cache);
+                return input;
+            }
+        }
+
         // mut self because prng must be mutable
         self.inputs[self.prng.rand() as usize % self.inputs.len()]
             .clone()
     }
 
-    /// write the corpus to a file
-    pub fn write_corpus(&self) -> std::io::Result<()> {
+    /// the inputs to write, without duplicates. a streaming corpus
+    /// generates `forest_size` of them, the kept ones first.
+    pub fn batch(&mut self) -> Vec<String> {
 
-        let mut n: usize = 0;
+        let stream = match self.stream {
+            Some(stream) => stream,
+            None         => return self.inputs.clone(),
+        };
 
-        for input in &self.inputs {
-            let filename = format!("corpus/{:#04}", n);
-            let file = File::create(filename)?;
-            let mut writer = BufWriter::new(file);
-            // NOTE: add a newline here if the output shall
-            // contain a newline as a last character
-            write!(&mut writer, "{}", input)?;
-            n += 1;
+        let mut hashes: HashSet<u64> = self.cache.hashes.iter().copied()
+            .collect();
+        let mut batch = self.inputs.clone();
+        while batch.len() < self.forest_size {
+            let mut retries = 0;
+            loop {
+                let (input, depth) = self.next_input();
+                let hash = FnvHash::new().hash(input.as_bytes());
+                if self.keep(input.clone(), depth, stream.cache)
+                    && hashes.insert(hash) {
+                    batch.push(input);
+                    break;
+                }
+                retries += 1;
+                if retries == MAX_RETRIES {
+                    return batch;
+                }
+            }
         }
 
-        Ok(())
+        batch
     }
 
 }
 
 
+// TESTING
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_grammar() {
+        let file = crate::util::test_path("invalid.txt");
+        std::fs::write(&file, "START ::= 'a'\n%unknown directive\n").unwrap();
+        let corpus = Corpus::new(file.to_str().unwrap(), "START", 1000, 10);
+        std::fs::remove_file(&file).unwrap();
+        match corpus {
+            Err(e) => assert!(e.contains("unknown directive")),
+            Ok(_)  => panic!("an invalid grammar was accepted"),
+        }
+    }
+
+    /// the depths of the trees of the corpus
+    fn depths(corpus: &Corpus) -> Vec<usize> {
+        corpus.forest.iter().map(|t| t.depth())
+            .chain(corpus.depths.iter().copied())
+            .collect()
+    }
+
+    #[test]
+    fn test_depth() {
+        let mut corpus = Corpus::new("grammars/json.txt", "JSON", 3000, 1000)
+            .unwrap();
+        corpus.set_seed(crate::util::stream_seed(1, 0));
+        corpus.set_depth(Some(12), Some(14)).unwrap();
+        corpus.set_size(200);
+        corpus.generate();
+        assert_eq!(depths(&corpus).len(), 200);
+        assert!(depths(&corpus).iter().all(|d| (12..=14).contains(d)));
+
+        // the depths of a symbol can have gaps, `NUMBER` only has trees
+        // of depth 1 and 5 or more
+        let file = crate::util::test_path("gaps.txt");
+        std::fs::write(&file, "NUMBER ::= '0' | TWO\nTWO ::= THREE\n\
+            THREE ::= DIGITS\nDIGITS ::= DIGIT DIGITS | DIGIT\n\
+            DIGIT ::= '1' | '2'\n").unwrap();
+        let mut corpus = Corpus::new(file.to_str().unwrap(), "NUMBER",
+            1000, 50).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        assert!(corpus.set_depth(Some(2), Some(3)).is_err());
+        corpus.set_depth(Some(2), Some(6)).unwrap();
+        corpus.generate();
+        assert!(!corpus.is_empty());
+        assert!(depths(&corpus).iter().all(|d| (5..=6).contains(d)));
+    }
+
+    #[test]
+    fn test_min_size_cycle() {
+        // the alternatives which fit the min. size are a cycle
+        // which emits nothing, the fallback has to leave it
+        let file = crate::util::test_path("cycle.txt");
+        std::fs::write(&file, "START ::= ALPHA | 'x' START\n\
+            ALPHA ::= START | 'y'\n").unwrap();
+        let mut corpus = Corpus::new(file.to_str().unwrap(), "START",
+            3000, 5).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        corpus.set_seed(crate::util::stream_seed(1, 0));
+        // too deep for `MAX_DEPTH`, the trees are dropped. the
+        // fallback ends them within the largest minimal depth
+        corpus.set_length(Some(1000), None, false).unwrap();
+        corpus.generate();
+        assert!(corpus.is_empty());
+        let deepest = corpus.grammar.costs.iter().flatten()
+            .map(|cost| cost.depth).max().unwrap();
+        for _ in 0..20 {
+            let (tree, _) = corpus.new_tree(Target {
+                depth:  corpus.depth,
+                length: corpus.length,
+            });
+            assert!(tree.depth() <= MAX_DEPTH + deepest);
+        }
+
+        corpus.set_length(Some(100), None, false).unwrap();
+        corpus.generate();
+        assert_eq!(corpus.len(), 5);
+        assert!(corpus.inputs.iter().all(|i| i.len() >= 100));
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut corpus = Corpus::new("grammars/json.txt", "JSON", 3000, 100)
+            .unwrap();
+        corpus.set_seed(1);
+        // few enough trees to count them all
+        corpus.set_depth(None, Some(5)).unwrap();
+        assert_eq!(corpus.capacity(), 22);
+        assert_eq!(corpus.language_size(), Some(22));
+        corpus.generate();
+        assert_eq!(corpus.len(), 22);
+
+        // too many to count within the budget
+        corpus.set_depth(None, None).unwrap();
+        corpus.set_length(Some(100), Some(120), false).unwrap();
+        assert_eq!(corpus.capacity(), 100);
+        assert_eq!(corpus.language_size(), None);
+    }
+
+    #[test]
+    fn test_length() {
+        let mut corpus = Corpus::new("grammars/json.txt", "JSON", 3000, 100)
+            .unwrap();
+        corpus.set_seed(1);
+        corpus.set_length(Some(100), Some(120), false).unwrap();
+        corpus.generate();
+        assert_eq!(corpus.len(), 100);
+        assert!(corpus.inputs.iter().all(|i| (100..=120).contains(&i.len())));
+
+        // spread over the range, each slice gets an input
+        corpus.set_length(Some(1000), Some(1099), true).unwrap();
+        corpus.generate();
+        let lengths: HashSet<usize> = corpus.inputs.iter()
+            .map(|i| i.len()).collect();
+        assert!(lengths.iter().all(|l| (1000..=1099).contains(l)));
+        assert!(lengths.len() > 50);
+
+        assert!(corpus.set_length(Some(0), Some(1), false).is_err());
+        assert!(corpus.set_length(None, None, true).is_err());
+    }
+
+    #[test]
+    fn test_stream() {
+        let mut corpus = Corpus::new("grammars/url.txt", "URL", 3000, 100)
+            .unwrap();
+        corpus.set_seed(1);
+        let stream = |recycle| Some(Stream { cache: 10, recycle });
+        assert!(corpus.set_stream(Some(Stream { cache: 0, recycle: 0.5 }))
+            .is_err());
+        assert!(corpus.set_stream(stream(1.5)).is_err());
+
+        // how many of `n` inputs were kept before they were returned,
+        // the cache never grows beyond its size
+        let recycled = |corpus: &mut Corpus, n: usize| -> usize {
+            (0..n).filter(|_| {
+                let kept = corpus.inputs.clone();
+                let input = corpus.get_input();
+                assert!(corpus.inputs.len() <= 10);
+                assert_eq!(corpus.inputs.len(), corpus.cache.known.len());
+                kept.contains(&input)
+            }).count()
+        };
+
+        // only new inputs, the oldest kept ones are replaced
+        corpus.set_stream(stream(0.0)).unwrap();
+        corpus.generate();
+        assert!(recycled(&mut corpus, 200) < 5);
+        assert_eq!(corpus.len(), 10);
+
+        // only kept inputs once there is one
+        corpus.set_stream(stream(1.0)).unwrap();
+        corpus.generate();
+        assert_eq!(recycled(&mut corpus, 200), 199);
+        assert_eq!(corpus.len(), 1);
+
+        // about as many kept as new ones
+        corpus.set_stream(stream(0.5)).unwrap();
+        corpus.generate();
+        let n = recycled(&mut corpus, 1000);
+        assert!((400..=600).contains(&n), "{} of 1000 recycled", n);
+        assert_eq!(corpus.len(), 10);
+
+        // a batch to write has `forest_size` distinct inputs
+        let batch = corpus.batch();
+        assert_eq!(batch.len(), 100);
+        assert_eq!(batch.iter().collect::<HashSet<_>>().len(), 100);
+    }
+
+    #[test]
+    fn test_share_saved() {
+        let mut corpus = Corpus::new("grammars/expr.txt", "start", 1000, 10)
+            .unwrap();
+        corpus.set_seed(1);
+        corpus.generate();
+        let path = crate::util::test_path("shared.grampus");
+        corpus.snapshot().write(&path).unwrap();
+
+        // each of the clones restores its part of the saved inputs
+        corpus.load(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let saved = corpus.inputs.clone();
+        let mut restored = Vec::new();
+        for i in 0..3 {
+            let mut clone = corpus.clone();
+            clone.share_saved(i, 3);
+            clone.generate();
+            restored.extend(clone.inputs);
+        }
+        assert_eq!(restored, saved);
+    }
+
+    #[test]
+    fn test_set_grammar() {
+        let file = crate::util::test_path("reload.txt");
+        let expr = std::fs::read_to_string("grammars/expr.txt").unwrap();
+        std::fs::write(&file, &expr).unwrap();
+        let mut corpus = Corpus::new(file.to_str().unwrap(), "start",
+            1000, 100).unwrap();
+        corpus.set_seed(1);
+        corpus.set_kpath(Some(3)).unwrap();
+        corpus.generate();
+
+        let old = corpus.grammar().clone();
+        let digits = |corpus: &Corpus, g: &Grammar| -> u64 {
+            let digit = g.non_terminal("digit").unwrap();
+            (0..10).map(|alt| corpus.expansions.expansions(digit, alt).chosen)
+                .sum()
+        };
+        let chosen = digits(&corpus, &old);
+        // the covered k-paths by the names of their non-terminals
+        let names = |corpus: &Corpus, g: &Grammar| -> HashSet<Vec<String>> {
+            corpus.kpaths().unwrap().covered().iter()
+                .map(|p| p.iter().map(|&s| g.name(s).to_string()).collect())
+                .collect()
+        };
+        let covered = names(&corpus, &old);
+        assert!(chosen > 0 && !covered.is_empty());
+
+        // a new rule in front of the others shifts their symbols
+        std::fs::write(&file, expr.replacen("start ::= expr\n",
+            "start ::= expr | sign expr\nsign ::= '+' | '-'\n", 1)).unwrap();
+        let g = corpus.load_grammar().unwrap();
+        std::fs::remove_file(&file).unwrap();
+        assert_ne!(g.non_terminal("digit"), old.non_terminal("digit"));
+
+        corpus.set_grammar(g.clone());
+        assert_eq!(digits(&corpus, &g), chosen);
+        assert_eq!(names(&corpus, &g), covered);
+    }
+}
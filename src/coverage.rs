@@ -0,0 +1,493 @@
+/*
+    grampus - a crappy grammar fuzzer
+    Copyright (C) 2022  0xca7
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+    Description:
+        systematic coverage of the alternatives of a grammar. a seed
+        is a small set of syntax trees in which every alternative of
+        every reachable rule is used at least once. with pairwise
+        coverage, every alternative of a parent is combined with
+        every alternative of each of its child non-terminals.
+
+        a tree is built by choosing an uncovered alternative whenever
+        there is one, else the alternative closest to an uncovered one.
+        each choice covers a goal, thus few trees are needed.
+
+        `GrammarCoverage` counts how often each alternative was chosen
+        during a campaign, at random or by the termination fallback.
+
+    Author: 0xca7
+*/
+
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::collections::{HashMap, HashSet};
+
+extern crate xshift;
+use xshift::XorShift64;
+
+use crate::grammar::Grammar;
+use crate::symbol::Symbol;
+use crate::syntax_tree::{SyntaxTree, NodeId};
+
+/// the file the grammar coverage is written to
+pub const COVERAGE_FILE: &str = "coverage.txt";
+
+/// how often an alternative was chosen
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Expansions {
+    /// chosen by the strategy, e.g. at random
+    pub chosen: u64,
+    /// chosen by the termination fallback, as it was the cheapest
+    pub fallback: u64,
+}
+
+/// how often each alternative of each rule was chosen,
+/// the rules are indexed by symbol
+#[derive(Debug, Clone, Default)]
+pub struct GrammarCoverage {
+    rules: Vec<Vec<Expansions>>,
+}
+
+/// the totals of a `GrammarCoverage`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    /// rules expanded at least once
+    pub rules: usize,
+    /// rules of the grammar
+    pub total_rules: usize,
+    /// alternatives chosen at least once
+    pub alternatives: usize,
+    /// alternatives of the grammar
+    pub total_alternatives: usize,
+    /// alternatives only chosen by the termination fallback
+    pub fallback_only: usize,
+}
+
+impl GrammarCoverage {
+
+    pub fn new() -> GrammarCoverage {
+        GrammarCoverage::default()
+    }
+
+    /// count the choice of alternative `alt` of `nterm`
+    pub fn record(&mut self, nterm: Symbol, alt: usize, fallback: bool) {
+        if self.rules.len() <= nterm {
+            self.rules.resize(nterm + 1, Vec::new());
+        }
+        let rule = &mut self.rules[nterm];
+        if rule.len() <= alt {
+            rule.resize(alt + 1, Expansions::default());
+        }
+        if fallback {
+            rule[alt].fallback += 1;
+        } else {
+            rule[alt].chosen += 1;
+        }
+    }
+
+    /// count all choices in `tree`, which was not built by `derive`.
+    /// the alternatives are found by their symbols.
+    pub fn record_tree(&mut self, g: &Grammar, tree: &SyntaxTree) {
+        for id in tree.preorder(SyntaxTree::ROOT) {
+            let children = match tree.child_values(id) {
+                Some(children) => children,
+                None           => continue,
+            };
+            let alt = g.productions[tree.value(id)].iter()
+                .position(|a| *a == children);
+            if let Some(alt) = alt {
+                self.record(tree.value(id), alt, false);
+            }
+        }
+    }
+
+    /// add all counts of `other`
+    pub fn merge(&mut self, other: &GrammarCoverage) {
+        for (nterm, expansions) in other.rules.iter().enumerate() {
+            self.add(nterm, expansions);
+        }
+    }
+
+    /// add the counts of the alternatives of `nterm`
+    fn add(&mut self, nterm: Symbol, expansions: &[Expansions]) {
+        if self.rules.len() <= nterm {
+            self.rules.resize(nterm + 1, Vec::new());
+        }
+        let rule = &mut self.rules[nterm];
+        if rule.len() < expansions.len() {
+            rule.resize(expansions.len(), Expansions::default());
+        }
+        for (alt, e) in expansions.iter().enumerate() {
+            rule[alt].chosen += e.chosen;
+            rule[alt].fallback += e.fallback;
+        }
+    }
+
+    /// the counts of the rules of grammar `from` for the rules of
+    /// `g`, e.g. a reloaded grammar. rules are matched by name, the
+    /// counts of rules which no longer exist are dropped.
+    pub fn with_grammar(&self, from: &Grammar, g: &Grammar)
+        -> GrammarCoverage {
+        let mut coverage = GrammarCoverage::new();
+        for (nterm, expansions) in self.rules.iter().enumerate() {
+            if let Some(n) = g.non_terminal(from.name(nterm)) {
+                coverage.add(n, expansions);
+            }
+        }
+        coverage
+    }
+
+    /// how often alternative `alt` of `nterm` was chosen
+    pub fn expansions(&self, nterm: Symbol, alt: usize) -> Expansions {
+        self.rules.get(nterm)
+            .and_then(|r| r.get(alt).copied())
+            .unwrap_or_default()
+    }
+
+    /// the totals with respect to the rules of `g`
+    pub fn summary(&self, g: &Grammar) -> Summary {
+
+        let mut summary = Summary {
+            rules: 0,
+            total_rules: g.order.len(),
+            alternatives: 0,
+            total_alternatives: 0,
+            fallback_only: 0,
+        };
+
+        for &nterm in &g.order {
+            let mut expanded = false;
+            for alt in 0..g.productions[nterm].len() {
+                let e = self.expansions(nterm, alt);
+                summary.total_alternatives += 1;
+                if e.chosen + e.fallback > 0 {
+                    summary.alternatives += 1;
+                    expanded = true;
+                }
+                if e.chosen == 0 && e.fallback > 0 {
+                    summary.fallback_only += 1;
+                }
+            }
+            if expanded {
+                summary.rules += 1;
+            }
+        }
+
+        summary
+    }
+
+    /// write a report for the rules of `g` to `path`
+    pub fn write(&self, g: &Grammar, path: &str) -> std::io::Result<()> {
+
+        let mut w = BufWriter::new(File::create(path)?);
+
+        writeln!(w, "[ Grammar Coverage ]")?;
+        writeln!(w, "{}", self.summary(g))?;
+
+        writeln!(w, "\nrules never expanded:")?;
+        for &nterm in &g.order {
+            if (0..g.productions[nterm].len())
+                .all(|alt| self.expansions(nterm, alt) == Expansions::default()) {
+                writeln!(w, "    {}", g.name(nterm))?;
+            }
+        }
+
+        writeln!(w, "\nalternatives only chosen by the termination fallback:")?;
+        for &nterm in &g.order {
+            for alt in 0..g.productions[nterm].len() {
+                let e = self.expansions(nterm, alt);
+                if e.chosen == 0 && e.fallback > 0 {
+                    writeln!(w, "    {}", g.alternative_to_string(nterm, alt))?;
+                }
+            }
+        }
+
+        writeln!(w, "\n{:>12} {:>12}  alternative", "chosen", "fallback")?;
+        for &nterm in &g.order {
+            for alt in 0..g.productions[nterm].len() {
+                let e = self.expansions(nterm, alt);
+                writeln!(w, "{:>12} {:>12}  {}", e.chosen, e.fallback,
+                    g.alternative_to_string(nterm, alt))?;
+            }
+        }
+
+        Ok(())
+    }
+
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rules {}/{}, alternatives {}/{}, {} only by fallback",
+            self.rules, self.total_rules, self.alternatives,
+            self.total_alternatives, self.fallback_only)
+    }
+}
+
+/// what is covered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoverageKind {
+    /// every alternative of every rule
+    Alternatives,
+    /// every alternative of every rule below every parent alternative
+    Pairwise,
+}
+
+impl CoverageKind {
+
+    /// parse a coverage kind from its name
+    pub fn from_name(name: &str) -> Option<CoverageKind> {
+        match name {
+            "alternatives" => Some(CoverageKind::Alternatives),
+            "pairwise"     => Some(CoverageKind::Pairwise),
+            _              => None,
+        }
+    }
+
+}
+
+/// where a non-terminal is expanded: the parent non-terminal and
+/// its alternative (only with pairwise coverage) and the non-terminal
+type Context = (Option<Symbol>, usize, Symbol);
+
+/// a goal, an alternative chosen in a context
+type Goal = (Context, usize);
+
+/// builds a seed, keeps track of the uncovered goals
+struct Seed<'a> {
+    /// the grammar the trees are derived from
+    grammar:    &'a Grammar,
+    /// what is covered
+    kind:       CoverageKind,
+    /// goals which are not covered yet
+    uncovered:  HashSet<Goal>,
+    /// number of expansions to the closest uncovered goal
+    distance:   HashMap<Context, usize>,
+    /// expansions in the current tree
+    expansions: usize,
+}
+
+impl<'a> Seed<'a> {
+
+    /// the context of `symbol` below alternative `alt` of `parent`
+    fn context(&self, parent: Symbol, alt: usize, symbol: Symbol) -> Context {
+        match self.kind {
+            CoverageKind::Pairwise => (Some(parent), alt, symbol),
+            CoverageKind::Alternatives => (None, 0, symbol),
+        }
+    }
+
+    /// the alternatives of `nterm` which derive a terminal string
+    fn productive(&self, nterm: Symbol) -> Vec<usize> {
+        (0..self.grammar.productions[nterm].len())
+            .filter(|&j| self.grammar.bounds[nterm][j].is_some())
+            .collect()
+    }
+
+    /// the contexts of the non-terminals in alternative `alt` of `nterm`
+    fn children(&self, nterm: Symbol, alt: usize) -> Vec<Context> {
+        self.grammar.productions[nterm][alt].iter()
+            .filter(|&&s| !self.grammar.is_terminal(s))
+            .map(|&s| self.context(nterm, alt, s))
+            .collect()
+    }
+
+    /// all goals reachable from the context `root`
+    fn goals(&self, root: Context) -> HashSet<Goal> {
+
+        let mut goals = HashSet::new();
+        let mut seen = HashSet::new();
+        let mut stack = vec![root];
+
+        while let Some(ctx) = stack.pop() {
+            if !seen.insert(ctx) {
+                continue;
+            }
+            for j in self.productive(ctx.2) {
+                stack.extend(self.children(ctx.2, j));
+                goals.insert((ctx, j));
+            }
+        }
+
+        goals
+    }
+
+    /// recompute the distances to the uncovered goals
+    fn update_distances(&mut self) {
+
+        let contexts: HashSet<Context> = self.uncovered.iter()
+            .map(|(ctx, _)| *ctx)
+            .chain(self.distance.keys().copied())
+            .collect();
+
+        self.distance = self.uncovered.iter()
+            .map(|(ctx, _)| (*ctx, 0))
+            .collect();
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for ctx in &contexts {
+                let best = self.productive(ctx.2).into_iter()
+                    .flat_map(|j| self.children(ctx.2, j))
+                    .filter_map(|c| self.distance.get(&c).map(|d| d + 1))
+                    .min();
+                if let Some(d) = best {
+                    if self.distance.get(ctx).is_none_or(|old| d < *old) {
+                        self.distance.insert(*ctx, d);
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+
+    /// choose an alternative for `ctx`
+    fn choose(&self, rand: &mut XorShift64, ctx: &Context) -> usize {
+
+        let nterm = ctx.2;
+        let productive = self.productive(nterm);
+        let mut pick = |c: &[usize]| c[rand.rand() as usize % c.len()];
+
+        // an uncovered alternative
+        let uncovered: Vec<usize> = productive.iter().copied()
+            .filter(|&j| self.uncovered.contains(&(*ctx, j)))
+            .collect();
+        if !uncovered.is_empty() {
+            return pick(&uncovered);
+        }
+
+        // the alternative closest to an uncovered goal, as long
+        // as the tree is not too large
+        let distance = |j: usize| self.children(nterm, j).iter()
+            .filter_map(|c| self.distance.get(c).copied())
+            .min();
+        if self.expansions < self.grammar.max_non_terminals {
+            let best = productive.iter().filter_map(|&j| distance(j)).min();
+            if best.is_some() {
+                let closest: Vec<usize> = productive.iter().copied()
+                    .filter(|&j| distance(j) == best)
+                    .collect();
+                return pick(&closest);
+            }
+        }
+
+        // else the cheapest, this terminates
+        let bounds = &self.grammar.bounds[nterm];
+        let cost = |j: usize| bounds[j].map(|b| (b.min.depth, b.min.length));
+        let best = productive.iter().filter_map(|&j| cost(j)).min();
+        let cheapest: Vec<usize> = productive.iter().copied()
+            .filter(|&j| cost(j) == best)
+            .collect();
+        pick(&cheapest)
+    }
+
+    /// expand the root of `tree` in the context `ctx`,
+    /// the tree is derived depth-first
+    fn derive(&mut self, rand: &mut XorShift64, tree: &mut SyntaxTree,
+        ctx: Context) {
+
+        let mut stack: Vec<(NodeId, Context)> = vec![(SyntaxTree::ROOT, ctx)];
+
+        while let Some((id, ctx)) = stack.pop() {
+            let nterm = tree.value(id);
+            if self.grammar.is_terminal(nterm) {
+                continue;
+            }
+            self.expansions += 1;
+
+            let alt = self.choose(rand, &ctx);
+            if self.uncovered.remove(&(ctx, alt)) {
+                self.update_distances();
+            }
+
+            let children = tree.expand(id, &self.grammar.productions[nterm][alt]);
+            for child in children.rev() {
+                let ctx = self.context(nterm, alt, tree.value(child));
+                stack.push((child, ctx));
+            }
+        }
+    }
+
+}
+
+/// a small set of trees of `start` which covers every alternative
+/// reachable from it, see `CoverageKind`. also returns the number
+/// of goals, i.e. alternatives or pairs of them.
+pub fn seed(g: &Grammar, rand: &mut XorShift64, start: Symbol,
+    kind: CoverageKind) -> (Vec<SyntaxTree>, usize) {
+
+    let mut seed = Seed {
+        grammar:    g,
+        kind,
+        uncovered:  HashSet::new(),
+        distance:   HashMap::new(),
+        expansions: 0,
+    };
+    let root = (None, 0, start);
+    seed.uncovered = seed.goals(root);
+    seed.update_distances();
+
+    let goals = seed.uncovered.len();
+    let mut trees = Vec::new();
+
+    // every tree covers at least one goal
+    while seed.distance.contains_key(&root) {
+        let mut tree = SyntaxTree::new(start);
+        seed.expansions = 0;
+        seed.derive(rand, &mut tree, root);
+        trees.push(tree);
+    }
+
+    (trees, goals)
+}
+
+
+// TESTING
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// all (non-terminal, alternative) pairs used in `tree`
+    fn used(g: &Grammar, tree: &SyntaxTree, out: &mut HashSet<(Symbol, usize)>) {
+        for id in tree.preorder(SyntaxTree::ROOT) {
+            if let Some(values) = tree.child_values(id) {
+                let alt = g.productions[tree.value(id)].iter()
+                    .position(|a| *a == values).unwrap();
+                out.insert((tree.value(id), alt));
+            }
+        }
+    }
+
+    #[test]
+    fn test_alternative_coverage() {
+        let g = Grammar::new("grammars/expr.txt", 1000).unwrap();
+        let mut rand = XorShift64::new(0x5eed).unwrap();
+        let (trees, goals) = seed(&g, &mut rand,
+            g.non_terminal("start").unwrap(), CoverageKind::Alternatives);
+        let mut covered = HashSet::new();
+        for tree in &trees {
+            used(&g, tree, &mut covered);
+        }
+        assert_eq!(goals, 1 + 3 + 3 + 5 + 2 + 10);
+        assert_eq!(covered.len(), goals);
+    }
+}
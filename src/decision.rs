@@ -0,0 +1,192 @@
+/*
+    grampus - a crappy grammar fuzzer
+    Copyright (C) 2022  0xca7
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+    Description:
+        derivations encoded as a sequence of decisions. the choices of
+        alternatives are taken from a byte slice instead of a PRNG, so
+        a byte fuzzer which mutates the slice generates inputs of the
+        grammar. a syntax tree is encoded into the bytes which derive
+        it again.
+
+    Notes:
+        the non-terminals are expanded in pre-order, each choice
+        among n productive alternatives takes the bytes needed for n
+        (one up to 256 alternatives), big endian, modulo n. rules with
+        a single productive alternative take no bytes. once the bytes
+        run out, the cheapest alternative is chosen, so every slice
+        derives a finite tree. the probabilities of the grammar are
+        not used.
+
+    Author: 0xca7
+*/
+
+use crate::grammar::Grammar;
+use crate::symbol::Symbol;
+use crate::syntax_tree::SyntaxTree;
+
+/// the number of bytes of a choice among `n` alternatives
+fn width(n: usize) -> usize {
+    let mut width = 1;
+    while width < std::mem::size_of::<usize>() && n > 1 << (8 * width) {
+        width += 1;
+    }
+    width
+}
+
+/// the productive alternatives of `nterm`
+fn productive(g: &Grammar, nterm: Symbol) -> Vec<usize> {
+    (0..g.productions[nterm].len())
+        .filter(|&i| g.bounds[nterm][i].is_some())
+        .collect()
+}
+
+/// the decisions of a derivation read from a byte slice
+#[derive(Debug, Clone)]
+pub struct Decisions<'a> {
+    /// the bytes of the decisions
+    bytes: &'a [u8],
+    /// the bytes consumed so far
+    pos:   usize,
+}
+
+impl<'a> Decisions<'a> {
+
+    pub fn new(bytes: &'a [u8]) -> Decisions<'a> {
+        Decisions { bytes, pos: 0 }
+    }
+
+    /// the number of bytes consumed
+    pub fn consumed(&self) -> usize {
+        self.pos
+    }
+
+    /// one of `n` choices, `None` if the bytes ran out
+    fn next(&mut self, n: usize) -> Option<usize> {
+        let width = width(n);
+        let bytes = self.bytes.get(self.pos..self.pos + width)?;
+        self.pos += width;
+        let value = bytes.iter().fold(0, |v, &b| v << 8 | b as usize);
+        Some(value % n)
+    }
+
+    /// choose an alternative of `nterm`, the cheapest one
+    /// if the bytes ran out
+    fn choose(&mut self, g: &Grammar, nterm: Symbol) -> usize {
+        let alternatives = productive(g, nterm);
+        if alternatives.len() == 1 {
+            return alternatives[0];
+        }
+        if let Some(i) = self.next(alternatives.len()) {
+            return alternatives[i];
+        }
+        // unwrap is safe, the rule is productive
+        let bounds = &g.bounds[nterm];
+        *alternatives.iter()
+            .min_by_key(|&&i| bounds[i].map(|b| (b.min.depth, b.min.length)))
+            .unwrap()
+    }
+
+}
+
+/// derive a syntax tree of `start` with the choices from `decisions`,
+/// the grammar has to be validated. the tree is derived with an
+/// explicit stack, the leftmost non-terminal is expanded first.
+pub fn derive(g: &Grammar, start: Symbol, decisions: &mut Decisions)
+    -> SyntaxTree {
+
+    let mut tree = SyntaxTree::new(start);
+    let mut stack = vec![SyntaxTree::ROOT];
+
+    while let Some(node) = stack.pop() {
+        let value = tree.value(node);
+        if g.is_terminal(value) {
+            continue;
+        }
+        let alt = decisions.choose(g, value);
+        let children = tree.expand(node, &g.productions[value][alt]);
+        stack.extend(children.rev());
+    }
+
+    tree
+}
+
+/// the decisions which derive `tree` again, `None` if it has an
+/// expansion which is not a productive alternative of the grammar
+pub fn encode(g: &Grammar, tree: &SyntaxTree) -> Option<Vec<u8>> {
+
+    let mut bytes = Vec::new();
+
+    for id in tree.preorder(SyntaxTree::ROOT) {
+        let children = match tree.child_values(id) {
+            Some(children) => children,
+            None           => continue,
+        };
+        let nterm = tree.value(id);
+        let alternatives = productive(g, nterm);
+        let i = alternatives.iter()
+            .position(|&a| g.productions[nterm][a] == children)?;
+        if alternatives.len() > 1 {
+            let width = width(alternatives.len());
+            bytes.extend_from_slice(&i.to_be_bytes()[
+                std::mem::size_of::<usize>() - width..]);
+        }
+    }
+
+    Some(bytes)
+}
+
+
+// TESTING
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decisions() {
+        let g = Grammar::new("grammars/expr.txt", 1000).unwrap();
+        let start = g.non_terminal("start").unwrap();
+        let sentence = |tree: &SyntaxTree| {
+            let mut s = String::new();
+            tree.build(&g.symbols, &mut s);
+            s
+        };
+
+        // without bytes, the cheapest tree is derived
+        let tree = derive(&g, start, &mut Decisions::new(&[]));
+        assert_eq!(sentence(&tree), "0");
+
+        // expr term '-' expr, term factor, factor '-' factor, ...
+        let bytes = [1, 2, 1, 4, 1, 7, 2, 2, 4, 1, 3];
+        let mut decisions = Decisions::new(&bytes);
+        let tree = derive(&g, start, &mut decisions);
+        assert_eq!(sentence(&tree), "-7-3");
+        assert_eq!(decisions.consumed(), bytes.len());
+        assert_eq!(encode(&g, &tree).unwrap(), bytes);
+
+        // the encoding of any tree derives it again
+        let bytes: Vec<u8> = (0..=255).rev().collect();
+        let tree = derive(&g, start, &mut Decisions::new(&bytes));
+        let encoded = encode(&g, &tree).unwrap();
+        let again = derive(&g, start, &mut Decisions::new(&encoded));
+        assert_eq!(tree.hash(), again.hash());
+        assert_eq!(width(256), 1);
+        assert_eq!(width(257), 2);
+    }
+}
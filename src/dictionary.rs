@@ -0,0 +1,372 @@
+/*
+    grampus - a crappy grammar fuzzer
+    Copyright (C) 2022  0xca7
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+    Description:
+        harvests tokens from the fuzz target. targets often compare
+        their input against hardcoded keywords the grammar does not
+        know about. this module reads the target ELF, collects the
+        printable strings in `.rodata` and the magic constants used
+        in `cmp` instructions in `.text`.
+
+        the strings are made available in grammars via the `@DICT`
+        non-terminal, all tokens are used by the mutators.
+
+    Notes:
+        the ELF parsing is done by hand to avoid another crate,
+        only the section headers are needed.
+
+    Author: 0xca7
+*/
+
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::collections::HashSet;
+
+/// the non-terminal which is replaced by the dictionary strings
+pub const DICT_SYMBOL: &str = "@DICT";
+
+/// minimum length of a printable string to be harvested
+const MIN_STRING_LEN: usize = 4;
+/// maximum length of a printable string to be harvested
+const MAX_STRING_LEN: usize = 64;
+/// maximum number of tokens kept per kind
+const MAX_TOKENS: usize = 512;
+
+/// ELF machine types which we can scan for `cmp` instructions
+const EM_386:    u16 = 3;
+const EM_X86_64: u16 = 62;
+
+/// prefixes of strings which are linker / compiler noise
+const NOISE_PREFIXES: [&str; 7] = [
+    "GLIBC_", "GCC:", "GNU", "_ITM_", "__", ".note", "ld-linux",
+];
+
+/// tokens harvested from a target binary
+#[derive(Debug, Clone, Default)]
+pub struct Dictionary {
+    /// printable strings found in `.rodata`
+    pub strings: Vec<String>,
+    /// magic constants from `cmp` instructions, little-endian bytes
+    pub constants: Vec<Vec<u8>>,
+}
+
+/// a section of an ELF file, only what we need
+struct Section {
+    name:   String,
+    offset: usize,
+    size:   usize,
+}
+
+/// reads integers from an ELF image with the right endianess
+struct ElfReader<'a> {
+    data:       &'a [u8],
+    little:     bool,
+}
+
+impl<'a> ElfReader<'a> {
+
+    fn bytes(&self, offset: usize, n: usize) -> std::io::Result<&'a [u8]> {
+        let end = offset.checked_add(n).ok_or_else(|| Error::new(
+            ErrorKind::InvalidData, "offset out of range"))?;
+        match self.data.get(offset..end) {
+            Some(b) => Ok(b),
+            None    => Err(Error::new(ErrorKind::InvalidData,
+                "truncated ELF file")),
+        }
+    }
+
+    fn uint(&self, offset: usize, n: usize) -> std::io::Result<u64> {
+        let b = self.bytes(offset, n)?;
+        let mut value: u64 = 0;
+        for i in 0..n {
+            let byte = if self.little { b[n - 1 - i] } else { b[i] };
+            value = (value << 8) | byte as u64;
+        }
+        Ok(value)
+    }
+
+}
+
+/// parse the section headers of an ELF image
+fn parse_sections(data: &[u8]) -> std::io::Result<(u16, Vec<Section>)> {
+
+    if data.len() < 0x34 || &data[0..4] != b"\x7fELF" {
+        return Err(Error::new(ErrorKind::InvalidData, "not an ELF file"));
+    }
+
+    let is_64 = data[4] == 2;
+    let reader = ElfReader { data, little: data[5] == 1 };
+
+    let machine = reader.uint(0x12, 2)? as u16;
+
+    // offsets of the section header fields differ between
+    // 32 and 64 bit files
+    let (shoff, shentsize, shnum, shstrndx) = if is_64 {
+        (reader.uint(0x28, 8)?, reader.uint(0x3a, 2)?,
+         reader.uint(0x3c, 2)?, reader.uint(0x3e, 2)?)
+    } else {
+        (reader.uint(0x20, 4)?, reader.uint(0x2e, 2)?,
+         reader.uint(0x30, 2)?, reader.uint(0x32, 2)?)
+    };
+
+    // the offset of a field of a section header
+    let field = |hdr: usize, offset: usize| hdr.checked_add(offset)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData,
+            "section header out of range"));
+
+    let mut raw = Vec::new();
+    for i in 0..shnum as usize {
+        let hdr = i.checked_mul(shentsize as usize)
+            .and_then(|o| o.checked_add(shoff as usize))
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData,
+                "section header out of range"))?;
+        let (name, offset, size) = if is_64 {
+            (reader.uint(hdr, 4)?, reader.uint(field(hdr, 0x18)?, 8)?,
+             reader.uint(field(hdr, 0x20)?, 8)?)
+        } else {
+            (reader.uint(hdr, 4)?, reader.uint(field(hdr, 0x10)?, 4)?,
+             reader.uint(field(hdr, 0x14)?, 4)?)
+        };
+        if (offset as usize).checked_add(size as usize).is_none() {
+            return Err(Error::new(ErrorKind::InvalidData,
+                "section out of range"));
+        }
+        raw.push((name as usize, offset as usize, size as usize));
+    }
+
+    // resolve the section names via the section header string table
+    let names = match raw.get(shstrndx as usize) {
+        Some(&(_, offset, size)) => reader.bytes(offset, size)?,
+        None => return Err(Error::new(ErrorKind::InvalidData,
+            "no section header string table")),
+    };
+
+    let sections = raw.iter().map(|&(name, offset, size)| {
+        let end = names[name.min(names.len())..].iter()
+            .position(|&b| b == 0)
+            .map_or(names.len(), |p| name + p);
+        Section {
+            name:   String::from_utf8_lossy(
+                &names[name.min(end)..end]).to_string(),
+            offset,
+            size,
+        }
+    }).collect();
+
+    Ok((machine, sections))
+}
+
+/// a byte which can be part of a harvested string
+fn is_printable(b: u8) -> bool {
+    (0x20..0x7f).contains(&b) || b == b'\t'
+}
+
+/// collect all runs of printable bytes with a length
+/// between `MIN_STRING_LEN` and `MAX_STRING_LEN`
+fn printable_strings(data: &[u8]) -> Vec<String> {
+
+    let mut strings = Vec::new();
+
+    for run in data.split(|&b| !is_printable(b)) {
+        if run.len() >= MIN_STRING_LEN && run.len() <= MAX_STRING_LEN {
+            strings.push(String::from_utf8_lossy(run).to_string());
+        }
+    }
+
+    strings
+}
+
+/// true if the string `s` is noise, i.e. a symbol name of the
+/// dynamic linker or some compiler generated string
+fn is_noise(s: &str, symbols: &HashSet<String>) -> bool {
+    s.trim().is_empty()
+        || symbols.contains(s)
+        || NOISE_PREFIXES.iter().any(|p| s.starts_with(p))
+}
+
+/// true if the 32-bit immediate looks like a magic constant
+/// rather than a loop bound or a bitmask
+fn is_magic(value: u32) -> bool {
+    value > 0xff
+        && value != u32::MAX
+        && value.to_le_bytes().iter().filter(|&&b| b != 0).count() >= 2
+}
+
+/// scan x86 machine code for `cmp` instructions with a 32-bit
+/// immediate and return the immediates.
+/// handled encodings: `3d imm32` (cmp eax) and `81 /7 imm32` with a
+/// register operand, both optionally preceded by a REX prefix.
+fn cmp_immediates(code: &[u8]) -> Vec<u32> {
+
+    let mut values = Vec::new();
+    let imm = |i: usize| -> Option<u32> {
+        code.get(i..i + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+
+    for i in 0..code.len() {
+        let value = match code[i] {
+            0x3d => imm(i + 1),
+            0x81 if code.get(i + 1).is_some_and(|m| m & 0xf8 == 0xf8)
+                => imm(i + 2),
+            _ => None,
+        };
+        if let Some(v) = value {
+            if is_magic(v) {
+                values.push(v);
+            }
+        }
+    }
+
+    values
+}
+
+impl Dictionary {
+
+    /// harvest a dictionary from the ELF file at `path`
+    pub fn from_elf(path: &str) -> std::io::Result<Dictionary> {
+
+        let data = fs::read(path)?;
+        let (machine, sections) = parse_sections(&data)?;
+
+        // a section past the end of the file is skipped like an empty one
+        let content = |s: &Section| -> &[u8] {
+            s.offset.checked_add(s.size)
+                .and_then(|end| data.get(s.offset..end))
+                .unwrap_or(&[])
+        };
+
+        // symbol names are what we want to filter, these are
+        // mostly libc functions imported by the target
+        let mut symbols = HashSet::new();
+        for section in sections.iter()
+            .filter(|s| s.name == ".dynstr" || s.name == ".strtab") {
+            for name in content(section).split(|&b| b == 0) {
+                symbols.insert(String::from_utf8_lossy(name).to_string());
+            }
+        }
+
+        let mut dict = Dictionary::default();
+        let mut seen = HashSet::new();
+
+        for section in sections.iter()
+            .filter(|s| s.name.starts_with(".rodata")) {
+            for s in printable_strings(content(section)) {
+                if dict.strings.len() < MAX_TOKENS
+                    && !is_noise(&s, &symbols) && seen.insert(s.clone()) {
+                    dict.strings.push(s);
+                }
+            }
+        }
+
+        // only x86 code can be scanned for compares
+        if machine == EM_386 || machine == EM_X86_64 {
+            let mut seen = HashSet::new();
+            for section in sections.iter().filter(|s| s.name == ".text") {
+                for v in cmp_immediates(content(section)) {
+                    if dict.constants.len() < MAX_TOKENS && seen.insert(v) {
+                        dict.constants.push(v.to_le_bytes().to_vec());
+                    }
+                }
+            }
+        }
+
+        Ok(dict)
+    }
+
+    /// true if nothing was harvested
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty() && self.constants.is_empty()
+    }
+
+    /// all tokens as raw bytes, for use in the mutators
+    pub fn tokens(&self) -> Vec<Vec<u8>> {
+        self.strings.iter()
+            .map(|s| s.as_bytes().to_vec())
+            .chain(self.constants.iter().cloned())
+            .collect()
+    }
+
+    /// the tokens usable as terminals of the `@DICT` non-terminal.
+    /// constants are included if all of their bytes are printable.
+    pub fn terminals(&self) -> Vec<String> {
+        let mut terminals = self.strings.clone();
+        for c in &self.constants {
+            if c.iter().all(|&b| is_printable(b)) {
+                terminals.push(String::from_utf8_lossy(c).to_string());
+            }
+        }
+        terminals
+    }
+
+}
+
+
+// TESTING
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_printable_strings() {
+        let data = b"\x00\x01json\x00ab\x00true\xffnull\x00";
+        assert_eq!(printable_strings(data), vec!["json", "true", "null"]);
+    }
+
+    #[test]
+    fn test_noise_filter() {
+        let mut symbols = HashSet::new();
+        symbols.insert("strerror".to_string());
+        assert!(is_noise("strerror", &symbols));
+        assert!(is_noise("GLIBC_2.2.5", &symbols));
+        assert!(is_noise("    ", &symbols));
+        assert!(!is_noise("null", &symbols));
+    }
+
+    #[test]
+    fn test_cmp_immediates() {
+        // cmp eax, 0x46464952 ('RIFF'); cmp ecx, 0x10; cmp edx, 'JSON'
+        let code = b"\x3d\x52\x49\x46\x46\x83\xf9\x10\x81\xfa\x4a\x53\x4f\x4e";
+        assert_eq!(cmp_immediates(code), vec![0x46464952, 0x4e4f534a]);
+    }
+
+    #[test]
+    fn test_not_elf() {
+        assert!(parse_sections(b"not an elf file, but long enough to \
+            pass the length check of the parser").is_err());
+    }
+
+    #[test]
+    fn test_overflowing_offsets() {
+        // a 64 bit ELF header with the section headers at the very
+        // end of the address space
+        let mut data = vec![0u8; 0x40];
+        data[0..4].copy_from_slice(b"\x7fELF");
+        data[4] = 2;
+        data[5] = 1;
+        data[0x3a] = 0x40;
+        data[0x3c] = 2;
+        for shoff in &[u64::MAX, u64::MAX - 8] {
+            data[0x28..0x30].copy_from_slice(&shoff.to_le_bytes());
+            let e = parse_sections(&data).err().unwrap();
+            assert_eq!(e.kind(), ErrorKind::InvalidData);
+        }
+    }
+}
@@ -0,0 +1,245 @@
+/*
+    grampus - a crappy grammar fuzzer
+    Copyright (C) 2022  0xca7
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+    Description:
+        exhaustive enumeration of the derivation trees of a grammar.
+        leftmost derivations are expanded breadth-first, a tree with
+        fewer expansions comes first. a partial derivation is pruned
+        as soon as its minimal depth or length exceeds the bound, so
+        with a bound, the enumeration is finite.
+
+    Notes:
+        a derivation is kept as the list of chosen alternatives, the
+        tree is only built when the derivation is complete. the queue
+        is limited to `MAX_QUEUE` derivations and the enumeration to
+        a budget of expanded derivations, see `truncated`.
+
+    Author: 0xca7
+*/
+
+use std::collections::VecDeque;
+
+use crate::grammar::Grammar;
+use crate::symbol::Symbol;
+use crate::syntax_tree::SyntaxTree;
+
+/// max. number of partial derivations in the queue
+const MAX_QUEUE: usize = 1 << 20;
+
+/// a partial leftmost derivation
+struct Derivation {
+    /// the chosen alternatives in pre-order
+    choices:    Vec<usize>,
+    /// the non-terminals left to expand and their depth,
+    /// the leftmost one is last
+    pending:    Vec<(Symbol, usize)>,
+    /// bytes of the terminals derived so far
+    length:     usize,
+    /// minimal bytes the pending non-terminals add
+    min_rest:   usize,
+}
+
+/// iterates over all derivation trees of a symbol, breadth-first
+pub struct Enumerator<'a> {
+    /// the grammar to derive from
+    grammar:    &'a Grammar,
+    /// the symbol the trees are derived from
+    symbol:     Symbol,
+    /// the partial derivations, shortest first
+    queue:      VecDeque<Derivation>,
+    /// trees are at most this deep
+    max_depth:  usize,
+    /// trees derive at most this many bytes
+    max_length: usize,
+    /// derivations which may still be expanded
+    budget:     usize,
+    /// true if derivations were dropped because the queue was full
+    /// or the budget was used up
+    truncated:  bool,
+}
+
+impl<'a> Enumerator<'a> {
+
+    /// enumerate the trees of `symbol` which are at most `max_depth`
+    /// deep and derive at most `max_length` bytes
+    pub fn new(g: &'a Grammar, symbol: Symbol, max_depth: Option<usize>,
+        max_length: Option<usize>) -> Enumerator<'a> {
+
+        let mut queue = VecDeque::new();
+        if let Some(cost) = g.costs[symbol] {
+            queue.push_back(Derivation {
+                choices:    Vec::new(),
+                pending:    vec![(symbol, 0)],
+                length:     0,
+                min_rest:   cost.length,
+            });
+        }
+
+        Enumerator {
+            grammar:    g,
+            symbol,
+            queue,
+            max_depth:  max_depth.unwrap_or(usize::MAX),
+            max_length: max_length.unwrap_or(usize::MAX),
+            budget:     usize::MAX,
+            truncated:  false,
+        }
+    }
+
+    /// stop after expanding `budget` partial derivations
+    pub fn with_budget(mut self, budget: usize) -> Enumerator<'a> {
+        self.budget = budget;
+        self
+    }
+
+    /// true if not all trees were enumerated, because there were
+    /// too many partial derivations at the same time or the
+    /// budget was used up
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// expand the leftmost non-terminal of `d` with all alternatives
+    /// which stay within the bounds
+    fn expand(&mut self, mut d: Derivation) {
+
+        let g = self.grammar;
+
+        // there is always a pending non-terminal, complete
+        // derivations are not expanded
+        let (nterm, depth) = d.pending.pop().unwrap();
+        // unwrap is safe, only symbols with a cost are pending
+        d.min_rest -= g.costs[nterm].unwrap().length;
+
+        for (j, alternative) in g.productions[nterm].iter().enumerate() {
+
+            let bounds = match g.bounds[nterm][j] {
+                Some(bounds) => bounds,
+                None         => continue,
+            };
+            if depth.saturating_add(bounds.min.depth) > self.max_depth
+                || d.length + d.min_rest + bounds.min.length > self.max_length {
+                continue;
+            }
+            if self.queue.len() >= MAX_QUEUE {
+                self.truncated = true;
+                return;
+            }
+
+            let mut next = Derivation {
+                choices:    d.choices.clone(),
+                pending:    d.pending.clone(),
+                length:     d.length,
+                min_rest:   d.min_rest,
+            };
+            next.choices.push(j);
+            for &symbol in alternative.iter().rev() {
+                // unwrap is safe, the alternative has bounds
+                let length = g.costs[symbol].unwrap().length;
+                if g.is_terminal(symbol) {
+                    next.length += length;
+                } else {
+                    next.min_rest += length;
+                    next.pending.push((symbol, depth + 1));
+                }
+            }
+            self.queue.push_back(next);
+        }
+    }
+
+    /// build the tree of `symbol` from the chosen alternatives,
+    /// they are in pre-order
+    fn build(&self, symbol: Symbol, choices: &[usize]) -> SyntaxTree {
+
+        let mut tree = SyntaxTree::new(symbol);
+        let mut choices = choices.iter();
+        let mut stack = vec![SyntaxTree::ROOT];
+
+        while let Some(id) = stack.pop() {
+            let nterm = tree.value(id);
+            if self.grammar.is_terminal(nterm) {
+                continue;
+            }
+            // the choices are complete, there is one per non-terminal
+            let j = *choices.next().unwrap();
+            let children = tree.expand(id, &self.grammar.productions[nterm][j]);
+            stack.extend(children.rev());
+        }
+
+        tree
+    }
+
+}
+
+impl<'a> Iterator for Enumerator<'a> {
+
+    type Item = SyntaxTree;
+
+    fn next(&mut self) -> Option<SyntaxTree> {
+
+        while let Some(d) = self.queue.pop_front() {
+            if d.pending.is_empty() {
+                return Some(self.build(self.symbol, &d.choices));
+            }
+            if self.budget == 0 {
+                self.truncated = true;
+                return None;
+            }
+            self.budget -= 1;
+            self.expand(d);
+        }
+
+        None
+    }
+
+}
+
+
+// TESTING
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enumerate_finite() {
+        let g = Grammar::new("grammars/url.txt", 100).unwrap();
+        let domain = g.non_terminal("DOMAIN").unwrap();
+        let mut strings: Vec<String> = Enumerator::new(&g, domain, None, None)
+            .map(|t| { let mut s = String::new(); t.build(&g.symbols, &mut s); s })
+            .collect();
+        strings.sort();
+        assert_eq!(strings, vec![".com/", ".de/", ".uk/"]);
+    }
+
+    #[test]
+    fn test_enumerate_bounded() {
+        let g = Grammar::new("grammars/expr.txt", 100).unwrap();
+        // ten digits and a hundred two-digit integers
+        let mut e = Enumerator::new(&g, g.non_terminal("integer").unwrap(),
+            None, Some(2));
+        assert_eq!(e.by_ref().count(), 110);
+        assert!(!e.truncated());
+
+        let mut e = Enumerator::new(&g, g.non_terminal("integer").unwrap(),
+            None, Some(2)).with_budget(10);
+        assert!(e.by_ref().count() < 110);
+        assert!(e.truncated());
+    }
+}
@@ -27,6 +27,7 @@
 use std::thread;
 use std::time::Instant;
 use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver};
 use std::process::{Command, Stdio};
 use std::os::unix::process::ExitStatusExt;
 
@@ -36,6 +37,8 @@ use fnv::FnvHash;
 use crate::util::*;
 use crate::stats::Stats;
 use crate::corpus::Corpus;
+use crate::grammar::Grammar;
+use crate::dictionary::Dictionary;
 use crate::mutation::{Mutator, MutatorType};
 use crate::scheduler::{Scheduler, FuzzingCycle};
 
@@ -51,14 +54,35 @@ const NUMBER_THREADS:       usize = 8;
 const MAX_ITERATIONS_CYCLE: usize = 10000;
 /// maximum number of mutations applied to one input
 const MAX_NUMBER_MUTATIONS: usize = 4;
+/// seconds between two writes of the coverage file
+const COVERAGE_INTERVAL:    u64 = 10;
 
 /// worker thread for a fuzzer. 
 /// the worker generates inputs from a `corpus`, mutates a
 /// corpus item and passes it to the PUT. The resulting return code
 /// from the PUT is then assessed and a crashfile is written if
 /// the PUT crashed given the current input.
+/// the `dictionary` holds tokens harvested from the target
+/// which are used by the mutators. reloaded grammars are received
+/// via `grammars`.
+/// the corpus and each mutator get their own stream of the worker's
+/// stream of the master `seed`, so a worker's inputs only depend on
+/// the seed and its `thread_id`.
+#[allow(clippy::print_with_newline, clippy::single_match, clippy::useless_format)]
 fn worker(thread_id: u32, corpus: Corpus, target: String,
-    stats: Arc<Mutex<Stats>>) {
+    dictionary: Arc<Vec<Vec<u8>>>, grammars: Receiver<Grammar>,
+    stats: Arc<Mutex<Stats>>, seed: u64) {
+
+    // stream 0 of the worker is the corpus, the mutators
+    // use the following ones
+    let worker_seed = stream_seed(seed, thread_id as u64 + 1);
+    let mut next_seed = {
+        let mut stream = 0;
+        move || {
+            stream += 1;
+            stream_seed(worker_seed, stream)
+        }
+    };
 
     // the scheduler dictates in which sub-cycle the fuzzer is,
     // which determines which mutations are applied to an input
@@ -67,6 +91,7 @@ fn worker(thread_id: u32, corpus: Corpus, target: String,
 
     // the corpus used for generating inputs from a grammar 
     let mut corpus = corpus;
+    corpus.set_seed(stream_seed(worker_seed, 0));
 
     // hash algorithm to give each crashfile a unique ID 
     let mut fnv = FnvHash::new();
@@ -78,14 +103,28 @@ fn worker(thread_id: u32, corpus: Corpus, target: String,
     
     // start with a deterministic mutator
     let mut mutator = Mutator::new(MutatorType::Deterministic, 
-        MAX_NUMBER_MUTATIONS);
+        MAX_NUMBER_MUTATIONS, &dictionary, next_seed());
     
     // outer loop.
     // here, inputs are generated via a `Corpus`
     loop {
 
+        // a reloaded grammar is picked up when regenerating,
+        // if there are several, only the latest is used
+        if let Some(grammar) = grammars.try_iter().last() {
+            corpus.set_grammar(grammar);
+        }
+
         // we generate a corpus 
         corpus.generate();
+        let mut _stats = stats.lock().unwrap();
+        _stats.add_depths(&corpus.depths());
+        _stats.add_coverage(&corpus.take_coverage());
+        _stats.add_repeats(corpus.take_repeats());
+        if let Some(kpaths) = corpus.kpaths() {
+            _stats.add_kpaths(kpaths);
+        }
+        drop(_stats);
 
         // start fuzzing with the corpus
         // label is mainly for documentation / readability 
@@ -107,16 +146,16 @@ fn worker(thread_id: u32, corpus: Corpus, target: String,
                     FuzzingCycle::CycleDeterministic    => {
                         mutator = Mutator::new(
                             MutatorType::Deterministic,
-                            MAX_NUMBER_MUTATIONS);
+                            MAX_NUMBER_MUTATIONS, &dictionary, next_seed());
                     },
                     FuzzingCycle::CycleNonDeterministic => {
                         mutator = Mutator::new(
                             MutatorType::NonDeterministic,
-                            MAX_NUMBER_MUTATIONS);
+                            MAX_NUMBER_MUTATIONS, &dictionary, next_seed());
                     },
                     FuzzingCycle::CycleBitWalk => {
                         mutator = Mutator::new(MutatorType::BitWalk,
-                            MAX_NUMBER_MUTATIONS);
+                            MAX_NUMBER_MUTATIONS, &dictionary, next_seed());
                     },
                 } // match 
             } // if a cycle change occurs
@@ -154,7 +193,7 @@ fn worker(thread_id: u32, corpus: Corpus, target: String,
                             SIGILL | SIGABRT | SIGBUS | SIGSEGV => {
 
                             match write_crashfile(&fuzz_input, 
-                                fnv.hash(&fuzz_input[..])) {
+                                fnv.hash(&fuzz_input[..]), seed, thread_id) {
                                 Ok(_)   => (),
                                 Err(e)  => print!("thread {} \
                                     couldn't write crashfile: {}\n
@@ -188,41 +227,93 @@ fn worker(thread_id: u32, corpus: Corpus, target: String,
 
 /// this is the main fuzzer routine, it starts 
 /// `NUMBER_THREADS` fuzzing threads, targeting
-/// the `target` application
-pub fn fuzz(corpus: Corpus, target: &String) {
+/// the `target` application.
+/// the grammar file is watched, when it changes and is still 
+/// valid, the new grammar is sent to all workers.
+/// the workers derive their PRNG streams from the master `seed`.
+/// the coverage of all workers is written to `coverage_file`.
+pub fn fuzz(corpus: Corpus, target: &str, dictionary: &Dictionary,
+    seed: u64, coverage_file: &str) {
 
     let mut seconds = 0;
+    let mut senders = Vec::new();
     let mut handles = Vec::new();
 
     // we're writing here, so mutex
-    let stats = Arc::new(Mutex::new(Stats::new()));
+    let mut stats = Stats::new();
+    stats.set_kpaths(corpus.kpaths().cloned());
+    stats.set_known(corpus.known());
+    let stats = Arc::new(Mutex::new(stats));
+
+    // the harvested tokens are shared by all mutators
+    let dictionary = Arc::new(dictionary.tokens());
         
     // each thread gets it's own copy of the corpus
     // so each thread can generate inputs for itself
     for i in 0..NUMBER_THREADS {
         let stats = Arc::clone(&stats);
-        let target = target.clone();
+        let dictionary = Arc::clone(&dictionary);
+        let target = target.to_string();
         // each thread receives their own `Corpus` to generate
         // inputs from
-        let corpus = corpus.clone();
+        let mut corpus = corpus.clone();
+        corpus.share_saved(i, NUMBER_THREADS);
+        let (sender, grammars) = channel();
+        senders.push(sender);
         let handle = thread::spawn(move || {
-            worker(i as u32, corpus, target, stats);
+            worker(i as u32, corpus, target, dictionary, grammars, stats,
+                seed);
         });
         handles.push(handle);
     }
 
     let now = Instant::now();
+    let mut modified = modification_time(corpus.grammar_file());
+    let mut grammar = corpus.grammar().clone();
 
     loop {
         std::thread::sleep(std::time::Duration::from_secs(1));
         seconds += 1;
+
+        // check if the grammar file was changed
+        let m = modification_time(corpus.grammar_file());
+        if m.is_some() && m != modified {
+            modified = m;
+            // parsing the grammar takes a while, the workers
+            // must not wait for the stats meanwhile
+            let reloaded = corpus.load_grammar();
+            let mut _stats = stats.lock().unwrap();
+            match reloaded {
+                Ok(reloaded) => {
+                    for sender in &senders {
+                        // a worker only stops when grampus is killed,
+                        // ignore errors
+                        let _ = sender.send(reloaded.clone());
+                    }
+                    _stats.reset_kpaths(&reloaded);
+                    _stats.reset_coverage(&grammar, &reloaded);
+                    grammar = reloaded;
+                    _stats.grammar_reloaded();
+                },
+                Err(errors) => _stats.grammar_rejected(errors),
+            }
+        }
+
         let mut _stats = stats.lock().unwrap();
-        _stats.show_stats(&seconds, &now.elapsed());
+        _stats.show_stats(&seconds, &now.elapsed(), &grammar);
+
+        // fuzzing ends when grampus is killed, so the
+        // coverage file is kept up to date
+        if seconds % COVERAGE_INTERVAL == 0 {
+            if let Err(e) = _stats.coverage().write(&grammar, coverage_file) {
+                println!("[!] could not write {}: {}", coverage_file, e);
+            }
+        }
     }
 
     // NOTE: resolve this
+    #[allow(unreachable_code)]
     for handle in handles {
         handle.join().unwrap();
     }
 }
-
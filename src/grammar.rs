@@ -26,18 +26,53 @@ use std::fmt;
 use std::collections::HashSet;
 use std::collections::HashMap;
 
+extern crate fnv;
+use fnv::FnvHash;
+
 use crate::grammar_parser::{parse_grammar};
+use crate::dictionary::DICT_SYMBOL;
+use crate::layout::Layout;
+use crate::symbol::{Symbol, Symbols};
+use crate::analysis::{Bounds, Cost, min_costs, max_depths, max_lengths,
+    depth_sets, alternative_cost};
 
 /// contains a grammar and options for generation
 #[derive(Debug,Clone)]
 pub struct Grammar {
 
-    /// the grammar represented via productions
-    pub productions: HashMap<String, Vec<Vec<String>>>,
-    /// the set of terminals in the grammar
-    terminals: HashSet<String>,
-    /// the set of non-terminals in the grammar
-    non_terminals: HashSet<String>,
+    /// the names and kinds of the symbols of the grammar
+    pub symbols: Symbols,
+    /// the alternatives of each non-terminal, indexed by symbol.
+    /// empty for terminals and non-terminals without a rule
+    pub productions: Vec<Vec<Vec<Symbol>>>,
+    /// the non-terminals in the order of the grammar file
+    pub order: Vec<Symbol>,
+    /// what is put between tokens when building a sentence
+    pub layout: Layout,
+    /// minimal derivation depth and length of each symbol,
+    /// `None` if the symbol derives no terminal string
+    pub costs: Vec<Option<Cost>>,
+    /// maximal derivation depth of each symbol, `None` if
+    /// unbounded or if the cost is `None`
+    pub max_depths: Vec<Option<usize>>,
+    /// maximal output length of each symbol, `None` if
+    /// unbounded or if the cost is `None`
+    pub max_lengths: Vec<Option<usize>>,
+    /// the bounds of each alternative of each non-terminal, `None`
+    /// if the alternative derives no terminal string
+    pub bounds: Vec<Vec<Option<Bounds>>>,
+    /// the alternatives of each non-terminal which derive a terminal
+    /// string and the largest min. depth and length among them, a
+    /// tree which may be that deep and long can use any of them
+    pub viable: Vec<(Vec<usize>, Cost)>,
+    /// the depths of the trees of each symbol up to `depth_limit`,
+    /// see `has_depth`
+    pub depths: Vec<Vec<bool>>,
+    /// the max. depth in `depths`
+    pub depth_limit: usize,
+    /// the probability of each alternative of a non-terminal, see
+    /// `%prob`. `None` if the alternatives are equally likely
+    pub probabilities: Vec<Option<Vec<f64>>>,
 
     /// number of non-terminals in tree
     pub no_non_terminals: usize,
@@ -49,50 +84,437 @@ impl Grammar {
 
     /// parses a grammar file, if successful, creates
     /// a new instance of a `Generator`
-    pub fn new(grammar_file: &String, 
+    pub fn new(grammar_file: &str, 
         max_non_terminals: usize) -> Option<Grammar> {
 
-        let mut productions: HashMap<String, Vec<Vec<String>>>
-            = HashMap::new();
+        match Grammar::load(grammar_file, max_non_terminals) {
+            Ok(grammar) => Some(grammar),
+            Err(e) => {
+                println!("{}", e);
+                None
+            },
+        }
+
+    }
+
+    /// parses a grammar file, like `new`, but returns the
+    /// error message instead of printing it
+    pub fn load(grammar_file: &str, 
+        max_non_terminals: usize) -> Result<Grammar, String> {
+
+        let mut rules: HashMap<String, Vec<Vec<String>>> = HashMap::new();
         let mut terminals = HashSet::new();
         let mut non_terminals = HashSet::new();
+        let mut order = Vec::new();
+        let mut layout = Layout::default();
+        let mut probabilities = HashMap::new();
 
-        match parse_grammar(grammar_file, &mut productions, 
-            &mut terminals, &mut non_terminals) {
-            Ok(_) => (),
-            Err(e) => {
-                print!("error parsing grammar: {}\n", e);
-                return None;
+        if let Err(e) = parse_grammar(grammar_file, &mut rules, 
+            &mut terminals, &mut non_terminals, &mut order, &mut layout,
+            &mut probabilities) {
+            return Err(format!("error parsing grammar: {}", e));
+        }
+
+        // the rules come first, in the order of the grammar file
+        let mut symbols = Symbols::new();
+        for nterm in &order {
+            symbols.intern_non_terminal(nterm);
+        }
+        let mut productions = Vec::new();
+        for nterm in &order {
+            let alternatives: Vec<Vec<Symbol>> = rules[nterm].iter()
+                .map(|a| a.iter().map(|s| if terminals.contains(s) {
+                    symbols.intern_terminal(s)
+                } else {
+                    symbols.intern_non_terminal(s)
+                }).collect())
+                .collect();
+            productions.push((symbols.intern_non_terminal(nterm), alternatives));
+        }
+        let mut probabilities: Vec<(String, Vec<f64>)> = probabilities
+            .into_iter().collect();
+        probabilities.sort_by(|a, b| a.0.cmp(&b.0));
+        let probabilities: Vec<(Symbol, Vec<f64>)> = probabilities.into_iter()
+            .map(|(nterm, p)| (symbols.intern_non_terminal(&nterm), p))
+            .collect();
+
+        let mut grammar = Grammar {
+            symbols,
+            productions:        Vec::new(),
+            order:              productions.iter().map(|(n, _)| *n).collect(),
+            layout,
+            costs:              Vec::new(),
+            max_depths:         Vec::new(),
+            max_lengths:        Vec::new(),
+            bounds:             Vec::new(),
+            viable:             Vec::new(),
+            depths:             Vec::new(),
+            depth_limit:        0,
+            probabilities:      Vec::new(),
+            no_non_terminals:   0,
+            max_non_terminals,
+        };
+        grammar.resize();
+        for (nterm, alternatives) in productions {
+            grammar.productions[nterm] = alternatives;
+        }
+        for (nterm, p) in probabilities {
+            grammar.probabilities[nterm] = Some(p);
+        }
+
+        // `@DICT` is filled from the fuzz target, until then
+        // it only derives the empty string
+        if grammar.references(DICT_SYMBOL) {
+            grammar.add_dictionary(&[]);
+        }
+        grammar.update_costs();
+
+        Ok(grammar)
+
+    }
+
+    /// make room for the symbols added to the table
+    fn resize(&mut self) {
+        self.productions.resize(self.symbols.len(), Vec::new());
+        self.probabilities.resize(self.symbols.len(), None);
+    }
+
+    /// check that the grammar can be used to derive sentences
+    /// from `start_symbol`, all problems found are returned
+    pub fn validate(&self, start_symbol: &str) -> Result<(), Vec<String>> {
+
+        let mut errors = Vec::new();
+
+        match self.non_terminal(start_symbol)
+            .filter(|&s| self.is_non_terminal(s)) {
+            // every reachable rule must be able to terminate, else
+            // the derivation cannot be bounded
+            Some(start) => for nterm in self.reachable(start) {
+                if self.costs[nterm].is_none() {
+                    errors.push(format!("rule {} derives no terminal \
+                        string, its derivation never terminates",
+                        self.name(nterm)));
+                }
             },
+            None => errors.push(format!("start symbol {} has no rule",
+                start_symbol)),
+        }
+
+        for &nterm in &self.order {
+            for &symbol in self.productions[nterm].iter().flatten() {
+                if !self.is_terminal(symbol) && !self.is_non_terminal(symbol) {
+                    errors.push(format!("undefined symbol {} in rule {}",
+                        self.name(symbol), self.name(nterm)));
+                }
+            }
+        }
+
+        for (nterm, probabilities) in self.probabilities.iter().enumerate() {
+            let probabilities = match probabilities {
+                Some(probabilities) => probabilities,
+                None                => continue,
+            };
+            let alternatives = self.productions[nterm].len();
+            if alternatives == 0 {
+                errors.push(format!("probabilities for undefined rule {}",
+                    self.name(nterm)));
+            } else if alternatives != probabilities.len() {
+                errors.push(format!("rule {} has {} alternatives, but {} \
+                    probabilities", self.name(nterm), alternatives,
+                    probabilities.len()));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// compute the costs, max. depths and lengths of all symbols
+    /// and the bounds of their alternatives, see `bounds`
+    pub fn update_costs(&mut self) {
+
+        self.costs = min_costs(self);
+        self.max_depths = max_depths(self, &self.costs);
+        self.max_lengths = max_lengths(self, &self.costs);
+        self.depths = depth_sets(self, &self.costs, self.depth_limit);
+
+        self.bounds = self.productions.iter().map(|alternatives| {
+            alternatives.iter().map(|a| {
+                let min = alternative_cost(self, &self.costs, a)?;
+                let mut max_depth = Some(1);
+                let mut max_length = Some(0);
+                for &symbol in a {
+                    max_depth = max_depth.zip(self.max_depths[symbol])
+                        .map(|(m, d)| m.max(d + 1));
+                    max_length = max_length.zip(self.max_lengths[symbol])
+                        .map(|(m, l)| m + l);
+                }
+                Some(Bounds { min, max_depth, max_length })
+            }).collect()
+        }).collect();
+
+        self.viable = self.bounds.iter().map(|bounds| {
+            let mut largest = Cost { depth: 0, length: 0 };
+            let alternatives = (0..bounds.len()).filter(|&i| {
+                bounds[i].inspect(|b| {
+                    largest.depth = largest.depth.max(b.min.depth);
+                    largest.length = largest.length.max(b.min.length);
+                }).is_some()
+            }).collect();
+            (alternatives, largest)
+        }).collect();
+    }
+
+    /// track the depths of the trees up to `limit`, see `has_depth`.
+    /// to check for depths of at least `min`, the limit has to be
+    /// `min` plus the number of non-terminals.
+    pub fn set_depth_limit(&mut self, limit: usize) {
+        if limit > self.depth_limit {
+            self.depth_limit = limit;
+            self.depths = depth_sets(self, &self.costs, limit);
+        }
+    }
+
+    /// true if `symbol` has a tree with a depth between `min` and `max`.
+    /// a symbol with a tree of depth `min` or more also has one of at
+    /// most `min` plus the number of non-terminals: cutting out a cycle
+    /// of the deepest path makes a tree shallower by at most that many.
+    /// depths above `depth_limit` are assumed to exist.
+    pub fn has_depth(&self, symbol: Symbol, min: usize, max: usize) -> bool {
+        let shallowest = match self.costs[symbol] {
+            Some(cost) => cost.depth,
+            None       => return false,
+        };
+        if shallowest > max || self.max_depths[symbol].is_some_and(|d| d < min) {
+            return false;
+        }
+        if shallowest >= min {
+            return true;
+        }
+        match self.depths[symbol].get(min..=max.min(self.depth_limit)) {
+            Some(depths) => depths.contains(&true),
+            None         => true,
+        }
+    }
+
+    /// true if `alternative` has a tree with a depth between `min`
+    /// and `max`: all symbols fit below `max` and one of them has
+    /// a tree of depth between `min - 1` and `max - 1`
+    pub fn alternative_has_depth(&self, alternative: &[Symbol], min: usize,
+        max: usize) -> bool {
+        max > 0
+            && alternative.iter()
+                .all(|&s| self.costs[s].is_some_and(|c| c.depth < max))
+            && (min <= 1 || alternative.iter()
+                .any(|&s| self.has_depth(s, min - 1, max - 1)))
+    }
+
+    /// the non-terminals reachable from `start` in 
+    /// the order of the grammar file
+    pub fn reachable(&self, start: Symbol) -> Vec<Symbol> {
+
+        let mut seen = vec![false; self.symbols.len()];
+        let mut stack = vec![start];
+
+        while let Some(nterm) = stack.pop() {
+            if seen[nterm] {
+                continue;
+            }
+            seen[nterm] = true;
+            for &symbol in self.productions[nterm].iter().flatten() {
+                if !self.is_terminal(symbol) {
+                    stack.push(symbol);
+                }
+            }
+        }
+
+        self.order.iter().copied().filter(|&n| seen[n]).collect()
+    }
+
+    /// alternative `alt` of `nterm` as written in a grammar file,
+    /// e.g. `S ::= 'a' S 'b'`
+    pub fn alternative_to_string(&self, nterm: Symbol, alt: usize) -> String {
+        let symbols: Vec<String> = self.productions[nterm][alt].iter()
+            .map(|&s| if self.is_terminal(s) {
+                format!("'{}'", self.name(s).replace(' ', "^"))
+            } else {
+                self.name(s).to_string()
+            })
+            .collect();
+        format!("{} ::= {}", self.name(nterm), symbols.join(" "))
+    }
+
+    /// true if any rule has probabilities, see `%prob`
+    pub fn has_probabilities(&self) -> bool {
+        self.probabilities.iter().any(|p| p.is_some())
+    }
+
+    /// swap common and uncommon alternatives: the probabilities
+    /// are replaced by their inverse, normalized. alternatives with
+    /// probability zero are the most uncommon ones, if there are
+    /// any, they share the whole probability of the rule.
+    pub fn invert_probabilities(&mut self) {
+        for probabilities in self.probabilities.iter_mut().flatten() {
+            let unseen = probabilities.iter().filter(|p| **p == 0.0).count();
+            let inverse: Vec<f64> = probabilities.iter()
+                .map(|&p| match (unseen, p == 0.0) {
+                    (0, _)     => 1.0 / p,
+                    (_, true)  => 1.0,
+                    (_, false) => 0.0,
+                })
+                .collect();
+            let total: f64 = inverse.iter().sum();
+            *probabilities = inverse.iter().map(|p| p / total).collect();
+        }
+    }
+
+    /// a hash of the rules, their probabilities and the layout,
+    /// grammars with the same rules in the same order have the same
+    /// fingerprint. terminals and non-terminals are told apart.
+    pub fn fingerprint(&self) -> u64 {
+
+        // each name is prefixed by its kind and length, so no two
+        // grammars are written the same
+        let mut bytes = Vec::new();
+        let mut add = |tag: u8, text: &str| {
+            bytes.push(tag);
+            bytes.extend_from_slice(&(text.len() as u64).to_le_bytes());
+            bytes.extend_from_slice(text.as_bytes());
         };
+        for &nterm in &self.order {
+            add(b'R', self.name(nterm));
+            for alternative in &self.productions[nterm] {
+                add(b'A', "");
+                for &s in alternative {
+                    match self.is_terminal(s) {
+                        true  => add(b'T', self.symbols.text(s)),
+                        false => add(b'N', self.name(s)),
+                    }
+                }
+            }
+            for p in self.probabilities[nterm].iter().flatten() {
+                add(b'P', &p.to_bits().to_string());
+            }
+        }
 
-        Some(Grammar {
-            productions:        productions.clone(),
-            terminals:          terminals.clone(),
-            non_terminals:      non_terminals.clone(),
-            no_non_terminals:   0,
-            max_non_terminals:  max_non_terminals,
-        })
+        // the sets are sorted, their order is random
+        let layout = &self.layout;
+        add(b'L', &format!("{:?}", layout.kind));
+        for (tag, set) in [(b'K', &layout.tokens), (b'I', &layout.indent),
+            (b'W', &layout.newline)] {
+            let mut names: Vec<&String> = set.iter().collect();
+            names.sort();
+            for name in names {
+                add(tag, name);
+            }
+        }
+        for (open, close) in &layout.comments {
+            add(b'C', open);
+            add(b'C', close);
+        }
 
+        FnvHash::new().hash(&bytes)
     }
 
-    /// check if a term is a terminal or not
-    pub fn is_terminal(&self, term: &String) -> bool {
-        self.terminals.contains(term)
+    /// the non-terminal `name`, if the grammar has it
+    pub fn non_terminal(&self, name: &str) -> Option<Symbol> {
+        self.symbols.non_terminal(name)
+    }
+
+    /// the name of `symbol` as written in the grammar file
+    pub fn name(&self, symbol: Symbol) -> &str {
+        self.symbols.name(symbol)
+    }
+
+    /// check if a symbol is a terminal or not
+    pub fn is_terminal(&self, symbol: Symbol) -> bool {
+        self.symbols.is_terminal(symbol)
+    }
+
+    /// check if a symbol is a non-terminal with a rule
+    pub fn is_non_terminal(&self, symbol: Symbol) -> bool {
+        !self.productions[symbol].is_empty()
+    }
+
+    /// check if the non-terminal `name` is used on the
+    /// RHS of any production
+    pub fn references(&self, name: &str) -> bool {
+        self.non_terminal(name).is_some_and(|symbol| self.productions.iter()
+            .flatten()
+            .any(|alt| alt.contains(&symbol)))
+    }
+
+    /// (re-)define the `@DICT` non-terminal, each of the `tokens`
+    /// becomes a terminal alternative. without tokens, `@DICT`
+    /// derives the empty string.
+    pub fn add_dictionary(&mut self, tokens: &[String]) {
+
+        let mut alternatives: Vec<Vec<Symbol>> = tokens.iter()
+            .map(|token| vec![self.symbols.intern_terminal(token)])
+            .collect();
+
+        if alternatives.is_empty() {
+            alternatives.push(vec![self.symbols.intern_terminal("")]);
+        }
+
+        let dict = self.symbols.intern_non_terminal(DICT_SYMBOL);
+        if !self.order.contains(&dict) {
+            self.order.push(dict);
+        }
+        self.resize();
+        self.productions[dict] = alternatives;
+        self.probabilities[dict] = None;
+        self.update_costs();
     }
 
 }
 
-/// NOTE:   this is a little problematic, does not 
-///         print in the right order (which is expected
-///         when you have a hashmap), mostly there for debugging.
+/// prints the productions in the order of the grammar file,
+/// mostly there for debugging.
 impl fmt::Display for Grammar {
 
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for (nterm, prod) in &self.productions {
-            write!(f, "{} -> {:?}\n", nterm, prod)?;
+        for &nterm in &self.order {
+            let alternatives: Vec<Vec<&str>> = self.productions[nterm].iter()
+                .map(|a| a.iter().map(|&s| self.name(s)).collect())
+                .collect();
+            writeln!(f, "{} -> {:?}", self.name(nterm), alternatives)?;
         }
-        write!(f, "\n")
+        writeln!(f)
     }
 
 }
+
+
+// TESTING
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint() {
+        let file = crate::util::test_path("fingerprint.txt");
+        let fingerprint = |text: &str| {
+            std::fs::write(&file, text).unwrap();
+            Grammar::new(file.to_str().unwrap(), 1000).unwrap().fingerprint()
+        };
+        let rules = "S ::= 'a' S | B\nB ::= 'b'\n";
+        let same = fingerprint(rules);
+        let probabilities = fingerprint(&format!("{}%prob S 0.9 0.1\n", rules));
+        let layout = fingerprint(&format!("{}%layout space\n", rules));
+        assert_eq!(fingerprint(rules), same);
+        // the terminal and the non-terminal `ITEM` are told apart
+        let first = fingerprint("S ::= ITEM | 'y'\nITEM ::= 'x'\n");
+        let second = fingerprint("S ::= 'ITEM' | 'y'\nITEM ::= 'x'\n");
+        std::fs::remove_file(&file).unwrap();
+        assert_ne!(first, second);
+
+        assert_ne!(same, probabilities);
+        assert_ne!(same, layout);
+        assert_ne!(probabilities, layout);
+    }
+}
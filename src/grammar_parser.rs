@@ -37,6 +37,9 @@ use std::io::BufReader;
 use std::io::prelude::*;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::io::{Error, ErrorKind};
+
+use crate::layout::{Layout, DIRECTIVE, COMMENT};
 
 // identify non-terminals
 // '(.*?)'
@@ -52,8 +55,13 @@ pub const PRODUCTION: &str = "::=";
 /// example: S = aSb | ab
 const DELIMITER:  &str = "|";
 
+/// directive for the probabilities of the alternatives of a rule
+/// example: %prob S 0.25 0.75
+pub const PROBABILITY: &str = "%prob";
+
 /// split a production by whitespaces and apply the 
 /// `split_non_terminals` function to it
+#[allow(clippy::ptr_arg)]
 pub fn split_production(text: &String) -> Vec<String> {
 
     // split by whitespace
@@ -73,6 +81,7 @@ pub fn split_production(text: &String) -> Vec<String> {
 
 /// badly programmed function to split a non-terminal
 /// and a terminal when they are written without a space
+#[allow(clippy::manual_pattern_char_comparison, clippy::ptr_arg, unused_parens)]
 pub fn split_non_terminals(text: &String) -> Vec<String> {
 
     let mut temp = String::new();
@@ -161,6 +170,7 @@ fn remove_quotes(s: &mut Vec<String>) {
 
 /// check if `s` contains terminals, if yes, insert them into
 /// the `terminals` set
+#[allow(clippy::borrow_deref_ref)]
 fn extract_terminals(s: &Vec<String>, terminals: &mut HashSet<String>) {
 
     // match everything between ''
@@ -177,11 +187,36 @@ fn extract_terminals(s: &Vec<String>, terminals: &mut HashSet<String>) {
 
 }
 
-/// read a grammar file and parse it to a hashmap data structure
-pub fn parse_grammar(file_name: &String, 
+/// parse a `%prob` directive, the rule and the probabilities
+/// of its alternatives
+fn parse_probabilities(line: &str) -> std::io::Result<(String, Vec<f64>)> {
+
+    let invalid = || Error::new(ErrorKind::InvalidData,
+        format!("invalid probabilities: {}", line));
+
+    let mut words = line.split_whitespace().skip(1)
+        .take_while(|w| !w.starts_with(COMMENT));
+    let rule = words.next().ok_or_else(invalid)?.to_string();
+    let probabilities = words
+        .map(|w| w.parse::<f64>().ok().filter(|p| *p >= 0.0))
+        .collect::<Option<Vec<f64>>>()
+        .ok_or_else(invalid)?;
+
+    Ok((rule, probabilities))
+}
+
+/// read a grammar file and parse it to a hashmap data structure,
+/// the LHS of the rules are stored in `grammar_order` in the order
+/// they appear in the file. directives (lines starting with `%`)
+/// set the `grammar_layout` and the `grammar_probabilities`.
+#[allow(clippy::comparison_to_empty)]
+pub fn parse_grammar(file_name: &str, 
     grammar_productions: &mut HashMap<String, Vec<Vec<String>>>, 
     grammar_terminals: &mut HashSet<String>, 
-    grammar_non_terminals: &mut HashSet<String>)
+    grammar_non_terminals: &mut HashSet<String>,
+    grammar_order: &mut Vec<String>,
+    grammar_layout: &mut Layout,
+    grammar_probabilities: &mut HashMap<String, Vec<f64>>)
     -> std::io::Result<()> {
 
     // file containing grammar
@@ -207,14 +242,31 @@ pub fn parse_grammar(file_name: &String,
             break;
         }
 
+        // directives are not rules
+        if line.split_whitespace().next() == Some(PROBABILITY) {
+            let (rule, probabilities) = parse_probabilities(line)?;
+            grammar_probabilities.insert(rule, probabilities);
+            continue;
+        }
+        if line.starts_with(DIRECTIVE) {
+            grammar_layout.parse_directive(line)?;
+            continue;
+        }
+
         // get the production rule
         let rule = line.split(PRODUCTION).collect::<Vec<&str>>();
+        if rule.len() != 2 {
+            return Err(Error::new(ErrorKind::InvalidData,
+                format!("not a production: {}", line)));
+        }
 
         // extract the non-terminals from the rule 
         // the LHS is always a non-terminal S -> aSb (S is non-terminal)
         let mut lhs = rule[0].to_string();
         remove_begin_end_whitespace(&mut lhs);
-        grammar_non_terminals.insert(lhs.clone());
+        if grammar_non_terminals.insert(lhs.clone()) {
+            grammar_order.push(lhs.clone());
+        }
 
         // this is also the key
         let key = lhs;
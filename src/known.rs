@@ -0,0 +1,156 @@
+/*
+    grampus - a crappy grammar fuzzer
+    Copyright (C) 2022  0xca7
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+    Description:
+        the inputs generated in earlier corpora and earlier runs. the
+        hashes of the inputs are kept in memory and appended to a
+        file, which is read again on the next run. the store is shared
+        by the corpora of all workers.
+
+    Notes:
+        the file is a sequence of 64 bit FNV hashes, little endian.
+        each hash is written as soon as it is inserted, grampus is
+        stopped by killing it, so there is nothing to flush. a hash
+        cut short by a kill is ignored when the file is read.
+
+    Author: 0xca7
+*/
+
+use std::fs::{self, File, OpenOptions};
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::path::Path;
+use std::convert::TryInto;
+
+extern crate fnv;
+use fnv::FnvHash;
+
+/// the inputs generated so far
+#[derive(Debug, Default)]
+pub struct Known {
+    /// the hashes of the inputs
+    hashes: HashSet<u64>,
+    /// the file new hashes are appended to, `None` if the
+    /// store is only kept in memory
+    file:   Option<File>,
+}
+
+impl Known {
+
+    /// a store which is only kept in memory
+    pub fn new() -> Known {
+        Known::default()
+    }
+
+    /// open the store in the file at `path`, it is created
+    /// if it does not exist
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Known> {
+
+        let hashes = match fs::read(&path) {
+            Ok(bytes) => bytes.chunks_exact(8)
+                // unwrap is safe, the chunks have 8 bytes
+                .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+                .collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashSet::new(),
+            Err(e) => return Err(e),
+        };
+
+        let file = OpenOptions::new().create(true).append(true)
+            .open(&path)?;
+        // a hash cut short would shift all hashes after it
+        let len = file.metadata()?.len();
+        if len % 8 != 0 {
+            file.set_len(len - len % 8)?;
+        }
+
+        Ok(Known { hashes, file: Some(file) })
+    }
+
+    /// the number of inputs in the store
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    /// true if no input is in the store
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    /// the hash of `input` in the store
+    pub fn hash(input: &str) -> u64 {
+        FnvHash::new().hash(input.as_bytes())
+    }
+
+    /// true if `input` is in the store
+    pub fn contains(&self, input: &str) -> bool {
+        self.hashes.contains(&Known::hash(input))
+    }
+
+    /// put `input` into the store, false if it was in it already.
+    /// if the file can not be written, the error is returned once
+    /// and the store is only kept in memory from then on.
+    pub fn insert(&mut self, input: &str) -> io::Result<bool> {
+        let hash = Known::hash(input);
+        if !self.hashes.insert(hash) {
+            return Ok(false);
+        }
+        if let Some(file) = self.file.as_mut() {
+            if let Err(e) = file.write_all(&hash.to_le_bytes()) {
+                self.file = None;
+                return Err(e);
+            }
+        }
+        Ok(true)
+    }
+
+}
+
+
+// TESTING
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known() {
+        let path = crate::util::test_path("known.bin");
+        let _ = fs::remove_file(&path);
+
+        let mut known = Known::open(&path).unwrap();
+        assert!(known.insert("1+2").unwrap());
+        assert!(known.insert("(3)").unwrap());
+        assert!(!known.insert("1+2").unwrap());
+        drop(known);
+
+        // a hash cut short by a kill is dropped
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[0xff; 3]).unwrap();
+        drop(file);
+
+        let mut known = Known::open(&path).unwrap();
+        assert_eq!(known.len(), 2);
+        assert!(known.contains("(3)"));
+        assert!(!known.contains("4"));
+        assert!(known.insert("4").unwrap());
+        assert_eq!(fs::metadata(&path).unwrap().len(), 24);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
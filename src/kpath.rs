@@ -0,0 +1,340 @@
+/*
+    grampus - a crappy grammar fuzzer
+    Copyright (C) 2022  0xca7
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+    Description:
+        k-path coverage (Havrikov and Zeller). a k-path is a sequence
+        of k nested non-terminals in a syntax tree, e.g. the 2-path
+        ARRAY ELEMENTS. the k-paths of the generated trees are tracked,
+        generation prefers alternatives which complete an uncovered
+        k-path, or which lead to one in as few expansions as possible.
+
+    Notes:
+        the number of k-paths grows exponentially with k, small values
+        (2 to 4) are practical.
+
+    Author: 0xca7
+*/
+
+use std::fmt;
+use std::collections::{HashMap, HashSet};
+
+use crate::grammar::Grammar;
+use crate::symbol::{Symbol, Symbols};
+use crate::syntax_tree::SyntaxTree;
+
+/// the smallest k, shorter paths are just non-terminals
+pub const MIN_K: usize = 2;
+
+/// the k-paths of a grammar and which of them are covered
+#[derive(Debug, Clone)]
+pub struct KPaths {
+    /// the length of the paths
+    k: usize,
+    /// the root of the trees
+    start: Symbol,
+    /// the symbols of the grammar, to match the
+    /// paths of another grammar by name
+    symbols: Symbols,
+    /// the non-terminals in the productive alternatives
+    /// of each non-terminal
+    children: Vec<Vec<Vec<Symbol>>>,
+    /// all k-paths which can occur in a tree of the start symbol
+    total: usize,
+    /// the k-paths not covered yet
+    uncovered: HashSet<Vec<Symbol>>,
+    /// the k-paths covered so far
+    covered: HashSet<Vec<Symbol>>,
+    /// expansions from a window of the last k - 1 non-terminals
+    /// to the closest uncovered k-path
+    distance: HashMap<Vec<Symbol>, usize>,
+}
+
+impl KPaths {
+
+    /// the k-paths of the trees of `start`, `k` must be
+    /// at least `MIN_K`
+    pub fn new(g: &Grammar, start: Symbol, k: usize) -> KPaths {
+
+        let children: Vec<Vec<Vec<Symbol>>> = g.productions.iter()
+            .zip(&g.bounds)
+            .map(|(alternatives, bounds)| alternatives.iter().zip(bounds)
+                .map(|(a, b)| if b.is_some() {
+                    a.iter().copied().filter(|&s| !g.is_terminal(s)).collect()
+                } else {
+                    Vec::new()
+                })
+                .collect())
+            .collect();
+
+        // extend all paths from the reachable non-terminals
+        let mut paths: Vec<Vec<Symbol>> = g.reachable(start).into_iter()
+            .filter(|&n| g.costs[n].is_some())
+            .map(|n| vec![n])
+            .collect();
+        for _ in 1..k {
+            let mut longer = HashSet::new();
+            for path in &paths {
+                for alternative in &children[*path.last().unwrap()] {
+                    for &child in alternative {
+                        let mut p = path.clone();
+                        p.push(child);
+                        longer.insert(p);
+                    }
+                }
+            }
+            paths = longer.into_iter().collect();
+        }
+
+        let mut kpaths = KPaths {
+            k,
+            start,
+            symbols: g.symbols.clone(),
+            children,
+            total: paths.len(),
+            uncovered: paths.into_iter().collect(),
+            covered: HashSet::new(),
+            distance: HashMap::new(),
+        };
+        kpaths.update_distances();
+        kpaths
+    }
+
+    /// the length of the paths
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    /// the number of k-paths which can occur
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// the k-paths covered so far
+    pub fn covered(&self) -> &HashSet<Vec<Symbol>> {
+        &self.covered
+    }
+
+    /// the k-paths of the trees of `g`, e.g. a reloaded grammar.
+    /// the covered paths which still exist stay covered, the
+    /// non-terminals are matched by name.
+    pub fn with_grammar(&self, g: &Grammar) -> KPaths {
+        let symbol = |s: Symbol| g.non_terminal(self.symbols.name(s));
+        // unwrap is safe, the grammar was validated for the start symbol
+        let mut kpaths = KPaths::new(g, symbol(self.start).unwrap(), self.k);
+        let covered: HashSet<Vec<Symbol>> = self.covered.iter()
+            .filter_map(|p| p.iter().map(|&s| symbol(s)).collect())
+            .collect();
+        kpaths.add_covered(&covered);
+        kpaths
+    }
+
+    /// add the k-paths covered by `other`, e.g. by another worker
+    pub fn merge(&mut self, other: &KPaths) {
+        self.add_covered(&other.covered);
+    }
+
+    fn add_covered(&mut self, covered: &HashSet<Vec<Symbol>>) {
+        let before = self.uncovered.len();
+        for p in covered {
+            if self.uncovered.remove(p) {
+                self.covered.insert(p.clone());
+            }
+        }
+        if self.uncovered.len() != before {
+            self.update_distances();
+        }
+    }
+
+    /// cover all k-paths in `tree`
+    pub fn cover(&mut self, tree: &SyntaxTree) {
+
+        // nothing left to cover, the tree need not be walked
+        let before = self.uncovered.len();
+        if before == 0 {
+            return;
+        }
+
+        // the non-terminals above a node, and the number of them
+        let mut path: Vec<Symbol> = Vec::new();
+        let mut stack = vec![(SyntaxTree::ROOT, 0)];
+
+        while let Some((id, depth)) = stack.pop() {
+            let children = match tree.children(id) {
+                Some(children) => children,
+                None           => continue,
+            };
+            path.truncate(depth);
+            path.push(tree.value(id));
+            // the path is only copied if it was not covered yet
+            if path.len() >= self.k {
+                let p = &path[path.len() - self.k..];
+                if self.uncovered.remove(p) {
+                    self.covered.insert(p.to_vec());
+                }
+            }
+            stack.extend(children.rev().map(|c| (c, depth + 1)));
+        }
+
+        if self.uncovered.len() != before {
+            self.update_distances();
+        }
+    }
+
+    /// recompute the distances to the uncovered k-paths
+    fn update_distances(&mut self) {
+
+        let windows: HashSet<Vec<Symbol>> = self.uncovered.iter()
+            .chain(self.covered.iter())
+            .flat_map(|p| vec![p[..self.k - 1].to_vec(), p[1..].to_vec()])
+            .collect();
+
+        self.distance = self.uncovered.iter()
+            .map(|p| (p[..self.k - 1].to_vec(), 0))
+            .collect();
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for w in &windows {
+                let best = self.children[*w.last().unwrap()].iter().flatten()
+                    .filter_map(|&c| self.distance.get(&self.next(w, c))
+                        .map(|d| d + 1))
+                    .min();
+                if let Some(d) = best {
+                    if self.distance.get(w).is_none_or(|old| d < *old) {
+                        self.distance.insert(w.clone(), d);
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+
+    /// the window after expanding `child` in `window`
+    fn next(&self, window: &[Symbol], child: Symbol) -> Vec<Symbol> {
+        let mut next = window.to_vec();
+        next.push(child);
+        if next.len() > self.k - 1 {
+            next.remove(0);
+        }
+        next
+    }
+
+    /// the distance of a window to the closest uncovered k-path,
+    /// windows near the root are shorter than k - 1
+    fn window_distance(&self, window: &[Symbol]) -> Option<usize> {
+        if window.len() == self.k - 1 {
+            return self.distance.get(window).copied();
+        }
+        self.children[*window.last()?].iter().flatten()
+            .filter_map(|&c| self.window_distance(&self.next(window, c)))
+            .map(|d| d + 1)
+            .min()
+    }
+
+    /// the alternatives of `candidates` of the last non-terminal of
+    /// `path` which complete the most uncovered k-paths, else those
+    /// closest to one. if no k-path is left, `candidates` is returned.
+    pub fn prefer(&self, path: &[Symbol], candidates: &[usize]) -> Vec<usize> {
+
+        if self.uncovered.is_empty() {
+            return candidates.to_vec();
+        }
+
+        let window = &path[path.len().saturating_sub(self.k - 1)..];
+        let alternatives = &self.children[*window.last().unwrap()];
+
+        // the number of uncovered k-paths completed by each
+        // alternative, a child which occurs twice counts once.
+        // the k-paths are looked up in one buffer, the window
+        // followed by the child.
+        let mut completes = vec![0; candidates.len()];
+        if window.len() == self.k - 1 {
+            let mut p = window.to_vec();
+            p.push(0);
+            for (n, &j) in candidates.iter().enumerate() {
+                let children = &alternatives[j];
+                for (i, &c) in children.iter().enumerate() {
+                    p[self.k - 1] = c;
+                    if !children[..i].contains(&c)
+                        && self.uncovered.contains(&p) {
+                        completes[n] += 1;
+                    }
+                }
+            }
+        }
+        let best = completes.iter().copied().max().unwrap_or(0);
+        if best > 0 {
+            return candidates.iter().zip(&completes)
+                .filter(|(_, &n)| n == best)
+                .map(|(&j, _)| j)
+                .collect();
+        }
+
+        let distance = |j: usize| alternatives[j].iter()
+            .filter_map(|&c| self.window_distance(&self.next(window, c)))
+            .min();
+        let best = candidates.iter().filter_map(|&j| distance(j)).min();
+        if best.is_none() {
+            return candidates.to_vec();
+        }
+        candidates.iter().copied()
+            .filter(|&j| distance(j) == best)
+            .collect()
+    }
+
+}
+
+impl fmt::Display for KPaths {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-paths {}/{}", self.k, self.covered.len(), self.total)
+    }
+}
+
+
+// TESTING
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kpaths() {
+        let g = Grammar::new("grammars/expr.txt", 1000).unwrap();
+        let symbol = |name: &str| g.non_terminal(name).unwrap();
+        let mut kpaths = KPaths::new(&g, symbol("start"), 2);
+        // start expr, expr term, expr expr, term factor, term term,
+        // factor factor, factor expr, factor integer, integer digit,
+        // integer integer
+        assert_eq!(kpaths.total(), 10);
+
+        let mut tree = SyntaxTree::new(symbol("integer"));
+        let digit = tree.expand(SyntaxTree::ROOT, &[symbol("digit")]).start;
+        tree.expand(digit, &[g.symbols.terminal("7").unwrap()]);
+        kpaths.cover(&tree);
+        assert_eq!(kpaths.covered().len(), 1);
+
+        // integer integer is the only uncovered path from integer
+        assert_eq!(kpaths.prefer(&[symbol("integer")], &[0, 1]), vec![0]);
+
+        let mut other = KPaths::new(&g, symbol("start"), 2);
+        other.merge(&kpaths);
+        assert_eq!(other.to_string(), "2-paths 1/10");
+    }
+}
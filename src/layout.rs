@@ -0,0 +1,322 @@
+/*
+    grampus - a crappy grammar fuzzer
+    Copyright (C) 2022  0xca7
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+    Description:
+        the layout policy of a grammar, it decides what is put
+        between two tokens when a syntax tree is built. this
+        separates the lexical rules (what is a token) from the
+        syntactic rules (how tokens are combined), so the same
+        tree can be rendered in many layouts.
+
+        the layout is set in the grammar file by directives:
+
+            %layout none | space | random | pretty
+            %token   NUMBER STRING   -> rendered as a single token
+            %indent  MEMBERS         -> content on new, indented lines
+            %newline ',' PAIR        -> line break after the symbol
+            %comment '/*' '*/'       -> comment used by random layout
+
+        a directive ends at the first word starting with `#`, the rest
+        of the line is a comment. a quoted `'#'` is an argument.
+
+    Author: 0xca7
+*/
+
+use std::collections::HashSet;
+use std::io::{Error, ErrorKind};
+
+extern crate xshift;
+use xshift::XorShift64;
+
+/// prefix of a directive line in a grammar file
+pub const DIRECTIVE: &str = "%";
+/// prefix of a comment after a directive
+pub const COMMENT: &str = "#";
+
+/// whitespace used by the random layout
+const WHITESPACE: [&str; 5] = [" ", "  ", "\t", "\n", "\r\n"];
+/// the indentation for one level of the pretty layout
+const INDENT: &str = "    ";
+
+/// what is put between two tokens
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayoutKind {
+    /// nothing, tokens are concatenated
+    None,
+    /// a single space
+    Space,
+    /// a random mix of whitespace and comments
+    Random,
+    /// spaces, line breaks and indentation, see `%indent`, `%newline`
+    Pretty,
+}
+
+/// the layout policy of a grammar
+#[derive(Debug, Clone)]
+pub struct Layout {
+    /// separator between tokens
+    pub kind: LayoutKind,
+    /// non-terminals which are rendered as one token
+    pub tokens: HashSet<String>,
+    /// non-terminals whose content is indented
+    pub indent: HashSet<String>,
+    /// symbols which are followed by a line break
+    pub newline: HashSet<String>,
+    /// comment delimiters (open, close) for the random layout
+    pub comments: Vec<(String, String)>,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout {
+            kind:       LayoutKind::None,
+            tokens:     HashSet::new(),
+            indent:     HashSet::new(),
+            newline:    HashSet::new(),
+            comments:   Vec::new(),
+        }
+    }
+}
+
+/// remove the quotes of a terminal, non-terminals are returned as is
+fn unquote(s: &str) -> String {
+    if s.len() >= 2 && s.starts_with('\'') && s.ends_with('\'') {
+        s[1..s.len() - 1].to_string()
+    } else {
+        s.to_string()
+    }
+}
+
+impl LayoutKind {
+
+    /// parse a layout kind from its name
+    pub fn from_name(name: &str) -> Option<LayoutKind> {
+        match name {
+            "none"   => Some(LayoutKind::None),
+            "space"  => Some(LayoutKind::Space),
+            "random" => Some(LayoutKind::Random),
+            "pretty" => Some(LayoutKind::Pretty),
+            _        => None,
+        }
+    }
+
+}
+
+impl Layout {
+
+    /// parse a directive `line` from a grammar file
+    pub fn parse_directive(&mut self, line: &str) -> std::io::Result<()> {
+
+        let invalid = |msg: String| Error::new(ErrorKind::InvalidData, msg);
+
+        let mut words = line.trim_start_matches(DIRECTIVE).split_whitespace()
+            .take_while(|w| !w.starts_with(COMMENT));
+        let directive = words.next().unwrap_or("");
+        let args: Vec<String> = words.map(unquote).collect();
+
+        match directive {
+            "layout" => {
+                self.kind = args.first()
+                    .and_then(|a| LayoutKind::from_name(a))
+                    .ok_or_else(|| invalid(format!("invalid layout: {}",
+                        line)))?;
+            },
+            "token"   => self.tokens.extend(args),
+            "indent"  => self.indent.extend(args),
+            "newline" => self.newline.extend(args),
+            "comment" => {
+                if args.len() != 2 {
+                    return Err(invalid(format!("comment needs an open and \
+                        a close delimiter: {}", line)));
+                }
+                self.comments.push((args[0].clone(), args[1].clone()));
+            },
+            _ => return Err(invalid(format!("unknown directive: {}", line))),
+        }
+
+        Ok(())
+    }
+
+}
+
+/// state while rendering a tree with a layout
+pub struct Renderer<'a> {
+    /// the layout policy
+    pub layout: &'a Layout,
+    /// randomness for the random layout
+    rand: Option<&'a mut XorShift64>,
+    /// true after the first token was written
+    started: bool,
+    /// the pretty layout breaks the line before the next token
+    line_break: bool,
+    /// current indentation level of the pretty layout
+    level: usize,
+    /// the open non-terminals and if they were indented
+    open: Vec<(String, bool)>,
+}
+
+impl<'a> Renderer<'a> {
+
+    /// a renderer for `layout`, `rand` is needed for `LayoutKind::Random`
+    pub fn new(layout: &'a Layout, rand: Option<&'a mut XorShift64>)
+        -> Renderer<'a> {
+        Renderer {
+            layout,
+            rand,
+            started:    false,
+            line_break: false,
+            level:      0,
+            open:       Vec::new(),
+        }
+    }
+
+    /// write a token, preceded by a separator
+    pub fn token(&mut self, token: &str, s: &mut String) {
+
+        // empty terminals are no tokens
+        if token.is_empty() {
+            return;
+        }
+
+        if self.started {
+            self.separator(s);
+        }
+        s.push_str(token);
+        self.started = true;
+
+        if self.layout.newline.contains(token) {
+            self.line_break = true;
+        }
+    }
+
+    /// called when the non-terminal `symbol` is entered.
+    /// a symbol directly nested in itself, like the recursion in
+    /// `MEMBERS ::= PAIR ',' MEMBERS`, is not indented again.
+    pub fn enter(&mut self, symbol: &str) {
+        let indent = self.layout.kind == LayoutKind::Pretty
+            && self.layout.indent.contains(symbol)
+            && self.open.last().is_none_or(|(s, _)| s != symbol);
+        if indent {
+            self.level += 1;
+            self.line_break = true;
+        }
+        self.open.push((symbol.to_string(), indent));
+    }
+
+    /// called when the non-terminal `symbol` is left
+    pub fn leave(&mut self, symbol: &str) {
+        if let Some((_, true)) = self.open.pop() {
+            self.level -= 1;
+            self.line_break = true;
+        }
+        if self.layout.newline.contains(symbol) {
+            self.line_break = true;
+        }
+    }
+
+    /// write the separator between two tokens
+    fn separator(&mut self, s: &mut String) {
+
+        match self.layout.kind {
+            LayoutKind::None   => (),
+            LayoutKind::Space  => s.push(' '),
+            LayoutKind::Pretty => {
+                if self.line_break {
+                    s.push('\n');
+                    for _ in 0..self.level {
+                        s.push_str(INDENT);
+                    }
+                } else {
+                    s.push(' ');
+                }
+            },
+            LayoutKind::Random => {
+                let rand = match self.rand.as_mut() {
+                    Some(rand) => rand,
+                    None       => {
+                        s.push(' ');
+                        return;
+                    }
+                };
+                // one to three pieces of whitespace or comments
+                for _ in 0..rand.rand() % 3 + 1 {
+                    let comments = &self.layout.comments;
+                    if !comments.is_empty() && rand.rand() % 4 == 0 {
+                        let (open, close) = &comments[
+                            rand.rand() as usize % comments.len()];
+                        s.push_str(open);
+                        for _ in 0..rand.rand() % 8 {
+                            s.push((b'a' + (rand.rand() % 26) as u8) as char);
+                        }
+                        s.push_str(close);
+                    } else {
+                        s.push_str(WHITESPACE[
+                            rand.rand() as usize % WHITESPACE.len()]);
+                    }
+                }
+            },
+        }
+
+        self.line_break = false;
+    }
+
+}
+
+
+// TESTING
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_directive() {
+        let mut layout = Layout::default();
+        layout.parse_directive("%layout pretty").unwrap();
+        layout.parse_directive("%token NUMBER STRING").unwrap();
+        layout.parse_directive("%newline ','").unwrap();
+        layout.parse_directive("%comment '/*' '*/'").unwrap();
+        assert_eq!(layout.kind, LayoutKind::Pretty);
+        assert!(layout.tokens.contains("STRING"));
+        assert!(layout.newline.contains(","));
+        assert_eq!(layout.comments, vec![("/*".to_string(), "*/".to_string())]);
+        assert!(layout.parse_directive("%layout fancy").is_err());
+        assert!(layout.parse_directive("%unknown").is_err());
+
+        // comments after a directive, a quoted '#' is an argument
+        let mut layout = Layout::default();
+        layout.parse_directive("%token QSTRING NUMBER   # one token").unwrap();
+        layout.parse_directive("%comment '#' ';' #shell").unwrap();
+        layout.parse_directive("%layout random # or pretty").unwrap();
+        assert_eq!(layout.tokens.len(), 2);
+        assert_eq!(layout.comments, vec![("#".to_string(), ";".to_string())]);
+        assert_eq!(layout.kind, LayoutKind::Random);
+    }
+
+    #[test]
+    fn test_space_separator() {
+        let layout = Layout { kind: LayoutKind::Space, ..Default::default() };
+        let mut r = Renderer::new(&layout, None);
+        let mut s = String::new();
+        r.token("a", &mut s);
+        r.token("", &mut s);
+        r.token("b", &mut s);
+        assert_eq!(s, "a b");
+    }
+}
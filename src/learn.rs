@@ -0,0 +1,166 @@
+/*
+    grampus - a crappy grammar fuzzer
+    Copyright (C) 2022  0xca7
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+    Description:
+        a probabilistic grammar learned from sample inputs, as in
+        "Inputs from Hell" (Soremekun et al.). the samples are parsed
+        and the alternatives used in their trees are counted, this
+        gives the probability of each alternative. the probabilities
+        are written as `%prob` directives into a copy of the grammar
+        file. generating from it yields common inputs, with inverted
+        probabilities (see `Grammar::invert_probabilities`) uncommon
+        ones.
+
+    Author: 0xca7
+*/
+
+use std::fs;
+use std::io::{BufWriter, Write};
+
+use crate::grammar::Grammar;
+use crate::grammar_parser::PROBABILITY;
+use crate::parser::parse_dir;
+use crate::coverage::GrammarCoverage;
+use crate::dictionary::DICT_SYMBOL;
+
+/// the file the annotated grammar is written to
+pub const LEARNED_FILE: &str = "learned.txt";
+
+/// the result of parsing the samples
+#[derive(Debug, Clone, Default)]
+pub struct Samples {
+    /// number of samples parsed
+    pub parsed: usize,
+    /// the files which could not be read or parsed and why
+    pub rejected: Vec<(String, String)>,
+    /// how often each alternative is used by the samples
+    pub coverage: GrammarCoverage,
+}
+
+/// parse the files in the directory `dir` as `start_symbol`
+/// and count the alternatives they use
+pub fn learn(g: &Grammar, start_symbol: &str, dir: &str)
+    -> std::io::Result<Samples> {
+
+    let inputs = parse_dir(g, start_symbol, dir)?;
+    let mut samples = Samples {
+        parsed:   inputs.parsed.len(),
+        rejected: inputs.rejected,
+        coverage: GrammarCoverage::new(),
+    };
+    for (_, _, tree) in &inputs.parsed {
+        samples.coverage.record_tree(g, tree);
+    }
+
+    Ok(samples)
+}
+
+/// the probability of each alternative of the rules used by the
+/// samples, indexed by symbol. rules which are not used are `None`.
+pub fn probabilities(g: &Grammar, coverage: &GrammarCoverage)
+    -> Vec<Option<Vec<f64>>> {
+
+    let mut probabilities = vec![None; g.symbols.len()];
+
+    for &nterm in &g.order {
+        let counts: Vec<u64> = (0..g.productions[nterm].len())
+            .map(|alt| coverage.expansions(nterm, alt).chosen)
+            .collect();
+        let total: u64 = counts.iter().sum();
+        if total > 0 {
+            probabilities[nterm] = Some(counts.iter()
+                .map(|&n| n as f64 / total as f64)
+                .collect());
+        }
+    }
+
+    probabilities
+}
+
+/// copy the grammar file of `g` to `path`, with a `%prob` directive
+/// for each rule in `probabilities` after the rules. the directives
+/// of the grammar file are replaced.
+pub fn write_annotated(grammar_file: &str, g: &Grammar,
+    probabilities: &[Option<Vec<f64>>], path: &str)
+    -> std::io::Result<()> {
+
+    let contents = fs::read_to_string(grammar_file)?;
+    let mut w = BufWriter::new(fs::File::create(path)?);
+
+    // the grammar ends at the first empty line
+    let mut lines = contents.split('\n');
+    for line in lines.by_ref() {
+        if line.is_empty() {
+            break;
+        }
+        if line.split_whitespace().next() != Some(PROBABILITY) {
+            writeln!(w, "{}", line)?;
+        }
+    }
+
+    // `@DICT` is filled from the fuzz target, its
+    // alternatives differ between targets
+    for &nterm in g.order.iter().filter(|&&n| g.name(n) != DICT_SYMBOL) {
+        if let Some(p) = &probabilities[nterm] {
+            let p: Vec<String> = p.iter().map(|p| format!("{:.6}", p)).collect();
+            writeln!(w, "{} {} {}", PROBABILITY, g.name(nterm), p.join(" "))?;
+        }
+    }
+
+    let rest: Vec<&str> = lines.collect();
+    if !rest.is_empty() {
+        write!(w, "\n{}", rest.join("\n"))?;
+    }
+
+    Ok(())
+}
+
+
+// TESTING
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::syntax_tree::SyntaxTree;
+
+    #[test]
+    fn test_probabilities() {
+        let mut g = Grammar::new("grammars/expr.txt", 1000).unwrap();
+        let parser = Parser::new(&g);
+        let mut coverage = GrammarCoverage::new();
+        for input in [&b"1+2"[..], b"3", b"4"] {
+            let tree: SyntaxTree = parser.parse("start", input).unwrap();
+            coverage.record_tree(&g, &tree);
+        }
+        g.probabilities = probabilities(&g, &coverage);
+        let (expr, digit) = (g.non_terminal("expr").unwrap(),
+            g.non_terminal("digit").unwrap());
+        // expr ::= term '+' expr | term '-' expr | term
+        assert_eq!(g.probabilities[expr], Some(vec![0.25, 0.0, 0.75]));
+
+        // the unseen alternative is the only uncommon one
+        g.invert_probabilities();
+        assert_eq!(g.probabilities[expr], Some(vec![0.0, 1.0, 0.0]));
+        // digit: 1, 2, 3, 4 once each, the others never
+        let p = g.probabilities[digit].as_ref().unwrap();
+        assert_eq!(p[1], 0.0);
+        assert!((p[0] - 1.0 / 6.0).abs() < 1e-9);
+    }
+}
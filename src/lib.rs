@@ -45,9 +45,57 @@ pub mod grammar_parser;
 /// a grammar inside the program
 pub mod grammar;
 
+/// interned terminals and non-terminals of a grammar
+pub mod symbol;
+
 /// internal representation of a syntax tree to 
 /// derive from a grammar
 pub mod syntax_tree;
 
 /// scheduler to determine what mutations to apply
 pub mod scheduler;
+
+/// tokens harvested from the fuzz target
+pub mod dictionary;
+
+/// static analysis of a grammar
+pub mod analysis;
+
+/// layout policy, what is put between tokens
+pub mod layout;
+
+/// uniform sampling of derivation trees
+pub mod uniform;
+
+/// exhaustive enumeration of derivation trees
+pub mod enumerate;
+
+/// systematic coverage of the alternatives of a grammar
+pub mod coverage;
+
+/// k-path coverage guided generation
+pub mod kpath;
+
+/// the grammar compiled for a fast virtual machine
+pub mod vm;
+
+/// derivations decoded from and encoded to bytes
+pub mod decision;
+
+/// the inputs generated in earlier corpora and runs
+pub mod known;
+
+/// corpora saved with their syntax trees
+pub mod snapshot;
+
+/// the files a generated corpus is written to
+pub mod output;
+
+/// parses inputs into derivation trees
+pub mod parser;
+
+/// probabilistic grammars learned from sample inputs
+pub mod learn;
+
+/// corpus minimization by grammar and behaviour coverage
+pub mod cmin;
@@ -24,25 +24,66 @@
     Author: 0xca7
 */
 
+use std::fs;
+use std::path::Path;
+use std::thread;
 use std::process;
-use std::time::Instant;
+use std::time::{Duration, Instant};
+use std::collections::HashMap;
 
 use colored::*;
-use clap::{Arg, App};
+use clap::{Arg, App, ArgMatches};
 
-use grampus::util::{file_exists, check_start_symbol};
+use grampus::util::{file_exists, check_start_symbol, generate_seed,
+    stream_seed};
 use grampus::fuzzer::fuzz;
-use grampus::corpus::Corpus;
+use grampus::corpus::{Corpus, Strategy, Stream};
+use grampus::dictionary::Dictionary;
+use grampus::analysis::analyze;
+use grampus::layout::LayoutKind;
+use grampus::coverage::{CoverageKind, COVERAGE_FILE};
+use grampus::learn::{self, LEARNED_FILE};
+use grampus::parser::{self, Parser};
+use grampus::cmin::{self, CminKind, CMIN_FILE};
+use grampus::known::Known;
+use grampus::snapshot::SNAPSHOT_FILE;
+use grampus::output::{Output, Naming};
+use grampus::stats::Distribution;
+use grampus::syntax_tree::SyntaxTree;
 
 /// max width of expansions
 const MAX_EXPANSION: usize = 3000;
 /// number of inputs for fuzzing
 const FOREST_SIZE:   usize = 1000;
+/// default max. string length for the grammar analysis
+const MAX_LENGTH:    &str = "6";
+/// max. inputs a streaming corpus generates in a round of 'gen'
+const STREAM_BATCH:  usize = 10000;
+/// rounds of 'gen' without a new input before it gives up
+const MAX_IDLE_ROUNDS: usize = 10;
+/// default milliseconds the target may run on an input in 'cmin'
+const CMIN_TIMEOUT:  usize = 1000;
+/// default probability of recycling an input of a streaming corpus
+const RECYCLE:       &str = "0.5";
 
 /// fuzzer mode fuzz, does fuzzing on a target
+#[allow(clippy::redundant_static_lifetimes)]
 const MODE_FUZZ:     &'static str = "fuzz";
 /// this mode only generates a corpus
+#[allow(clippy::redundant_static_lifetimes)]
 const MODE_GEN:      &'static str = "gen";
+/// this mode reports the shape of the grammar
+const MODE_ANALYZE:  &str = "analyze";
+/// this mode learns probabilities from sample inputs
+const MODE_LEARN:    &str = "learn";
+/// this mode checks if files are in the language
+const MODE_CHECK:    &str = "check";
+/// this mode minimizes a corpus
+const MODE_CMIN:     &str = "cmin";
+/// the directory the minimized corpus is written to
+const CMIN_DIR:      &str = "cmin";
+/// the directory the generated corpus is written to
+const CORPUS_DIR:    &str = "corpus";
 
 /// fancy banner, because ASCII art is cool
 fn banner() {
@@ -60,20 +101,80 @@ print!("{}", r#"
         'fuzz': fuzz mode fuzzes the supplied target.
                 - requires the '-t' flag
         'gen' : only generates input for fuzzing, does
-                no fuzz testing
+                no fuzz testing. if '-t' is given, strings
+                from the target are used for '@DICT'
+        'analyze': reports depth, length, finiteness,
+                recursion and ambiguity of each rule
+        'learn': learns the probabilities of the alternatives
+                from the inputs in the '-i' directory
+        'check': checks if the files or the files in the
+                directories given are in the language, exits
+                with 1 if one is rejected or can not be read
+        'cmin': keeps a minimal subset of the inputs in the '-i'
+                directory which covers the same alternatives of
+                the grammar or behaviour of the target
 
     EXAMPLES:
         grampus -g grammars/ini.txt -s INI -m gen 
         -> generates fuzz inputs for fuzzing with a different,
            good fuzzer (for example AFL).
+        grampus -g grammars/json.txt -s JSON -m gen -n 10000 -j 4 \
+            -o json --naming hash --suffix .json
+        -> generates 10000 inputs with 4 threads, named by their
+           hash, e.g. json/86d3b4d7e8b0d5a1.json
         grampus -g grammars/ini.txt -s INI -m fuzz -t target
         -> generates inputs from grammar ini.txt and uses them
            to fuzz the 'target'
+        grampus -g grammars/url.txt -s URL -m analyze -l 8
+        -> analyzes url.txt, counting strings up to 8 bytes
+        grampus -g grammars/json.txt -s JSON -m learn -i samples
+        -> writes json.txt with the probabilities of the samples
+           to learned.txt
+        grampus -g grammars/json.txt -s JSON -m check samples
+        -> accepts or rejects each file in samples
+        grampus -g grammars/json.txt -s JSON -m cmin -i corpus
+        -> copies the inputs of corpus which cover all alternatives
+           to cmin/, the report is written to cmin.txt
+        grampus -g grammars/json.txt -s JSON -m gen -o json \
+            --report-dir reports
+        -> writes the coverage report to reports/coverage.txt
 
 "#.yellow().bold());
 
 }
 
+/// parse the numeric argument `name`, exits if it is not a number
+fn parse_number(matches: &ArgMatches, name: &str) -> Option<usize> {
+    let value = matches.value_of(name)?;
+    match value.parse::<usize>() {
+        Ok(n)  => Some(n),
+        Err(e) => {
+            println!("[!] invalid {}: {}", name, e);
+            process::exit(1);
+        }
+    }
+}
+
+/// parse the seed, decimal or hex with `0x`, exits if it is invalid.
+/// without a seed, a random one is generated.
+fn parse_seed(matches: &ArgMatches) -> u64 {
+    let value = match matches.value_of("seed") {
+        Some(value) => value,
+        None        => return generate_seed(),
+    };
+    let seed = match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None      => value.parse::<u64>(),
+    };
+    match seed {
+        Ok(seed) => seed,
+        Err(e)   => {
+            println!("[!] invalid seed: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
 fn main() {
     
     banner();
@@ -100,16 +201,204 @@ fn main() {
             .short("m")
             .long("mode")
             .value_name("mode")
-            .help("the mode to run in, is either 'fuzz' or 'gen'")
+            .help("the mode to run in, is either 'fuzz', 'gen', \
+                'analyze', 'learn', 'check' or 'cmin'")
             .required(true)
             .takes_value(true))
         .arg(Arg::with_name("fuzz target")
             .short("t")
             .long("fuzz-target")
             .value_name("fuzz_target")
-            .help("the program to fuzz")
+            .help("the program to fuzz, strings and magic constants \
+                are harvested from it")
             .required(false)
             .takes_value(true))
+        .arg(Arg::with_name("inputs")
+            .short("i")
+            .long("inputs")
+            .value_name("inputs")
+            .help("a directory of sample inputs for 'learn', of seed \
+                inputs for 'gen' and 'fuzz', of the corpus for 'cmin'")
+            .takes_value(true))
+        .arg(Arg::with_name("uncommon")
+            .long("uncommon")
+            .help("invert the probabilities of the grammar to generate \
+                uncommon inputs"))
+        .arg(Arg::with_name("seed")
+            .long("seed")
+            .value_name("seed")
+            .help("the master seed of all PRNGs, decimal or hex, the same \
+                seed, grammar and target reproduce a run")
+            .takes_value(true))
+        .arg(Arg::with_name("max length")
+            .short("l")
+            .long("max-length")
+            .value_name("max_length")
+            .help("the max. string length considered by 'analyze'")
+            .default_value(MAX_LENGTH)
+            .takes_value(true))
+        .arg(Arg::with_name("layout")
+            .long("layout")
+            .value_name("layout")
+            .help("what is put between tokens, overrides '%layout' \
+                of the grammar")
+            .possible_values(&["none", "space", "random", "pretty"])
+            .takes_value(true))
+        .arg(Arg::with_name("min depth")
+            .long("min-depth")
+            .value_name("min_depth")
+            .help("the min. depth of the generated syntax trees")
+            .takes_value(true))
+        .arg(Arg::with_name("max depth")
+            .long("max-depth")
+            .value_name("max_depth")
+            .help("the max. depth of the generated syntax trees")
+            .takes_value(true))
+        .arg(Arg::with_name("min size")
+            .long("min-size")
+            .value_name("min_size")
+            .help("the min. length of the generated inputs in bytes")
+            .takes_value(true))
+        .arg(Arg::with_name("max size")
+            .long("max-size")
+            .value_name("max_size")
+            .help("the max. length of the generated inputs in bytes")
+            .takes_value(true))
+        .arg(Arg::with_name("spread")
+            .long("spread")
+            .help("spread the input lengths evenly between min. and \
+                max. size"))
+        .arg(Arg::with_name("strategy")
+            .long("strategy")
+            .value_name("strategy")
+            .help("how inputs are generated: 'random' alternatives, \
+                'uniform' among all inputs of a length or 'enumerate' \
+                all inputs, shortest derivations first")
+            .possible_values(&["random", "uniform", "enumerate"])
+            .default_value("random")
+            .takes_value(true))
+        .arg(Arg::with_name("coverage seed")
+            .long("coverage-seed")
+            .value_name("coverage")
+            .help("start each corpus with inputs which cover all \
+                'alternatives' or all 'pairwise' combinations of a parent \
+                and a child alternative")
+            .possible_values(&["alternatives", "pairwise"])
+            .takes_value(true))
+        .arg(Arg::with_name("k-path")
+            .long("k-path")
+            .value_name("k")
+            .help("prefer alternatives which cover new sequences of k \
+                nested non-terminals")
+            .takes_value(true))
+        .arg(Arg::with_name("cmin coverage")
+            .long("cmin-coverage")
+            .value_name("coverage")
+            .help("what the inputs kept by 'cmin' cover: the \
+                'grammar' alternatives of their trees, which are taken \
+                from the corpus of '--load' or parsed, or the \
+                'behaviour' of the target, requires '-t'")
+            .possible_values(&["grammar", "behaviour"])
+            .default_value("grammar")
+            .takes_value(true))
+        .arg(Arg::with_name("timeout")
+            .long("timeout")
+            .value_name("ms")
+            .help("the milliseconds the target may run on an input in \
+                'cmin', then it is killed, 1000 by default")
+            .takes_value(true))
+        .arg(Arg::with_name("output dir")
+            .short("o")
+            .long("output-dir")
+            .value_name("output_dir")
+            .help("the directory 'gen' writes the corpus to, 'corpus' \
+                by default, and 'cmin' the kept inputs, 'cmin' by \
+                default")
+            .takes_value(true))
+        .arg(Arg::with_name("report dir")
+            .long("report-dir")
+            .value_name("report_dir")
+            .help("the directory the reports are written to, coverage.txt \
+                of 'gen' and 'fuzz', learned.txt of 'learn' and cmin.txt \
+                of 'cmin', the current directory by default")
+            .takes_value(true))
+        .arg(Arg::with_name("count")
+            .short("n")
+            .long("count")
+            .value_name("count")
+            .help("the number of inputs 'gen' generates, 1000 by default")
+            .takes_value(true))
+        .arg(Arg::with_name("naming")
+            .long("naming")
+            .value_name("naming")
+            .help("how 'gen' names the files, by sequence 'number' \
+                or by the 'hash' of their content")
+            .possible_values(&["number", "hash"])
+            .default_value("number")
+            .takes_value(true))
+        .arg(Arg::with_name("suffix")
+            .long("suffix")
+            .value_name("suffix")
+            .help("appended to the names of the files of 'gen', e.g. \
+                '.json'")
+            .default_value("")
+            .takes_value(true))
+        .arg(Arg::with_name("threads")
+            .short("j")
+            .long("threads")
+            .value_name("threads")
+            .help("the number of threads 'gen' generates with, the \
+                inputs of all threads are written once, in the order \
+                of the threads, until '-n' inputs are written")
+            .default_value("1")
+            .takes_value(true))
+        .arg(Arg::with_name("tree")
+            .long("tree")
+            .help("print the syntax tree of each file accepted by 'check'"))
+        .arg(Arg::with_name("files")
+            .value_name("files")
+            .help("the files and directories of files for 'check', \
+                the exit status is 1 if one of them is rejected")
+            .multiple(true))
+        .arg(Arg::with_name("save")
+            .long("save")
+            .value_name("file")
+            .help("save the corpus of 'gen' with its syntax trees, \
+                to corpus.grampus without a file, not with '--stream'")
+            .takes_value(true)
+            .min_values(0))
+        .arg(Arg::with_name("load")
+            .long("load")
+            .value_name("file")
+            .help("start 'gen' or 'fuzz' from a saved corpus instead of \
+                generating the first one, each thread starts from a part \
+                of it. 'cmin' takes the syntax trees of the inputs from it")
+            .takes_value(true))
+        .arg(Arg::with_name("known")
+            .long("known")
+            .value_name("file")
+            .help("a store of the inputs generated so far, 'gen' and \
+                'fuzz' avoid them and add new ones, it is kept \
+                across runs")
+            .takes_value(true))
+        .arg(Arg::with_name("stream")
+            .long("stream")
+            .value_name("cache")
+            .help("generate the inputs on demand instead of up front, \
+                the last 'cache' inputs are kept for recycling")
+            .takes_value(true))
+        .arg(Arg::with_name("recycle")
+            .long("recycle")
+            .value_name("probability")
+            .help("the probability that a streaming corpus passes a kept \
+                input to the fuzzer instead of a new one")
+            .default_value(RECYCLE)
+            .takes_value(true))
+        .arg(Arg::with_name("vm")
+            .long("vm")
+            .help("generate with the grammar compiled for a virtual \
+                machine, much faster, but without syntax trees, for \
+                'gen' and 'fuzz'"))
         .get_matches();
 
     // unwrap is safe, as all values are required.
@@ -118,12 +407,14 @@ fn main() {
     let mode = matches.value_of("mode").unwrap().to_string();
     
     // first, we need to know if the grammar file passed is valid
+    #[allow(clippy::print_with_newline)]
     if !file_exists(&grammar_file) {
         print!("grammar file does not exist\n");
         process::exit(1);
     }
 
     // ... and check the start symbol
+    #[allow(clippy::print_with_newline)]
     if !check_start_symbol(&grammar_file, &start_symbol) {
         print!("start symbol is not in grammar\n");
         process::exit(1);
@@ -131,6 +422,7 @@ fn main() {
 
     // next is determining which mode we are in.
     // in fuzzing mode, we need a target
+    #[allow(clippy::collapsible_if, clippy::print_with_newline)]
     if mode == "fuzz" {
         if matches.value_of("fuzz target").is_none() {
             print!("fuzz target not specified\n");
@@ -138,34 +430,489 @@ fn main() {
         }
     }
 
+    // learning needs samples
+    if mode == MODE_LEARN && matches.value_of("inputs").is_none() {
+        println!("sample inputs not specified");
+        process::exit(1);
+    }
+
+    // minimizing needs a corpus, and a target for its behaviour
+    // unwrap is safe, the value has a default
+    let cmin_kind = CminKind::from_name(matches.value_of("cmin coverage")
+        .unwrap()).unwrap();
+    if mode == MODE_CMIN && matches.value_of("inputs").is_none() {
+        println!("corpus to minimize not specified");
+        process::exit(1);
+    }
+    if mode == MODE_CMIN && cmin_kind == CminKind::Behaviour
+        && matches.value_of("fuzz target").is_none() {
+        println!("fuzz target not specified");
+        process::exit(1);
+    }
+
+    // checking needs files
+    if mode == MODE_CHECK && matches.values_of("files").is_none() {
+        println!("files to check not specified");
+        process::exit(1);
+    }
+
+    // the threads of 'gen' would all enumerate the same inputs
+    // unwrap is safe, the value has a default
+    let threads = parse_number(&matches, "threads").unwrap();
+    if threads == 0 {
+        println!("[!] 'gen' needs at least one thread");
+        process::exit(1);
+    }
+    if threads > 1 && matches.value_of("strategy") == Some("enumerate") {
+        println!("[!] enumerated inputs are generated by one thread");
+        process::exit(1);
+    }
+
+    // a streaming corpus only keeps the inputs of its cache, the
+    // others are written and dropped
+    if matches.is_present("save") && matches.is_present("stream") {
+        println!("[!] a streaming corpus can not be saved");
+        process::exit(1);
+    }
+
+    // all randomness is derived from the master seed
+    let seed = parse_seed(&matches);
+    println!("[+] seed {:#x}", seed);
+
+    // the reports are written to the current directory, or the
+    // one given, it is created if it does not exist
+    let report_dir = matches.value_of("report dir");
+    if let Some(dir) = report_dir {
+        if let Err(e) = fs::create_dir_all(dir) {
+            println!("[!] could not create {}: {}", dir, e);
+            process::exit(1);
+        }
+    }
+    let report = |file: &str| match report_dir {
+        Some(dir) => Path::new(dir).join(file).to_string_lossy().into_owned(),
+        None      => file.to_string(),
+    };
+
+    // tokens from the target, these are used for the `@DICT`
+    // non-terminal and by the mutators
+    let dictionary = match matches.value_of("fuzz target") {
+        Some(target) if file_exists(&target.to_string()) => {
+            match Dictionary::from_elf(target) {
+                Ok(dict) => {
+                    println!("[+] harvested {} strings and {} constants \
+                        from {}", dict.strings.len(), dict.constants.len(),
+                        target);
+                    dict
+                },
+                Err(e) => {
+                    println!("[!] could not harvest tokens: {}", e);
+                    Dictionary::default()
+                },
+            }
+        },
+        _ => Dictionary::default(),
+    };
+
     // regardless of the mode, we need a corpus to work with
     // so get a new corpus instance, this reads the grammar into
     // an internal representation
-    let mut corpus = Corpus::new(
+    let mut corpus = match Corpus::new(
         &grammar_file, &start_symbol, MAX_EXPANSION, FOREST_SIZE
-    );
+    ) {
+        Ok(corpus) => corpus,
+        Err(e) => {
+            println!("[!] {}", e);
+            process::exit(1);
+        },
+    };
+    corpus.set_dictionary(&dictionary);
+    corpus.set_seed(stream_seed(seed, 0));
+
+    if let Err(errors) = corpus.grammar().validate(&start_symbol) {
+        for e in errors {
+            println!("[!] {}", e);
+        }
+        process::exit(1);
+    }
+
+    // unwrap is safe, clap checks the possible values
+    if let Some(layout) = matches.value_of("layout") {
+        corpus.set_layout(LayoutKind::from_name(layout).unwrap());
+    }
+
+    let min_depth = parse_number(&matches, "min depth");
+    let max_depth = parse_number(&matches, "max depth");
+    if let Err(e) = corpus.set_depth(min_depth, max_depth) {
+        println!("[!] {}", e);
+        process::exit(1);
+    }
+
+    let min_size = parse_number(&matches, "min size");
+    let max_size = parse_number(&matches, "max size");
+    if let Err(e) = corpus.set_length(min_size, max_size,
+        matches.is_present("spread")) {
+        println!("[!] {}", e);
+        process::exit(1);
+    }
+
+    // unwrap is safe, clap checks the possible values
+    let strategy = Strategy::from_name(matches.value_of("strategy").unwrap())
+        .unwrap();
+    if let Err(e) = corpus.set_strategy(strategy) {
+        println!("[!] {}", e);
+        process::exit(1);
+    }
+
+    // unwrap is safe, clap checks the possible values
+    corpus.set_coverage(matches.value_of("coverage seed")
+        .map(|c| CoverageKind::from_name(c).unwrap()));
+
+    if let Err(e) = corpus.set_kpath(parse_number(&matches, "k-path")) {
+        println!("[!] {}", e);
+        process::exit(1);
+    }
+
+    if matches.is_present("uncommon") {
+        if !corpus.grammar().has_probabilities() {
+            println!("[!] the grammar has no probabilities, see 'learn'");
+        }
+        corpus.set_uncommon();
+    }
+
+    // the seed inputs are parsed into syntax trees, which
+    // are part of every corpus
+    if let (Some(inputs), MODE_GEN | MODE_FUZZ) = (matches.value_of("inputs"),
+        &mode[..]) {
+        match corpus.add_seed_dir(inputs) {
+            Ok((parsed, rejected)) => {
+                for (file, e) in &rejected {
+                    println!("[!] could not parse {}: {}", file, e);
+                }
+                println!("[+] parsed {} of {} seed inputs", parsed,
+                    parsed + rejected.len());
+            },
+            Err(e) => {
+                println!("[!] could not read {}: {}", inputs, e);
+                process::exit(1);
+            }
+        }
+    }
+
+    // unwrap is safe, the value has a default
+    let recycle = match matches.value_of("recycle").unwrap().parse::<f64>() {
+        Ok(recycle) => recycle,
+        Err(e) => {
+            println!("[!] invalid recycle: {}", e);
+            process::exit(1);
+        }
+    };
+    let stream = parse_number(&matches, "stream")
+        .map(|cache| Stream { cache, recycle });
+    if let Err(e) = corpus.set_stream(stream) {
+        println!("[!] {}", e);
+        process::exit(1);
+    }
+
+    // the inputs of earlier runs are avoided
+    if let Some(path) = matches.value_of("known") {
+        match Known::open(path) {
+            Ok(known) => {
+                println!("[+] {} known inputs in {}", known.len(), path);
+                corpus.set_known(Some(known));
+            },
+            Err(e) => {
+                println!("[!] could not open {}: {}", path, e);
+                process::exit(1);
+            }
+        }
+    }
+
+    // a saved corpus is the first one, its trees are
+    // those of the grammar with all options applied
+    if let Some(path) = matches.value_of("load") {
+        match corpus.load(path) {
+            Ok((snapshot, dropped)) => {
+                if snapshot.fingerprint != corpus.grammar().fingerprint() {
+                    println!("[!] {} was saved with another grammar, its \
+                        inputs were parsed again", path);
+                }
+                println!("[+] loaded {} inputs from {}, {} dropped",
+                    snapshot.entries.len() - dropped, path, dropped);
+            },
+            Err(e) => {
+                println!("[!] could not load {}: {}", path, e);
+                process::exit(1);
+            }
+        }
+    }
+
+    // the vm has to know all other options
+    if let Err(e) = corpus.set_vm(matches.is_present("vm")) {
+        println!("[!] {}", e);
+        process::exit(1);
+    }
 
     match &mode[..] {
+        MODE_ANALYZE => {
+            // unwrap is safe, the value has a default
+            let max_length = parse_number(&matches, "max length").unwrap();
+            print!("{}", analyze(corpus.grammar(), &start_symbol, max_length));
+        },
+        MODE_LEARN => {
+            // unwrap is safe, checked above
+            let inputs = matches.value_of("inputs").unwrap();
+            let samples = match learn::learn(corpus.grammar(), &start_symbol,
+                inputs) {
+                Ok(samples) => samples,
+                Err(e) => {
+                    println!("[!] could not read {}: {}", inputs, e);
+                    process::exit(1);
+                }
+            };
+            for (file, e) in &samples.rejected {
+                println!("[!] could not parse {}: {}", file, e);
+            }
+            println!("[+] parsed {} of {} samples", samples.parsed,
+                samples.parsed + samples.rejected.len());
+            println!("[+] coverage: {}", samples.coverage
+                .summary(corpus.grammar()));
+            let probabilities = learn::probabilities(corpus.grammar(),
+                &samples.coverage);
+            match learn::write_annotated(&grammar_file, corpus.grammar(),
+                &probabilities, &report(LEARNED_FILE)) {
+                Ok(()) => println!("[+] wrote {}", report(LEARNED_FILE)),
+                Err(e) => println!("[!] error: {}", e),
+            }
+        },
+        MODE_CHECK => {
+            // unwrap is safe, checked above
+            let paths: Vec<&str> = matches.values_of("files").unwrap()
+                .collect();
+            let files = match parser::files(&paths) {
+                Ok(files) => files,
+                Err(e) => {
+                    println!("[!] could not read the files: {}", e);
+                    process::exit(1);
+                }
+            };
+            let parser = Parser::new(corpus.grammar());
+            let mut accepted = 0;
+            for file in &files {
+                let tree = fs::read(file).map_err(|e| e.to_string())
+                    .and_then(|input| parser.parse(&start_symbol, &input)
+                        .map_err(|e| e.to_string()));
+                match tree {
+                    Ok(tree) => {
+                        accepted += 1;
+                        println!("[+] accept {}", file.display());
+                        if matches.is_present("tree") {
+                            print!("{}", tree.dump(&corpus.grammar().symbols));
+                        }
+                    },
+                    Err(e) => println!("[!] reject {}: {}", file.display(), e),
+                }
+            }
+            println!("[+] accepted {} of {} files", accepted, files.len());
+            if accepted < files.len() {
+                process::exit(1);
+            }
+        },
+        MODE_CMIN => {
+            // unwrap is safe, checked above
+            let inputs = matches.value_of("inputs").unwrap();
+            let files = match parser::files(&[inputs]) {
+                Ok(files) => files,
+                Err(e) => {
+                    println!("[!] could not read {}: {}", inputs, e);
+                    process::exit(1);
+                }
+            };
+            println!("[+] measuring the {} inputs of {}", files.len(), inputs);
+            let timeout = Duration::from_millis(parse_number(&matches,
+                "timeout").unwrap_or(CMIN_TIMEOUT) as u64);
+            let trees: HashMap<&[u8], &SyntaxTree> = corpus.saved_trees()
+                .iter().map(|(input, tree)| (input.as_bytes(), tree))
+                .collect();
+            let measured = cmin::measure(corpus.grammar(), &start_symbol,
+                &files, cmin_kind, matches.value_of("fuzz target"), timeout,
+                &trees);
+            let kept = cmin::minimize(&measured);
+            println!("[+] kept {} of {} inputs", kept.len(), measured.len());
+            let dir = matches.value_of("output dir").unwrap_or(CMIN_DIR);
+            match cmin::write(&measured, &kept, dir) {
+                Ok(()) => println!("[+] wrote {}", dir),
+                Err(e) => {
+                    println!("[!] could not write {}: {}", dir, e);
+                    process::exit(1);
+                }
+            }
+            match cmin::write_report(&measured, &kept, &report(CMIN_FILE)) {
+                Ok(()) => println!("[+] wrote {}", report(CMIN_FILE)),
+                Err(e) => println!("[!] error: {}", e),
+            }
+        },
         MODE_GEN => {
-            print!("[+] generating a corpus\n");
+            let count = parse_number(&matches, "count").unwrap_or(FOREST_SIZE);
+            // unwrap is safe, the values have defaults
+            let naming = Naming::from_name(matches.value_of("naming")
+                .unwrap()).unwrap();
+            let dir = matches.value_of("output dir").unwrap_or(CORPUS_DIR);
+            let output = match Output::new(dir, naming,
+                matches.value_of("suffix").unwrap()) {
+                Ok(output) => output,
+                Err(e) => {
+                    println!("[!] could not create {}: {}", dir, e);
+                    process::exit(1);
+                }
+            };
+
+            println!("[+] generating a corpus of {} inputs with {} threads",
+                count, threads);
             let now = Instant::now();
-            corpus.generate();
-            print!("[+] generation done, took {:?}.{:?} secs\n", now
+            // each thread generates its share of the missing inputs
+            // with its own stream of the seed. the inputs are written
+            // in the order of the threads, so the names and contents
+            // of the files only depend on the seed, unless the threads
+            // share a store of known inputs. inputs which
+            // another thread or round generated already are dropped,
+            // so this is repeated until `count` inputs are written
+            let mut corpora: Vec<Corpus> = (0..threads).map(|i| {
+                let mut corpus = corpus.clone();
+                corpus.set_seed(stream_seed(seed, i as u64));
+                corpus.share_saved(i, threads);
+                corpus
+            }).collect();
+            let mut snapshots: Vec<_> = Vec::new();
+            let mut depths = Distribution::new();
+            let mut idle = 0;
+            let mut exhausted = false;
+            while output.counts().0 < count && idle < MAX_IDLE_ROUNDS
+                && !exhausted {
+                let missing = count - output.counts().0;
+                // a streaming corpus holds only the inputs of a round
+                let share = match stream {
+                    Some(_) => missing.div_ceil(threads).min(STREAM_BATCH),
+                    None    => missing.div_ceil(threads),
+                };
+                let batches: Vec<(Vec<String>, bool)> =
+                    thread::scope(|scope| {
+                    let handles: Vec<_> = corpora.iter_mut().map(|corpus| {
+                        scope.spawn(move || {
+                            corpus.set_size(share);
+                            // a loaded corpus is restored as it is,
+                            // even if it is smaller
+                            let loaded = corpus.is_loaded();
+                            corpus.generate();
+                            // all inputs of the language if there
+                            // are fewer than its share
+                            let capped = stream.is_none() && !loaded
+                                && corpus.language_size().is_some();
+                            (corpus.batch(), capped)
+                        })
+                    }).collect();
+                    // unwrap is safe, a thread only panics on a bug
+                    handles.into_iter().map(|h| h.join().unwrap()).collect()
+                });
+
+                let written = output.counts().0;
+                for (batch, capped) in &batches {
+                    exhausted |= *capped;
+                    for input in batch {
+                        if output.counts().0 == count {
+                            break;
+                        }
+                        if let Err(e) = output.write(input) {
+                            println!("[!] could not write {}: {}", dir, e);
+                            process::exit(1);
+                        }
+                    }
+                }
+                idle = if output.counts().0 == written { idle + 1 } else { 0 };
+
+                // only the kept inputs of a streaming corpus are in
+                // the statistics and the snapshot
+                for corpus in &corpora {
+                    depths.merge(&corpus.depths());
+                    if matches.is_present("save") {
+                        snapshots.push(corpus.snapshot());
+                    }
+                }
+            }
+            println!("[+] generation done, took {:?}.{:?} secs", now
                 .elapsed().as_secs(), now.elapsed().as_millis());
-            match corpus.write_corpus() {
-                Ok(()) => print!("[+] wrote corpus\n"),
-                Err(e) => print!("[!] error: {}", e),
+
+            if let (trees @ 1.., goals) = corpora[0].seed() {
+                println!("[+] {} inputs cover all {} goals", trees, goals);
+            }
+            if output.counts().0 < count && exhausted {
+                println!("[+] the language has only {} inputs",
+                    output.counts().0);
+            } else if output.counts().0 < count {
+                println!("[!] no new inputs turned up in {} rounds",
+                    MAX_IDLE_ROUNDS);
+            }
+            depths.show_histogram("depth");
+            let (unique, total) = output.counts();
+            println!("[+] wrote {} inputs to {}, {} of {} were duplicates \
+                ({:.1}%)", unique, dir, total - unique, total,
+                (total - unique) as f64 * 100.0 / total.max(1) as f64);
+            println!("[+] input lengths: {}, {} bytes in total",
+                output.lengths(), output.bytes());
+
+            if matches.is_present("save") {
+                let path = matches.value_of("save").unwrap_or(SNAPSHOT_FILE);
+                // the inputs beyond `count` were not written
+                let mut snapshot = corpora[0].snapshot();
+                for other in snapshots {
+                    snapshot.merge(other);
+                }
+                snapshot.entries.retain(|entry| output
+                    .is_written(&entry.input));
+                // each thread has its own stream of the master seed
+                // and its share of the inputs
+                snapshot.set_param("seed", format!("{:#x}", seed));
+                snapshot.set_param("threads", threads.to_string());
+                snapshot.set_param("size", count.to_string());
+                match snapshot.write(path) {
+                    Ok(()) => println!("[+] saved the corpus to {}", path),
+                    Err(e) => println!("[!] error: {}", e),
+                }
+            }
+            let (generated, repeats) = corpora.iter_mut()
+                .map(|corpus| corpus.take_repeats())
+                .fold((0, 0), |(g, r), (n, k)| (g + n, r + k));
+            if let Some(stored) = corpora[0].known() {
+                println!("[+] {} of {} inputs were known, {} stored", repeats,
+                    generated, stored);
+            }
+            let mut coverage = corpora[0].take_coverage();
+            let mut kpaths = corpora[0].kpaths().cloned();
+            for corpus in &mut corpora[1..] {
+                coverage.merge(&corpus.take_coverage());
+                if let (Some(all), Some(k)) = (kpaths.as_mut(), corpus.kpaths()) {
+                    all.merge(k);
+                }
+            }
+            println!("[+] coverage: {}", coverage.summary(corpus.grammar()));
+            if let Some(kpaths) = kpaths {
+                println!("[+] coverage: {}", kpaths);
+            }
+            match coverage.write(corpus.grammar(), &report(COVERAGE_FILE)) {
+                Ok(()) => println!("[+] wrote {}", report(COVERAGE_FILE)),
+                Err(e) => println!("[!] error: {}", e),
             }
         },
         MODE_FUZZ => {
             let fuzz_target = matches.value_of("fuzz target").unwrap().to_string();
+            #[allow(clippy::print_with_newline)]
             if !file_exists(&fuzz_target) {
                 print!("[!] fuzz target does not exist\n");
                 process::exit(1);
             }
             // use the corpus for fuzzing a target
             print!("[+] starting fuzzer on target {}", fuzz_target);
-            fuzz(corpus, &fuzz_target);
+            fuzz(corpus, &fuzz_target, &dictionary, seed,
+                &report(COVERAGE_FILE));
         },
         _         => print!("[!] error, unknown mode..."),
     }
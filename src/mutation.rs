@@ -25,14 +25,18 @@
     Author: 0xca7
 */
 
+use std::sync::Arc;
+
 extern crate xshift;
 use xshift::XorShift64;
 
-use crate::util::generate_seed;
-
 /// alias for mutation functions
 pub type MutationFunction = fn(rand: &mut XorShift64, &Vec<u8>) -> Vec<u8>;
 
+/// alias for mutation functions which take tokens from a dictionary
+pub type DictMutationFunction = fn(rand: &mut XorShift64, &[u8], 
+    &[Vec<u8>]) -> Vec<u8>;
+
 /// describes the specific mutations a mutator applies
 pub enum MutatorType {
     /// bitflip, XOR and arithmetic mutations only
@@ -49,6 +53,10 @@ pub struct Mutator {
     prng: XorShift64,
     /// available mutations for random application
     mutations: Vec<MutationFunction>,
+    /// available dictionary mutations, only used with a dictionary
+    dict_mutations: Vec<DictMutationFunction>,
+    /// tokens harvested from the target, see `Dictionary`
+    dictionary: Arc<Vec<Vec<u8>>>,
     /// maximum number of mutations
     max_mutations: usize,
 }
@@ -56,18 +64,28 @@ pub struct Mutator {
 impl Mutator {
 
     /// creates a new mutator of `mut_type` and sets the 
-    /// `max_mutations` to apply per input string
-    pub fn new(mut_type: MutatorType, max_mutations: usize) -> Mutator {
-
-        let prng = XorShift64::new(generate_seed()).unwrap();
+    /// `max_mutations` to apply per input string.
+    /// tokens from the `dictionary` are used as an additional
+    /// source of bytes, an empty dictionary disables this.
+    /// the mutations are chosen by a PRNG seeded with `seed`.
+    #[allow(clippy::redundant_field_names)]
+    pub fn new(mut_type: MutatorType, max_mutations: usize,
+        dictionary: &Arc<Vec<Vec<u8>>>, seed: u64) -> Mutator {
+
+        let prng = match XorShift64::new(seed) {
+            Some(rng)   => rng,
+            None        => panic!("invalid PRNG seed\n"),
+        };
 
         let mut mutations: Vec<MutationFunction> = Vec::new();
+        let mut dict_mutations: Vec<DictMutationFunction> = Vec::new();
 
         match mut_type {
             MutatorType::Deterministic      => {
                 mutations.push(mutation_bitflip);
                 mutations.push(mutation_xor);
                 mutations.push(mutation_arithmetic);
+                dict_mutations.push(mutation_dict_overwrite);
             },
             MutatorType::NonDeterministic   => {
                 mutations.push(mutation_bitflip);
@@ -75,33 +93,48 @@ impl Mutator {
                 mutations.push(mutation_arithmetic);
                 mutations.push(mutation_insert);
                 mutations.push(mutation_remove);
+                dict_mutations.push(mutation_dict_overwrite);
+                dict_mutations.push(mutation_dict_insert);
             },
             MutatorType::BitWalk            => {
                 mutations.push(mutation_bitwalk);
                 mutations.push(mutation_insert);
                 mutations.push(mutation_remove);
+                dict_mutations.push(mutation_dict_insert);
             },
         }
 
+        if dictionary.is_empty() {
+            dict_mutations.clear();
+        }
+
         Mutator {
             prng:           prng,
             mutations:      mutations,
+            dict_mutations: dict_mutations,
+            dictionary:     Arc::clone(dictionary),
             max_mutations:  max_mutations,
         }
     }
 
     /// take an input and apply `self.max_mutations` to it
     /// clones the `input` and returns the mutated result
+    #[allow(clippy::ptr_arg)]
     pub fn mutate(&mut self, input: &Vec<u8>) -> Vec<u8> {
     
         let mut mutation = input.clone();
 
         let n = (self.prng.rand() as usize) % self.max_mutations;
+        let total = self.mutations.len() + self.dict_mutations.len();
         
         for _ in 0..n {
-            let f = self.mutations[(self.prng.rand() as usize) % 
-                self.mutations.len()];
-            mutation = f(&mut self.prng, &mutation);
+            let idx = (self.prng.rand() as usize) % total;
+            mutation = if idx < self.mutations.len() {
+                self.mutations[idx](&mut self.prng, &mutation)
+            } else {
+                self.dict_mutations[idx - self.mutations.len()](
+                    &mut self.prng, &mutation, &self.dictionary)
+            };
     
             // don't remove too many characters
             if mutation.len() == 1 {
@@ -115,6 +148,7 @@ impl Mutator {
 }
 
 /// remove a byte from a vector
+#[allow(clippy::len_zero, clippy::ptr_arg)]
 pub fn mutation_remove(rand: &mut XorShift64, input: &Vec<u8>) -> Vec<u8> {
     let mut v = input.clone();
     if v.len() != 0 {
@@ -124,6 +158,7 @@ pub fn mutation_remove(rand: &mut XorShift64, input: &Vec<u8>) -> Vec<u8> {
 }
 
 /// insert a byte in a vector
+#[allow(clippy::ptr_arg)]
 pub fn mutation_insert(rand: &mut XorShift64, input: &Vec<u8>) -> Vec<u8> {
     let mut v = input.clone();
     v.insert(
@@ -134,6 +169,7 @@ pub fn mutation_insert(rand: &mut XorShift64, input: &Vec<u8>) -> Vec<u8> {
 }
 
 /// flip a single bit in a vector
+#[allow(clippy::ptr_arg)]
 pub fn mutation_bitflip(rand: &mut XorShift64, input: &Vec<u8>) -> Vec<u8> {
 
     let mut v = input.clone();
@@ -153,6 +189,7 @@ pub fn mutation_bitflip(rand: &mut XorShift64, input: &Vec<u8>) -> Vec<u8> {
 }
 
 /// xor a single byte in a vector
+#[allow(clippy::ptr_arg)]
 pub fn mutation_xor(rand: &mut XorShift64, input: &Vec<u8>) -> Vec<u8> {
 
     let mut v = input.clone();
@@ -173,6 +210,7 @@ pub fn mutation_xor(rand: &mut XorShift64, input: &Vec<u8>) -> Vec<u8> {
 
 /// takes a byte from an input and treats it as an integer
 /// which arithmetic is applied to. here, it is a wrapping add.
+#[allow(clippy::ptr_arg)]
 pub fn mutation_arithmetic(rand: &mut XorShift64, input: &Vec<u8>) -> Vec<u8> {
 
     let mut v = input.clone();
@@ -191,6 +229,7 @@ pub fn mutation_arithmetic(rand: &mut XorShift64, input: &Vec<u8>) -> Vec<u8> {
 
 /// walk through the input byte by byte, flip l bits in each byte
 /// supported l-values: [1,4]
+#[allow(clippy::ptr_arg)]
 pub fn mutation_bitwalk(rand: &mut XorShift64, input: &Vec<u8>) -> Vec<u8> {
 
     let mut mutation = input.clone();
@@ -204,3 +243,32 @@ pub fn mutation_bitwalk(rand: &mut XorShift64, input: &Vec<u8>) -> Vec<u8> {
 
     mutation
 }
+
+/// overwrite the input at a random position with a 
+/// token from the `dictionary`
+pub fn mutation_dict_overwrite(rand: &mut XorShift64, input: &[u8],
+    dictionary: &[Vec<u8>]) -> Vec<u8> {
+
+    let mut v = input.to_vec();
+    let token = &dictionary[(rand.rand() as usize) % dictionary.len()];
+
+    // position to overwrite, the token may extend the input
+    let idx = (rand.rand() as usize) % (v.len() + 1);
+    let end = (idx + token.len()).min(v.len());
+
+    v.splice(idx..end, token.iter().cloned());
+    v
+}
+
+/// insert a token from the `dictionary` at a random position
+pub fn mutation_dict_insert(rand: &mut XorShift64, input: &[u8],
+    dictionary: &[Vec<u8>]) -> Vec<u8> {
+
+    let mut v = input.to_vec();
+    let token = &dictionary[(rand.rand() as usize) % dictionary.len()];
+
+    let idx = (rand.rand() as usize) % (v.len() + 1);
+
+    v.splice(idx..idx, token.iter().cloned());
+    v
+}
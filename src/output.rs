@@ -0,0 +1,193 @@
+/*
+    grampus - a crappy grammar fuzzer
+    Copyright (C) 2022  0xca7
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+    Description:
+        the files of a generated corpus. the generator threads of
+        `gen` share one output, an input is only written once, no
+        matter which thread generated it.
+
+    Notes:
+        the files are named by a sequence number, in the order they
+        are written, or by the FNV hash of their content. existing
+        files of the same name are overwritten.
+
+    Author: 0xca7
+*/
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::collections::HashSet;
+
+extern crate fnv;
+use fnv::FnvHash;
+
+use crate::stats::Distribution;
+
+/// how the files of a corpus are named
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Naming {
+    /// the sequence number, `0000`, `0001`, ...
+    Number,
+    /// the hash of the content
+    Hash,
+}
+
+impl Naming {
+
+    /// parse a naming from its name
+    pub fn from_name(name: &str) -> Option<Naming> {
+        match name {
+            "number" => Some(Naming::Number),
+            "hash"   => Some(Naming::Hash),
+            _        => None,
+        }
+    }
+
+}
+
+/// the inputs written so far
+#[derive(Debug, Default)]
+struct Written {
+    /// the hashes of the inputs, to drop duplicates
+    hashes:  HashSet<u64>,
+    /// the number of inputs passed to `write`
+    offered: usize,
+    /// the lengths of the inputs
+    lengths: Distribution,
+    /// the total size of the inputs in bytes
+    bytes:   usize,
+}
+
+/// the directory a corpus is written to
+#[derive(Debug)]
+pub struct Output {
+    /// the directory of the files
+    dir:     PathBuf,
+    /// how the files are named
+    naming:  Naming,
+    /// appended to the name of each file, e.g. `.json`
+    suffix:  String,
+    /// the inputs written so far
+    written: Mutex<Written>,
+}
+
+impl Output {
+
+    /// write the inputs to the directory `dir`, it is
+    /// created if it does not exist
+    pub fn new(dir: &str, naming: Naming, suffix: &str) -> io::Result<Output> {
+        fs::create_dir_all(dir)?;
+        Ok(Output {
+            dir:     PathBuf::from(dir),
+            naming,
+            suffix:  suffix.to_string(),
+            written: Mutex::new(Written::default()),
+        })
+    }
+
+    /// write `input` to a new file, false if it was written already
+    pub fn write(&self, input: &str) -> io::Result<bool> {
+
+        let hash = FnvHash::new().hash(input.as_bytes());
+        let name = {
+            // unwrap is safe, the lock is only poisoned if a
+            // generator thread panicked
+            let mut written = self.written.lock().unwrap();
+            written.offered += 1;
+            if !written.hashes.insert(hash) {
+                return Ok(false);
+            }
+            written.lengths.add(input.len());
+            written.bytes += input.len();
+            match self.naming {
+                Naming::Number => format!("{:#04}{}", written.hashes.len() - 1,
+                    self.suffix),
+                Naming::Hash   => format!("{:016x}{}", hash, self.suffix),
+            }
+        };
+
+        let mut writer = BufWriter::new(File::create(self.dir.join(name))?);
+        // NOTE: add a newline here if the output shall
+        // contain a newline as a last character
+        write!(&mut writer, "{}", input)?;
+        writer.flush()?;
+        Ok(true)
+    }
+
+    /// true if `input` was written
+    pub fn is_written(&self, input: &str) -> bool {
+        let hash = FnvHash::new().hash(input.as_bytes());
+        self.written.lock().unwrap().hashes.contains(&hash)
+    }
+
+    /// the number of inputs written and passed to `write`,
+    /// the others were duplicates
+    pub fn counts(&self) -> (usize, usize) {
+        let written = self.written.lock().unwrap();
+        (written.hashes.len(), written.offered)
+    }
+
+    /// the lengths of the inputs written
+    pub fn lengths(&self) -> Distribution {
+        self.written.lock().unwrap().lengths.clone()
+    }
+
+    /// the total size of the inputs written in bytes
+    pub fn bytes(&self) -> usize {
+        self.written.lock().unwrap().bytes
+    }
+
+}
+
+
+// TESTING
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_output() {
+        let dir = crate::util::test_path("output");
+        let _ = fs::remove_dir_all(&dir);
+        // unwrap is safe, the temp dir is valid unicode here
+        let dir = dir.to_str().unwrap();
+
+        let output = Output::new(dir, Naming::Number, ".txt").unwrap();
+        assert!(output.write("1+2").unwrap());
+        assert!(output.write("").unwrap());
+        assert!(!output.write("1+2").unwrap());
+        assert_eq!(output.counts(), (2, 3));
+        assert_eq!(output.bytes(), 3);
+        assert!(output.is_written("1+2") && !output.is_written("2+1"));
+        assert_eq!(fs::read_to_string(format!("{}/0000.txt", dir)).unwrap(),
+            "1+2");
+        assert_eq!(fs::read(format!("{}/0001.txt", dir)).unwrap().len(), 0);
+
+        let output = Output::new(dir, Naming::Hash, "").unwrap();
+        assert!(output.write("1+2").unwrap());
+        let name = format!("{:016x}", FnvHash::new().hash(b"1+2"));
+        assert!(Path::new(dir).join(name).exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
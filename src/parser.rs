@@ -0,0 +1,648 @@
+/*
+    grampus - a crappy grammar fuzzer
+    Copyright (C) 2022  0xca7
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+    Description:
+        an Earley parser, it turns an input into a derivation tree
+        of a grammar. any context-free grammar is parsed, including
+        ambiguous and left-recursive rules. terminals are matched
+        byte by byte, `\n` in a terminal matches a newline, like in
+        the generated inputs. nothing is skipped between tokens, the
+        input has to be in the `none` layout.
+
+    Notes:
+        each item of the chart points back to the items it was made
+        of, an item is only added once, so the back pointers lead to
+        items added before and the tree is built without cycles and
+        without backtracking. if the input is ambiguous, the tree of
+        the derivation recognized first is built. nullable rules are
+        handled as by Aycock and Horspool, a skipped rule derives the
+        empty string by its shallowest derivation.
+
+        right recursion is parsed in linear time with the items of
+        Leo: if a completed rule is the last symbol of the only item
+        waiting for it, the items it completes form a chain. only the
+        topmost item of the chain is added, the others are rebuilt
+        from the chain when the tree is built.
+
+    Author: 0xca7
+*/
+
+use std::fs;
+use std::fmt;
+use std::rc::Rc;
+use std::path::PathBuf;
+use std::collections::HashMap;
+
+use crate::grammar::Grammar;
+use crate::symbol::Symbol;
+use crate::syntax_tree::{SyntaxTree, NodeId};
+
+/// an Earley item, alternative `alt` of `rule` started at
+/// position `origin`, the symbols before `dot` are recognized
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Item {
+    rule:   Symbol,
+    alt:    usize,
+    dot:    usize,
+    origin: usize,
+}
+
+/// how an item was added to the chart, the indices
+/// are those of the items in their sets
+#[derive(Debug, Clone, Copy)]
+enum Back {
+    /// predicted, nothing is recognized yet
+    Predict,
+    /// the terminal before the dot was scanned after an item
+    Scan(usize),
+    /// the non-terminal before the dot was completed by an item
+    /// of this set, after an item of the set of its origin
+    Complete(usize, usize),
+    /// the nullable non-terminal before the dot was skipped
+    /// after an item of this set
+    Empty(usize),
+    /// the item is the top of the Leo chain `Link` of the chart,
+    /// the bottom of the chain was completed by an item of this set
+    Leo(usize, usize),
+}
+
+/// a link of a Leo chain, the only item waiting for a non-terminal
+/// in a set, with the non-terminal as its last symbol
+#[derive(Debug, Clone, Copy)]
+struct Link {
+    /// the set and index of the waiting item
+    set:    usize,
+    index:  usize,
+    /// the link its completion completes, if it is deterministic
+    up:     Option<usize>,
+    /// the completed item at the top of the chain
+    top:    Item,
+}
+
+/// why an input could not be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// the grammar has no such non-terminal
+    UnknownSymbol(String),
+    /// the input is not in the language, its first `position`
+    /// bytes are the longest prefix of a sentence, which can go on
+    /// with the `expected` terminals or end there
+    Syntax { position: usize, expected: Vec<String> },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnknownSymbol(symbol) => write!(f,
+                "unknown start symbol {}", symbol),
+            ParseError::Syntax { position, expected } => {
+                write!(f, "syntax error at byte {}", position)?;
+                match expected.split_last() {
+                    None => Ok(()),
+                    Some((last, [])) => write!(f, ", expected {}", last),
+                    Some((last, rest)) => write!(f, ", expected one of {} \
+                        or {}", rest.join(", "), last),
+                }
+            },
+        }
+    }
+}
+
+/// the inputs read from the files of a directory
+#[derive(Debug, Clone, Default)]
+pub struct Inputs {
+    /// the files which were parsed, their contents and trees
+    pub parsed: Vec<(String, Vec<u8>, SyntaxTree)>,
+    /// the files which could not be read or parsed and why
+    pub rejected: Vec<(String, String)>,
+}
+
+/// parses inputs with a grammar
+pub struct Parser<'a> {
+    /// the grammar of the inputs
+    grammar:    &'a Grammar,
+    /// the depth and alternative of the shallowest derivation of
+    /// the empty string, `None` if the symbol is not nullable
+    empty:      Vec<Option<(usize, usize)>>,
+}
+
+/// the items recognized while parsing one input
+#[derive(Default)]
+struct Chart {
+    /// the items of each position
+    sets:       Vec<Vec<Item>>,
+    /// how each item was added
+    backs:      Vec<Vec<Back>>,
+    /// the index of each item in its set
+    index:      Vec<HashMap<Item, usize>>,
+    /// the items of each set waiting for a non-terminal
+    waiting:    Vec<HashMap<Symbol, Vec<usize>>>,
+    /// the Leo chain of a non-terminal completed in a set, `None`
+    /// if the completion is not deterministic
+    leo:        Vec<HashMap<Symbol, Option<usize>>>,
+    /// the links of all Leo chains
+    links:      Vec<Link>,
+}
+
+impl Chart {
+
+    /// add `item` to set `k` unless it is there already,
+    /// `next` is the symbol after its dot
+    fn add(&mut self, k: usize, item: Item, back: Back, next: Option<Symbol>) {
+        if self.index[k].contains_key(&item) {
+            return;
+        }
+        let i = self.sets[k].len();
+        self.index[k].insert(item, i);
+        self.sets[k].push(item);
+        self.backs[k].push(back);
+        if let Some(symbol) = next {
+            self.waiting[k].entry(symbol).or_default().push(i);
+        }
+    }
+
+    /// the links of the chain of `link`, from the bottom to the top
+    fn chain(&self, link: usize) -> Vec<(usize, usize)> {
+        let mut chain = Vec::new();
+        let mut link = Some(link);
+        while let Some(l) = link.map(|l| self.links[l]) {
+            chain.push((l.set, l.index));
+            link = l.up;
+        }
+        chain
+    }
+
+}
+
+impl<'a> Parser<'a> {
+
+    pub fn new(g: &'a Grammar) -> Parser<'a> {
+
+        // terminals without text are empty, a non-terminal is
+        // nullable if an alternative only has nullable symbols
+        let mut empty: Vec<Option<(usize, usize)>> = (0..g.symbols.len())
+            .map(|s| Some((0, 0))
+                .filter(|_| g.is_terminal(s) && g.symbols.text(s).is_empty()))
+            .collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &nterm in &g.order {
+                for (alt, symbols) in g.productions[nterm].iter().enumerate() {
+                    let depth = symbols.iter()
+                        .map(|&s| empty[s].map(|(d, _)| d))
+                        .try_fold(0, |max, d| d.map(|d| max.max(d)));
+                    if let Some(depth) = depth.map(|d| d + 1) {
+                        if empty[nterm].is_none_or(|(d, _)| depth < d) {
+                            empty[nterm] = Some((depth, alt));
+                            changed = true;
+                        }
+                    }
+                }
+            }
+        }
+
+        Parser { grammar: g, empty }
+    }
+
+    /// the symbol after the dot of `item`, `None` if it is complete
+    fn next(&self, item: &Item) -> Option<Symbol> {
+        self.grammar.productions[item.rule][item.alt].get(item.dot).copied()
+    }
+
+    /// the Leo chain of `symbol` completed in set `j`, which must
+    /// not grow anymore. the chain goes up as long as the only item
+    /// waiting for the completed rule has it as its last symbol and
+    /// started in an earlier set, the links are remembered.
+    fn leo(&self, chart: &mut Chart, j: usize, symbol: Symbol)
+        -> Option<usize> {
+
+        // the links which are not known yet, from the bottom up
+        let mut path = Vec::new();
+        let (mut set, mut symbol) = (j, symbol);
+        let mut up = loop {
+            if let Some(&link) = chart.leo[set].get(&symbol) {
+                break link;
+            }
+            let index = match chart.waiting[set].get(&symbol) {
+                Some(waiting) if waiting.len() == 1 => waiting[0],
+                _ => {
+                    chart.leo[set].insert(symbol, None);
+                    break None;
+                },
+            };
+            let item = chart.sets[set][index];
+            if self.next(&Item { dot: item.dot + 1, ..item }).is_some() {
+                chart.leo[set].insert(symbol, None);
+                break None;
+            }
+            path.push((set, symbol, index));
+            if item.origin == set {
+                break None;
+            }
+            set = item.origin;
+            symbol = item.rule;
+        };
+
+        // link them from the top down
+        for (set, symbol, index) in path.into_iter().rev() {
+            let item = chart.sets[set][index];
+            let top = match up {
+                Some(link) => chart.links[link].top,
+                None       => Item { dot: item.dot + 1, ..item },
+            };
+            chart.links.push(Link { set, index, up, top });
+            up = Some(chart.links.len() - 1);
+            chart.leo[set].insert(symbol, up);
+        }
+
+        up
+    }
+
+    /// the terminal `symbol` as it appears in an input
+    fn terminal(&self, symbol: Symbol) -> Option<&'a [u8]> {
+        let g = self.grammar;
+        Some(g.symbols.text(symbol).as_bytes()).filter(|_| g.is_terminal(symbol))
+    }
+
+    /// the derivation tree of `input` from `start_symbol`. if the
+    /// input is not in the language, the error has the furthest
+    /// position the parser reached.
+    pub fn parse(&self, start_symbol: &str, input: &[u8])
+        -> Result<SyntaxTree, ParseError> {
+
+        let g = self.grammar;
+        let start = g.non_terminal(start_symbol)
+            .ok_or_else(|| ParseError::UnknownSymbol(start_symbol.to_string()))?;
+
+        let chart = self.recognize(start, input);
+
+        let end = input.len();
+        let complete = (0..g.productions[start].len())
+            .filter_map(|alt| chart.index[end].get(&Item {
+                rule: start, alt, dot: g.productions[start][alt].len(),
+                origin: 0,
+            }))
+            .min();
+        match complete {
+            Some(&i) => Ok(self.build(&chart, start, end, i)),
+            None     => Err(self.error(&chart, start)),
+        }
+    }
+
+    /// the chart of the items of `start` recognized in `input`
+    fn recognize(&self, start: Symbol, input: &[u8]) -> Chart {
+
+        let g = self.grammar;
+        let n = input.len() + 1;
+        let mut chart = Chart {
+            sets:    vec![Vec::new(); n],
+            backs:   vec![Vec::new(); n],
+            index:   vec![HashMap::new(); n],
+            waiting: vec![HashMap::new(); n],
+            leo:     vec![HashMap::new(); n],
+            links:   Vec::new(),
+        };
+
+        for alt in 0..g.productions[start].len() {
+            let item = Item { rule: start, alt, dot: 0, origin: 0 };
+            chart.add(0, item, Back::Predict, self.next(&item));
+        }
+
+        for k in 0..n {
+            let mut i = 0;
+            while i < chart.sets[k].len() {
+                let item = chart.sets[k][i];
+                let advanced = Item { dot: item.dot + 1, ..item };
+
+                let symbol = match self.next(&item) {
+                    Some(symbol) => symbol,
+                    // complete, advance the items waiting for the rule.
+                    // the set of the origin only grows if it is this
+                    // one, then the rule is nullable and skipped.
+                    // a deterministic completion adds the top of its
+                    // Leo chain instead
+                    None => {
+                        let link = Some(item.origin).filter(|&o| o < k)
+                            .and_then(|o| self.leo(&mut chart, o, item.rule));
+                        if let Some(link) = link {
+                            let top = chart.links[link].top;
+                            chart.add(k, top, Back::Leo(i, link),
+                                self.next(&top));
+                            i += 1;
+                            continue;
+                        }
+                        let waiting = chart.waiting[item.origin]
+                            .get(&item.rule).cloned().unwrap_or_default();
+                        for w in waiting {
+                            let w_item = chart.sets[item.origin][w];
+                            let next = Item { dot: w_item.dot + 1, ..w_item };
+                            chart.add(k, next, Back::Complete(i, w),
+                                self.next(&next));
+                        }
+                        i += 1;
+                        continue;
+                    },
+                };
+
+                if let Some(terminal) = self.terminal(symbol) {
+                    // scan
+                    if input[k..].starts_with(terminal) {
+                        chart.add(k + terminal.len(), advanced, Back::Scan(i),
+                            self.next(&advanced));
+                    }
+                } else {
+                    // predict, a nullable rule may be skipped right away
+                    for alt in 0..g.productions[symbol].len() {
+                        let predicted = Item { rule: symbol, alt, dot: 0,
+                            origin: k };
+                        chart.add(k, predicted, Back::Predict,
+                            self.next(&predicted));
+                    }
+                    if self.empty[symbol].is_some() {
+                        chart.add(k, advanced, Back::Empty(i),
+                            self.next(&advanced));
+                    }
+                }
+                i += 1;
+            }
+        }
+
+        chart
+    }
+
+    /// the error at the furthest position of the `chart`, the
+    /// terminals which can follow there and the end of the input
+    /// if a sentence of `start` ends there
+    fn error(&self, chart: &Chart, start: Symbol) -> ParseError {
+
+        // unwrap is safe, the first set has the start items
+        let position = (0..chart.sets.len()).rev()
+            .find(|&k| !chart.sets[k].is_empty()).unwrap();
+
+        let mut expected: Vec<String> = chart.sets[position].iter()
+            .filter_map(|item| self.next(item))
+            .filter(|&s| self.grammar.is_terminal(s))
+            .map(|s| format!("'{}'", self.grammar.name(s)))
+            .collect();
+        expected.sort();
+        expected.dedup();
+        let ends = chart.sets[position].iter().any(|item| item.rule == start
+            && item.origin == 0 && self.next(item).is_none());
+        if ends {
+            expected.push("end of input".to_string());
+        }
+
+        ParseError::Syntax { position, expected }
+    }
+
+    /// build the tree of the complete item `i` of set `k` of `start`
+    /// by following the back pointers, with an explicit stack
+    fn build(&self, chart: &Chart, start: Symbol, k: usize, i: usize)
+        -> SyntaxTree {
+
+        /// what a node derives
+        enum Derives {
+            /// the span of a complete item, set and index
+            Item(usize, usize),
+            /// the item completed by link `m` of a Leo `chain`, its
+            /// bottom was completed by an item of a set, see `below`
+            Chain(usize, usize, Rc<Vec<(usize, usize)>>, usize),
+            /// the empty string
+            Empty,
+        }
+
+        /// the last child of the item completed by link `m` of a
+        /// chain, the bottom link is completed by item `c` of set `k`
+        fn below(k: usize, c: usize, chain: &Rc<Vec<(usize, usize)>>,
+            m: usize) -> Derives {
+            match m {
+                0 => Derives::Item(k, c),
+                m => Derives::Chain(k, c, Rc::clone(chain), m - 1),
+            }
+        }
+
+        let g = self.grammar;
+        let mut tree = SyntaxTree::new(start);
+        let mut stack: Vec<(NodeId, Derives)> =
+            vec![(SyntaxTree::ROOT, Derives::Item(k, i))];
+
+        while let Some((node, derives)) = stack.pop() {
+            let value = tree.value(node);
+            // the item of a link has the symbols of the completed
+            // one, all but the last child follow its back pointers
+            let (mut k, mut i, mut last) = match derives {
+                Derives::Item(k, i) => (k, i, None),
+                Derives::Chain(k, c, chain, m) => {
+                    let (set, index) = chain[m];
+                    (set, index, Some(below(k, c, &chain, m)))
+                },
+                Derives::Empty => {
+                    // terminals without text are leaves, unwrap is
+                    // safe, only nullable symbols are skipped
+                    if !g.is_terminal(value) {
+                        let (_, alt) = self.empty[value].unwrap();
+                        let children = tree.expand(node,
+                            &g.productions[value][alt]);
+                        stack.extend(children.map(|c| (c, Derives::Empty)));
+                    }
+                    continue;
+                },
+            };
+
+            // the children from the last one back to the first
+            let item = chart.sets[k][i];
+            let symbols = &g.productions[item.rule][item.alt];
+            let children = tree.expand(node, symbols);
+            for (child, &symbol) in children.rev().zip(symbols.iter().rev()) {
+                if let Some(derives) = last.take() {
+                    stack.push((child, derives));
+                    continue;
+                }
+                match chart.backs[k][i] {
+                    Back::Scan(p) => {
+                        // unwrap is safe, only terminals are scanned
+                        k -= self.terminal(symbol).unwrap().len();
+                        i = p;
+                    },
+                    Back::Complete(c, p) => {
+                        stack.push((child, Derives::Item(k, c)));
+                        k = chart.sets[k][c].origin;
+                        i = p;
+                    },
+                    Back::Empty(p) => {
+                        stack.push((child, Derives::Empty));
+                        i = p;
+                    },
+                    // the item is the top of the chain
+                    Back::Leo(c, link) => {
+                        let chain = Rc::new(chart.chain(link));
+                        let m = chain.len() - 1;
+                        stack.push((child, below(k, c, &chain, m)));
+                        (k, i) = chain[m];
+                    },
+                    // a predicted item has no symbols before its dot
+                    Back::Predict => unreachable!(),
+                }
+            }
+        }
+
+        tree
+    }
+
+}
+
+
+/// the files of `paths`, a directory stands for the files
+/// in it in the order of their names
+pub fn files(paths: &[&str]) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths.iter().map(PathBuf::from) {
+        if !path.is_dir() {
+            files.push(path);
+            continue;
+        }
+        let mut entries: Vec<PathBuf> = fs::read_dir(&path)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.is_file())
+            .collect();
+        entries.sort();
+        files.append(&mut entries);
+    }
+    Ok(files)
+}
+
+/// parse the files in the directory `dir` as `start_symbol`,
+/// in the order of their names
+pub fn parse_dir(g: &Grammar, start_symbol: &str, dir: &str)
+    -> std::io::Result<Inputs> {
+
+    let parser = Parser::new(g);
+    let mut inputs = Inputs::default();
+
+    // fails if `dir` is not a directory
+    fs::read_dir(dir)?;
+
+    for path in files(&[dir])? {
+        let file = path.display().to_string();
+        let input = match fs::read(&path) {
+            Ok(input) => input,
+            Err(e)    => {
+                inputs.rejected.push((file, e.to_string()));
+                continue;
+            },
+        };
+        match parser.parse(start_symbol, &input) {
+            Ok(tree) => inputs.parsed.push((file, input, tree)),
+            Err(e)   => inputs.rejected.push((file, e.to_string())),
+        }
+    }
+
+    Ok(inputs)
+}
+
+
+// TESTING
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let g = Grammar::new("grammars/expr.txt", 1000).unwrap();
+        let parser = Parser::new(&g);
+        let tree = parser.parse("start", b"12+(3*-4.5)").unwrap();
+        let mut s = String::new();
+        tree.build(&g.symbols, &mut s);
+        assert_eq!(s, "12+(3*-4.5)");
+        let position = |input: &[u8]| match parser.parse("start", input) {
+            Err(ParseError::Syntax { position, .. }) => Some(position),
+            _ => None,
+        };
+        assert_eq!(position(b"1+"), Some(2));
+        assert_eq!(position(b"12)+3"), Some(2));
+        assert!(parser.parse("start", b"").is_err());
+
+        // long inputs are parsed without backtracking
+        let input = "(1*2-3)+".repeat(500) + "4";
+        let tree = parser.parse("start", input.as_bytes()).unwrap();
+        assert!(tree.depth() > 500);
+    }
+
+    #[test]
+    fn test_left_recursion() {
+        // left-recursive and ambiguous rules
+        let file = crate::util::test_path("left.txt");
+        std::fs::write(&file, "STRING ::= STRING LETTER | STRING STRING \
+            | LETTER\nLETTER ::= 'a' | 'b'\n").unwrap();
+        let g = Grammar::new(file.to_str().unwrap(), 1000).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        let parser = Parser::new(&g);
+        let tree = parser.parse("STRING", b"abba").unwrap();
+        let mut s = String::new();
+        tree.build(&g.symbols, &mut s);
+        assert_eq!(s, "abba");
+        assert!(parser.parse("STRING", b"").is_err());
+        assert_eq!(parser.parse("STRING", b"abc").unwrap_err().to_string(),
+            "syntax error at byte 2, expected one of 'a', 'b' or end of input");
+        assert_eq!(parser.parse("X", b"a").err(),
+            Some(ParseError::UnknownSymbol("X".to_string())));
+    }
+
+    #[test]
+    fn test_right_recursion() {
+        let g = Grammar::new("grammars/expr.txt", 1000).unwrap();
+        let parser = Parser::new(&g);
+        let start = g.non_terminal("start").unwrap();
+
+        // the chart grows linearly with the input, without the items
+        // of Leo, each '+' would complete all the ones before it
+        let items = |n: usize| {
+            let input = "1+".repeat(n) + "1";
+            parser.recognize(start, input.as_bytes()).sets.iter()
+                .map(|set| set.len()).sum::<usize>()
+        };
+        let (small, large) = (items(1000), items(2000));
+        assert!(large <= small * 21 / 10, "{} -> {} items", small, large);
+
+        // the skipped items are rebuilt from the chains
+        let input = "1+".repeat(2000) + "1";
+        let tree = parser.parse("start", input.as_bytes()).unwrap();
+        let mut s = String::new();
+        tree.build(&g.symbols, &mut s);
+        assert_eq!(s, input);
+        assert!(tree.depth() > 2000);
+
+        // a chain ending in a nullable rule, `@DICT` is empty
+        let file = crate::util::test_path("right.txt");
+        std::fs::write(&file, "LIST ::= 'a' LIST | 'b' LIST | END\n\
+            END ::= @DICT\n").unwrap();
+        let g = Grammar::new(file.to_str().unwrap(), 1000).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        let parser = Parser::new(&g);
+        for input in ["", "a", "abba", &"ab".repeat(1000)] {
+            let tree = parser.parse("LIST", input.as_bytes()).unwrap();
+            let mut s = String::new();
+            tree.build(&g.symbols, &mut s);
+            assert_eq!(s, input);
+        }
+        assert!(parser.parse("LIST", b"abc").is_err());
+    }
+}
@@ -63,6 +63,7 @@ pub struct Scheduler {
 impl Scheduler {
 
     /// instantiate a new Scheduler
+    #[allow(clippy::redundant_field_names)]
     pub fn new(max_ips: usize) -> Scheduler {
         Scheduler {
             cycle: FuzzingCycle::CycleDeterministic,
@@ -74,6 +75,7 @@ impl Scheduler {
     /// determine the new cycle, if a cycle switch
     /// occurs, set the boolean value returned from
     /// this function
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> (bool, FuzzingCycle) {
 
         if self.ips == self.max_ips {
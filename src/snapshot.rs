@@ -0,0 +1,244 @@
+/*
+    grampus - a crappy grammar fuzzer
+    Copyright (C) 2022  0xca7
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+    Description:
+        a corpus saved to a file, with the syntax tree of each input,
+        the fingerprint of the grammar and the parameters it was
+        generated with, see `Corpus::save` and `Corpus::load`.
+
+    Notes:
+        the file is text, one record per line:
+
+            grampus corpus 1
+            fingerprint <hash of the rules of the grammar, hex>
+            start <start symbol>
+            param <name> <value>
+            input <depth> <decisions, hex> <input, hex>
+
+        a tree is saved as the decisions which derive it, see
+        `decision::encode`, which are only valid for the same rules.
+        inputs without a tree have `-` as their decisions, like the
+        inputs of the vm, they are parsed again when loaded. so are
+        trees without choices, which have no decisions. an empty
+        input is `-` as well.
+
+    Author: 0xca7
+*/
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::collections::HashSet;
+
+/// the default file a corpus is saved to
+pub const SNAPSHOT_FILE: &str = "corpus.grampus";
+
+/// the first line of a saved corpus, with the version of the format
+const HEADER: &str = "grampus corpus 1";
+
+/// an input of a saved corpus
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// the input
+    pub input:     String,
+    /// the depth of its syntax tree
+    pub depth:     usize,
+    /// the decisions which derive its syntax tree, if it has one
+    pub decisions: Option<Vec<u8>>,
+}
+
+/// a saved corpus
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    /// the fingerprint of the grammar, see `Grammar::fingerprint`
+    pub fingerprint:  u64,
+    /// the start symbol of the syntax trees
+    pub start_symbol: String,
+    /// the parameters the corpus was generated with, by name
+    pub params:       Vec<(String, String)>,
+    /// the inputs
+    pub entries:      Vec<Entry>,
+}
+
+/// `bytes` in hex, `-` if there are none
+fn to_hex(bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        return "-".to_string();
+    }
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// the bytes of `hex`, see `to_hex`
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex == "-" {
+        return Some(Vec::new());
+    }
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+impl Snapshot {
+
+    /// the value of the parameter `name`, if it was saved
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.iter().find(|(n, _)| n == name).map(|(_, v)| &v[..])
+    }
+
+    /// set the parameter `name` to `value`, e.g. one which is not
+    /// known to a single corpus, it is added if it was not saved
+    pub fn set_param(&mut self, name: &str, value: String) {
+        match self.params.iter_mut().find(|(n, _)| n == name) {
+            Some((_, v)) => *v = value,
+            None         => self.params.push((name.to_string(), value)),
+        }
+    }
+
+    /// add the inputs of `other` which are not in this corpus,
+    /// e.g. of the corpus of another generator thread
+    pub fn merge(&mut self, other: Snapshot) {
+        let mut inputs: HashSet<String> = self.entries.iter()
+            .map(|entry| entry.input.clone())
+            .collect();
+        for entry in other.entries {
+            if inputs.insert(entry.input.clone()) {
+                self.entries.push(entry);
+            }
+        }
+    }
+
+    /// write the corpus to the file at `path`
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+
+        let mut w = BufWriter::new(File::create(path)?);
+
+        writeln!(w, "{}", HEADER)?;
+        writeln!(w, "fingerprint {:016x}", self.fingerprint)?;
+        writeln!(w, "start {}", self.start_symbol)?;
+        for (name, value) in &self.params {
+            writeln!(w, "param {} {}", name, value)?;
+        }
+        for entry in &self.entries {
+            let decisions = match &entry.decisions {
+                Some(decisions) => to_hex(decisions),
+                None            => "-".to_string(),
+            };
+            writeln!(w, "input {} {} {}", entry.depth, decisions,
+                to_hex(entry.input.as_bytes()))?;
+        }
+
+        w.flush()
+    }
+
+    /// read a corpus from the file at `path`
+    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Snapshot> {
+
+        let text = fs::read_to_string(path)?;
+        let invalid = |n: usize, what: &str| io::Error::new(
+            io::ErrorKind::InvalidData, format!("line {}: {}", n + 1, what));
+
+        let mut lines = text.lines().enumerate();
+        if lines.next().map(|(_, l)| l) != Some(HEADER) {
+            return Err(invalid(0, "not a saved corpus"));
+        }
+
+        let mut snapshot = Snapshot::default();
+        for (n, line) in lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[..] {
+                [] => continue,
+                ["fingerprint", hash] => {
+                    snapshot.fingerprint = u64::from_str_radix(hash, 16)
+                        .map_err(|_| invalid(n, "invalid fingerprint"))?;
+                },
+                ["start", symbol] => {
+                    snapshot.start_symbol = symbol.to_string();
+                },
+                ["param", name, ref value @ ..] => {
+                    snapshot.params.push((name.to_string(), value.join(" ")));
+                },
+                ["input", depth, decisions, input] => {
+                    let depth = depth.parse()
+                        .map_err(|_| invalid(n, "invalid depth"))?;
+                    let decisions = match decisions {
+                        "-" => None,
+                        hex => Some(from_hex(hex)
+                            .ok_or_else(|| invalid(n, "invalid decisions"))?),
+                    };
+                    let input = from_hex(input)
+                        .and_then(|bytes| String::from_utf8(bytes).ok())
+                        .ok_or_else(|| invalid(n, "invalid input"))?;
+                    snapshot.entries.push(Entry { input, depth, decisions });
+                },
+                _ => return Err(invalid(n, "unknown record")),
+            }
+        }
+
+        Ok(snapshot)
+    }
+
+}
+
+
+// TESTING
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot() {
+        let snapshot = Snapshot {
+            fingerprint:  0x0123456789abcdef,
+            start_symbol: "start".to_string(),
+            params:       vec![
+                ("grammar".to_string(), "grammars/expr.txt".to_string()),
+                ("depth".to_string(), "3 to 7".to_string()),
+            ],
+            entries:      vec![
+                Entry {
+                    input:     "-7-3\n".to_string(),
+                    depth:     9,
+                    decisions: Some(vec![1, 2, 1, 4, 1, 7, 2, 2, 4, 1, 3]),
+                },
+                Entry { input: String::new(), depth: 1, decisions: None },
+            ],
+        };
+
+        let path = crate::util::test_path("snapshot.txt");
+        snapshot.write(&path).unwrap();
+        let read = Snapshot::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(read, snapshot);
+        assert_eq!(read.param("depth"), Some("3 to 7"));
+        assert_eq!(read.param("seed"), None);
+
+        let mut snapshot = read;
+        snapshot.set_param("depth", "5".to_string());
+        snapshot.set_param("seed", "0x1".to_string());
+        assert_eq!(snapshot.param("depth"), Some("5"));
+        assert_eq!(snapshot.param("seed"), Some("0x1"));
+        assert_eq!(snapshot.params.len(), 3);
+        assert_eq!(from_hex("0g"), None);
+    }
+}
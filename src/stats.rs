@@ -24,7 +24,82 @@
     Author:
         0xca7
 */
+use std::fmt;
 use std::time::Duration;
+use std::collections::BTreeMap;
+
+use crate::grammar::Grammar;
+use crate::coverage::GrammarCoverage;
+use crate::kpath::KPaths;
+
+/// how often each value occurred, e.g. the depths of the trees
+/// of a corpus
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Distribution {
+    counts: BTreeMap<usize, u64>,
+}
+
+impl Distribution {
+
+    pub fn new() -> Distribution {
+        Distribution::default()
+    }
+
+    /// count one occurrence of `value`
+    pub fn add(&mut self, value: usize) {
+        *self.counts.entry(value).or_insert(0) += 1;
+    }
+
+    /// add all counts of `other`
+    pub fn merge(&mut self, other: &Distribution) {
+        for (value, n) in &other.counts {
+            *self.counts.entry(*value).or_insert(0) += n;
+        }
+    }
+
+    /// total number of occurrences
+    pub fn total(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    /// the smallest value with at least `p` percent of
+    /// all occurrences at or below it
+    pub fn percentile(&self, p: u64) -> Option<usize> {
+        let threshold = (self.total() * p).div_ceil(100).max(1);
+        let mut sum = 0;
+        for (value, n) in &self.counts {
+            sum += n;
+            if sum >= threshold {
+                return Some(*value);
+            }
+        }
+        None
+    }
+
+    /// print one line per value with a bar of its share
+    pub fn show_histogram(&self, name: &str) {
+        let total = self.total().max(1);
+        println!("{:>8}  {:>8}", name, "count");
+        for (value, n) in &self.counts {
+            println!("{:>8}  {:>8}  {}", value, n,
+                "#".repeat((n * 50).div_ceil(total) as usize));
+        }
+    }
+
+}
+
+/// a summary: min, median, 90th percentile and max
+impl fmt::Display for Distribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.percentile(0), self.percentile(50), self.percentile(90),
+            self.counts.keys().last()) {
+            (Some(min), Some(p50), Some(p90), Some(max)) =>
+                write!(f, "min {} / p50 {} / p90 {} / max {}", min, p50,
+                    p90, max),
+            _ => write!(f, "-"),
+        }
+    }
+}
 
 /// statistics for fuzzing
 pub struct Stats {
@@ -34,15 +109,35 @@ pub struct Stats {
     crashes: u64,
     /// cycles will be added in the future...
     cycles: u64,
+    /// number of times the grammar was reloaded
+    reloads: u64,
+    /// errors of the last rejected grammar reload
+    grammar_errors: Vec<String>,
+    /// depths of all generated syntax trees
+    depths: Distribution,
+    /// how often each alternative was chosen by all workers
+    coverage: GrammarCoverage,
+    /// the k-paths covered by all workers, if enabled
+    kpaths: Option<KPaths>,
+    /// the inputs in the store of known inputs, the inputs
+    /// generated and how many of them were known, if enabled
+    known: Option<(u64, u64, u64)>,
 }
 
 impl Stats {
 
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Stats {
         Stats {
             total: 0,
             crashes: 0,
             cycles: 0,    
+            reloads: 0,
+            grammar_errors: Vec::new(),
+            depths: Distribution::new(),
+            coverage: GrammarCoverage::new(),
+            kpaths: None,
+            known: None,
         }
     } // pub fn new
 
@@ -58,7 +153,73 @@ impl Stats {
         self.crashes += 1;
     }
 
-    pub fn show_stats(&self, sec: &u64, elapsed: &Duration) {
+    /// a changed grammar file was accepted and sent to the workers
+    pub fn grammar_reloaded(&mut self) {
+        self.reloads += 1;
+        self.grammar_errors.clear();
+    }
+
+    /// a changed grammar file was rejected, the old grammar is kept
+    pub fn grammar_rejected(&mut self, errors: Vec<String>) {
+        self.grammar_errors = errors;
+    }
+
+    /// add the depths of a newly generated corpus
+    pub fn add_depths(&mut self, depths: &Distribution) {
+        self.depths.merge(depths);
+    }
+
+    /// add the choices of alternatives of a worker
+    pub fn add_coverage(&mut self, coverage: &GrammarCoverage) {
+        self.coverage.merge(coverage);
+    }
+
+    /// track the k-paths covered by all workers, starting with `kpaths`
+    pub fn set_kpaths(&mut self, kpaths: Option<KPaths>) {
+        self.kpaths = kpaths;
+    }
+
+    /// add the k-paths covered by a worker
+    pub fn add_kpaths(&mut self, kpaths: &KPaths) {
+        if let Some(covered) = self.kpaths.as_mut() {
+            covered.merge(kpaths);
+        }
+    }
+
+    /// track the inputs generated by all workers which were known,
+    /// starting with a store of `known` inputs
+    pub fn set_known(&mut self, known: Option<usize>) {
+        self.known = known.map(|n| (n as u64, 0, 0));
+    }
+
+    /// add the inputs generated by a worker and how many
+    /// of them were known
+    pub fn add_repeats(&mut self, (generated, repeats): (usize, usize)) {
+        if let Some((stored, total, known)) = self.known.as_mut() {
+            *stored += (generated - repeats) as u64;
+            *total += generated as u64;
+            *known += repeats as u64;
+        }
+    }
+
+    /// the grammar was reloaded, the k-paths are those of `g`
+    pub fn reset_kpaths(&mut self, g: &Grammar) {
+        self.kpaths = self.kpaths.as_ref().map(|k| k.with_grammar(g));
+    }
+
+    /// the grammar `from` was reloaded as `g`, the choices
+    /// of alternatives are kept for the rules of `g`
+    pub fn reset_coverage(&mut self, from: &Grammar, g: &Grammar) {
+        self.coverage = self.coverage.with_grammar(from, g);
+    }
+
+    /// the choices of alternatives of all workers
+    pub fn coverage(&self) -> &GrammarCoverage {
+        &self.coverage
+    }
+
+    #[allow(clippy::print_with_newline)]
+    pub fn show_stats(&self, sec: &u64, elapsed: &Duration, g: &Grammar) {
 
         let fcps = self.total / sec;
 
@@ -69,7 +230,23 @@ impl Stats {
         print!("| [total]     {}\n", self.total);
         print!("| [crashes]   {}\n", self.crashes);
         print!("| [cycles]    {}\n", self.cycles);
+        print!("| [reloads]   {}\n", self.reloads);
+        print!("| [depth]     {}\n", self.depths);
+        print!("| [coverage]  {}\n", self.coverage.summary(g));
+        if let Some(kpaths) = &self.kpaths {
+            print!("| [k-paths]   {}\n", kpaths);
+        }
+        if let Some((stored, total, known)) = self.known {
+            print!("| [known]     {} of {} inputs, {} stored\n", known,
+                total, stored);
+        }
         print!("+----------------------------------+\n");
+        if !self.grammar_errors.is_empty() {
+            print!("[!] grammar rejected, keeping the old one:\n");
+            for e in &self.grammar_errors {
+                print!("    {}\n", e);
+            }
+        }
         // clear screen after each print.
         print!("{esc}[2J{esc}[1;1H", esc = 27 as char);
     }
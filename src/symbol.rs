@@ -0,0 +1,149 @@
+/*
+    grampus - a crappy grammar fuzzer
+    Copyright (C) 2022  0xca7
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+    Description:
+        the symbols of a grammar are interned, each terminal and each
+        non-terminal is a small integer. the rules of a grammar are
+        stored in vectors indexed by symbol and syntax trees hold
+        symbols, names are only looked up for display. the text of a
+        terminal, which ends up in an input, is only needed when a
+        tree is built.
+
+    Notes:
+        terminals and non-terminals are interned separately, a
+        terminal `'A'` and a non-terminal `A` are different symbols.
+        the symbols of two grammars, e.g. of a reloaded grammar
+        file, are unrelated, they are matched by name.
+
+    Author: 0xca7
+*/
+
+use std::collections::HashMap;
+
+/// an interned terminal or non-terminal of a grammar
+pub type Symbol = usize;
+
+/// the table of the symbols of a grammar
+#[derive(Debug, Clone, Default)]
+pub struct Symbols {
+    /// the name of each symbol as written in the grammar file
+    names: Vec<String>,
+    /// the text of each symbol in a sentence, for terminals the
+    /// name with `\n` replaced by a newline, else the name
+    text: Vec<String>,
+    /// true for terminals
+    terminal: Vec<bool>,
+    /// the terminals by name
+    terminals: HashMap<String, Symbol>,
+    /// the non-terminals by name
+    non_terminals: HashMap<String, Symbol>,
+}
+
+impl Symbols {
+
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    /// the symbol of the terminal `name`, it is added if it is new
+    pub fn intern_terminal(&mut self, name: &str) -> Symbol {
+        if let Some(&symbol) = self.terminals.get(name) {
+            return symbol;
+        }
+        let symbol = self.push(name, name.replace("\\n", "\n"), true);
+        self.terminals.insert(name.to_string(), symbol);
+        symbol
+    }
+
+    /// the symbol of the non-terminal `name`, it is added if it is new
+    pub fn intern_non_terminal(&mut self, name: &str) -> Symbol {
+        if let Some(&symbol) = self.non_terminals.get(name) {
+            return symbol;
+        }
+        let symbol = self.push(name, name.to_string(), false);
+        self.non_terminals.insert(name.to_string(), symbol);
+        symbol
+    }
+
+    fn push(&mut self, name: &str, text: String, terminal: bool) -> Symbol {
+        self.names.push(name.to_string());
+        self.text.push(text);
+        self.terminal.push(terminal);
+        self.names.len() - 1
+    }
+
+    /// the terminal `name`, if the grammar has it
+    pub fn terminal(&self, name: &str) -> Option<Symbol> {
+        self.terminals.get(name).copied()
+    }
+
+    /// the non-terminal `name`, if the grammar has it
+    pub fn non_terminal(&self, name: &str) -> Option<Symbol> {
+        self.non_terminals.get(name).copied()
+    }
+
+    /// the name of `symbol` as written in the grammar file
+    pub fn name(&self, symbol: Symbol) -> &str {
+        &self.names[symbol]
+    }
+
+    /// the text of `symbol` in a sentence
+    pub fn text(&self, symbol: Symbol) -> &str {
+        &self.text[symbol]
+    }
+
+    /// check if `symbol` is a terminal
+    pub fn is_terminal(&self, symbol: Symbol) -> bool {
+        self.terminal[symbol]
+    }
+
+    /// the number of symbols
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// true if there are no symbols
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+}
+
+
+// TESTING
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern() {
+        let mut symbols = Symbols::new();
+        let a = symbols.intern_non_terminal("A");
+        let t = symbols.intern_terminal("A");
+        let n = symbols.intern_terminal("\\n");
+        assert_ne!(a, t);
+        assert_eq!(symbols.intern_non_terminal("A"), a);
+        assert_eq!(symbols.terminal("A"), Some(t));
+        assert!(symbols.is_terminal(t) && !symbols.is_terminal(a));
+        assert_eq!(symbols.name(n), "\\n");
+        assert_eq!(symbols.text(n), "\n");
+        assert_eq!(symbols.len(), 3);
+    }
+}
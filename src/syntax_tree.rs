@@ -19,95 +19,266 @@
 
 /*
     Description:
-        internal representation of a syntax tree
+        internal representation of a syntax tree.
+        the nodes of a tree are stored in an arena, a node refers to
+        its children by their indices. the children of a node are
+        stored next to each other and after the node, so the tree is
+        walked with an explicit stack instead of recursion and deep
+        trees do not overflow the stack. a node holds a symbol of
+        the grammar, the text of the terminals is looked up in the
+        symbol table when the tree is built.
 
     Author: 0xca7
 */
 
 use std::fmt;
+use std::ops::Range;
 
-extern crate fnv;
-use fnv::FnvHash;
+extern crate xshift;
+use xshift::XorShift64;
+
+use crate::layout::{Layout, LayoutKind, Renderer};
+use crate::symbol::{Symbol, Symbols};
+
+/// the index of a node in a `SyntaxTree`
+pub type NodeId = usize;
+
+/// parameters of the FNV-1a hash of a tree, see `SyntaxTree::hash`
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME:        u64 = 0x100000001b3;
 
 /// a node in a derivation tree 
-/// this node contains the node `value` and a list of `children`,
-/// the children are child nodes of the node
+/// this node contains the node `value` and the indices of its
+/// `children` in the `SyntaxTree`
 #[derive(Debug,Clone)]
 pub struct TreeNode {
-    /// the value stored in this node
-    pub value: String,
-    /// a list of child nodes
-    /// if None, the symbol is a terminal
-    pub children: Option<Vec<TreeNode>>,
+    /// the symbol stored in this node
+    pub value: Symbol,
+    /// the range of child nodes
+    /// if None, the symbol is a terminal or not expanded yet
+    pub children: Option<Range<NodeId>>,
 }
 
-impl TreeNode {
+/// a derivation tree, the nodes are stored in an arena
+#[derive(Debug,Clone)]
+pub struct SyntaxTree {
+    /// the nodes, the root comes first
+    nodes: Vec<TreeNode>,
+}
 
-    /// create a new tree node
-    pub fn new(v: &String) -> TreeNode {
-        TreeNode {
-            value: v.clone(),
-            children: None,
+impl SyntaxTree {
+
+    /// the index of the root node
+    pub const ROOT: NodeId = 0;
+
+    /// create a new tree consisting of the root `v`
+    pub fn new(v: Symbol) -> SyntaxTree {
+        SyntaxTree {
+            nodes: vec![TreeNode {
+                value: v,
+                children: None,
+            }],
         }
     } // pub fn new
 
-    /// insert a new child item, with a `value`
-    /// if no children exist, this function creates a vector
-    /// to hold the children
-    pub fn insert_child(&mut self, value: &String) {
-        if self.children.is_none() {
-            self.children = Some(Vec::new());
-        }
-        self.children
-            .as_mut().unwrap().push(TreeNode::new(&value.clone()));
+    /// the number of nodes in the tree
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// a tree always has a root
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// the node `id`
+    pub fn node(&self, id: NodeId) -> &TreeNode {
+        &self.nodes[id]
+    }
+
+    /// the value of node `id`
+    pub fn value(&self, id: NodeId) -> Symbol {
+        self.nodes[id].value
+    }
+
+    /// the children of node `id`, `None` if it is a leaf
+    pub fn children(&self, id: NodeId) -> Option<Range<NodeId>> {
+        self.nodes[id].children.clone()
+    }
+
+    /// the values of the children of node `id`
+    pub fn child_values(&self, id: NodeId) -> Option<Vec<Symbol>> {
+        self.children(id)
+            .map(|c| c.map(|child| self.value(child)).collect())
+    }
+
+    /// expand node `id` by a child node for each of the `symbols`,
+    /// the indices of the children are returned
+    pub fn expand(&mut self, id: NodeId, symbols: &[Symbol])
+        -> Range<NodeId> {
+        let children = self.nodes.len()..self.nodes.len() + symbols.len();
+        self.nodes.extend(symbols.iter().map(|&s| TreeNode {
+            value: s,
+            children: None,
+        }));
+        self.nodes[id].children = Some(children.clone());
+        children
+    }
+
+    /// undo the expansion of node `id`, the tree had `len` nodes
+    /// before it was expanded. later expansions are undone as well.
+    pub fn prune(&mut self, id: NodeId, len: usize) {
+        self.nodes.truncate(len);
+        self.nodes[id].children = None;
+    }
+
+    /// the nodes of the subtree of `id` in pre-order
+    pub fn preorder(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        let mut stack = vec![id];
+        std::iter::from_fn(move || {
+            let id = stack.pop()?;
+            if let Some(children) = self.children(id) {
+                stack.extend(children.rev());
+            }
+            Some(id)
+        })
     }
 
     /// when a syntax tree is built and by derivation, we can 
     /// get all leaf elements to form a sentence produced by the
-    /// grammar used to generate the tree 
-    pub fn build(&self, s: &mut String) {
+    /// grammar used to generate the tree, their text is looked up
+    /// in `symbols`. nothing is put between the leaf elements,
+    /// this is the `none` layout.
+    pub fn build(&self, symbols: &Symbols, s: &mut String) {
+        self.build_node(symbols, SyntaxTree::ROOT, s);
+    } // fn build
 
-        if self.children.is_none() {
-            s.push_str(&self.value);
+    /// build the sentence of the subtree of `id`
+    pub fn build_node(&self, symbols: &Symbols, id: NodeId, s: &mut String) {
+        for node in self.preorder(id).map(|n| &self.nodes[n]) {
+            if node.children.is_none() {
+                s.push_str(symbols.text(node.value));
+            }
         }
+    }
 
-        if self.children.is_some() {
-            // now, derive further (unwrap safe, tree has children)
-            for child in self.children.as_ref().unwrap() {
-                //print!("child: {:?}\n", child);
-                child.build(s);
-            }
+    /// build the sentence with a `layout`, which decides what is
+    /// put between two tokens. `rand` is only used by random layouts.
+    pub fn build_with(&self, symbols: &Symbols, layout: &Layout,
+        rand: Option<&mut XorShift64>, s: &mut String) {
+        if layout.kind == LayoutKind::None {
+            return self.build(symbols, s);
+        }
+        let mut renderer = Renderer::new(layout, rand);
+        self.render(symbols, &mut renderer, s);
+    }
+
+    /// walk the tree and pass tokens to the renderer,
+    /// a non-terminal is left after all of its children
+    fn render(&self, symbols: &Symbols, r: &mut Renderer, s: &mut String) {
 
+        // the nodes to visit and if they are left
+        let mut stack = vec![(SyntaxTree::ROOT, false)];
+
+        while let Some((id, leave)) = stack.pop() {
+            let node = &self.nodes[id];
+            let children = match &node.children {
+                None => {
+                    r.token(symbols.text(node.value), s);
+                    continue;
+                },
+                Some(children) => children.clone(),
+            };
+            let name = symbols.name(node.value);
+            if leave {
+                r.leave(name);
+                continue;
+            }
+            r.enter(name);
+            stack.push((id, true));
+            // lexical non-terminals are a single token
+            if r.layout.tokens.contains(name) {
+                let mut token = String::new();
+                self.build_node(symbols, id, &mut token);
+                r.token(&token, s);
+            } else {
+                stack.extend(children.rev().map(|c| (c, false)));
+            }
         }
-    } // fn build
+    }
 
-    /// hash the syntax tree to ensure it is unique
-    pub fn hash(&self) -> u64 {
+    /// the tree as text, a node per line indented by its depth.
+    /// the terminals are quoted like in the grammar file.
+    pub fn dump(&self, symbols: &Symbols) -> String {
         let mut s = String::new();
-        let mut fnv = FnvHash::new();
-        self.collect(&mut s);
-        fnv.hash(s.as_bytes())
+        let mut stack = vec![(SyntaxTree::ROOT, 0)];
+        while let Some((id, depth)) = stack.pop() {
+            let value = self.nodes[id].value;
+            s.push_str(&"  ".repeat(depth));
+            if symbols.is_terminal(value) {
+                s.push_str(&format!("'{}'\n", symbols.name(value)));
+            } else {
+                s.push_str(&format!("{}\n", symbols.name(value)));
+            }
+            if let Some(children) = self.children(id) {
+                stack.extend(children.rev().map(|c| (c, depth + 1)));
+            }
+        }
+        s
     }
 
-    /// create one string from all the elements in the
-    /// tree by pre-order traversal, for hashing
-    fn collect(&self, s: &mut String) {
-
-        s.push_str(&self.value);
-        if self.children.is_some() {
-            for child in self.children.as_ref().unwrap() {
-                child.collect(s);
+    /// the depth of the tree, a single node has depth zero
+    pub fn depth(&self) -> usize {
+        // children come after their parent, so they are done first
+        let mut depths = vec![0; self.nodes.len()];
+        for id in (0..self.nodes.len()).rev() {
+            if let Some(children) = self.children(id) {
+                depths[id] = 1 + children.map(|c| depths[c]).max().unwrap_or(0);
             }
-        } // traverse 
+        }
+        depths[SyntaxTree::ROOT]
+    }
 
+    /// hash the syntax tree to ensure it is unique.
+    /// the symbols of the nodes are hashed in pre-order, FNV-1a is
+    /// applied to a whole symbol at once instead of to each byte.
+    pub fn hash(&self) -> u64 {
+        self.preorder(SyntaxTree::ROOT).fold(FNV_OFFSET_BASIS, |hash, id|
+            (hash ^ self.nodes[id].value as u64).wrapping_mul(FNV_PRIME))
     }
 
 }
 
 /// currently unimplemented, will be in future
-impl fmt::Display for TreeNode {
+impl fmt::Display for SyntaxTree {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "unimplemented")
     }
 }
 
+
+// TESTING
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deep_tree() {
+        // a chain far deeper than the stack could recurse
+        let mut symbols = Symbols::new();
+        let (a, ta, tb) = (symbols.intern_non_terminal("A"),
+            symbols.intern_terminal("a"), symbols.intern_terminal("b"));
+        let mut tree = SyntaxTree::new(a);
+        let mut id = SyntaxTree::ROOT;
+        for _ in 0..1_000_000 {
+            id = tree.expand(id, &[ta, a]).end - 1;
+        }
+        tree.expand(id, &[tb]);
+        let mut s = String::new();
+        tree.build(&symbols, &mut s);
+        assert_eq!(s.len(), 1_000_001);
+        assert_eq!(tree.depth(), 1_000_001);
+        assert_eq!(tree.hash(), tree.clone().hash());
+    }
+}
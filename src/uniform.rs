@@ -0,0 +1,280 @@
+/*
+    grampus - a crappy grammar fuzzer
+    Copyright (C) 2022  0xca7
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+    Description:
+        uniform random sampling of derivation trees. choosing
+        alternatives at random favors short derivations, large parts
+        of a language are (almost) never produced. instead, the number
+        of derivation trees of each non-terminal and output length is
+        counted, a tree of a given length is then sampled by choosing
+        alternatives and splits of the length proportional to the
+        number of trees below them. every tree of that length is
+        equally likely (McKenzie, Hickey and Cohen).
+
+    Notes:
+        the counts grow exponentially with the length, they are kept
+        as natural logarithms. rules which derive the empty string in
+        a cycle have infinitely many trees per length, these are only
+        counted up to `MAX_ROUNDS` nested cycles.
+
+    Author: 0xca7
+*/
+
+extern crate xshift;
+use xshift::XorShift64;
+
+use crate::grammar::Grammar;
+use crate::symbol::Symbol;
+use crate::syntax_tree::{SyntaxTree, NodeId};
+
+/// the max. output length trees are counted for
+pub const MAX_LENGTH: usize = 1024;
+/// the max. output length if none is given
+pub const DEFAULT_LENGTH: usize = 64;
+/// max. rounds of the fixpoint iteration for a single length
+const MAX_ROUNDS: usize = 32;
+/// counts closer than this (relative) are considered equal
+const EPSILON: f64 = 1e-12;
+
+/// ln(0), the count of lengths without a tree
+const ZERO: f64 = f64::NEG_INFINITY;
+
+/// ln(exp(a) + exp(b)) without leaving log-space
+fn log_add(a: f64, b: f64) -> f64 {
+    if a == ZERO {
+        return b;
+    }
+    if b == ZERO {
+        return a;
+    }
+    let max = a.max(b);
+    max + ((a - max).exp() + (b - max).exp()).ln()
+}
+
+/// a random number in [0, 1)
+fn uniform(rand: &mut XorShift64) -> f64 {
+    (rand.rand() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// pick an index with probability proportional to exp(weights[i]),
+/// `total` is the sum of the weights in log-space
+fn pick(rand: &mut XorShift64, weights: &[f64], total: f64) -> Option<usize> {
+    let mut r = uniform(rand);
+    let mut last = None;
+    for (i, &w) in weights.iter().enumerate() {
+        if w == ZERO {
+            continue;
+        }
+        let p = (w - total).exp();
+        if r < p {
+            return Some(i);
+        }
+        r -= p;
+        last = Some(i);
+    }
+    // rounding errors, the last possible choice
+    last
+}
+
+/// the number of derivation trees of each non-terminal and length
+#[derive(Debug, Clone)]
+pub struct Counts {
+    /// trees are counted for lengths up to this
+    max_length: usize,
+    /// ln of the number of trees of each non-terminal per length,
+    /// indexed by symbol
+    symbols: Vec<Vec<f64>>,
+    /// ln of the number of trees of each suffix of each alternative
+    /// per length: `suffixes[nterm][alternative][position][length]`
+    suffixes: Vec<Vec<Vec<Vec<f64>>>>,
+}
+
+impl Counts {
+
+    /// count the trees of all non-terminals of `g` for all lengths
+    /// up to `max_length`, at most `MAX_LENGTH`
+    pub fn new(g: &Grammar, max_length: usize) -> Counts {
+
+        let max_length = max_length.min(MAX_LENGTH);
+
+        let mut counts = Counts {
+            max_length,
+            symbols: vec![vec![ZERO; max_length + 1]; g.symbols.len()],
+            suffixes: g.productions.iter()
+                .map(|alternatives| alternatives.iter()
+                    .map(|a| vec![vec![ZERO; max_length + 1]; a.len() + 1])
+                    .collect())
+                .collect(),
+        };
+
+        // the trees of length `n` only depend on shorter ones, except
+        // for siblings deriving the empty string
+        for n in 0..=max_length {
+            for _ in 0..MAX_ROUNDS {
+                if !counts.update(g, n) {
+                    break;
+                }
+            }
+        }
+
+        counts
+    }
+
+    /// the count of `symbol` with length `m`
+    fn count(&self, g: &Grammar, symbol: Symbol, m: usize) -> f64 {
+        if g.is_terminal(symbol) {
+            if g.symbols.text(symbol).len() == m { 0.0 } else { ZERO }
+        } else {
+            self.symbols[symbol][m]
+        }
+    }
+
+    /// one round of counting all trees of length `n`,
+    /// returns true if a count changed
+    fn update(&mut self, g: &Grammar, n: usize) -> bool {
+
+        let mut changed = false;
+
+        for &nterm in &g.order {
+            let alternatives = &g.productions[nterm];
+            let mut total = ZERO;
+            for (j, alternative) in alternatives.iter().enumerate() {
+                let k = alternative.len();
+                for i in (0..=k).rev() {
+                    let suffix = &self.suffixes[nterm][j];
+                    let c = if i == k {
+                        if n == 0 { 0.0 } else { ZERO }
+                    } else if g.is_terminal(alternative[i]) {
+                        let len = g.symbols.text(alternative[i]).len();
+                        if len <= n { suffix[i + 1][n - len] } else { ZERO }
+                    } else {
+                        (0..=n).fold(ZERO, |acc, m| log_add(acc,
+                            self.count(g, alternative[i], m)
+                                + suffix[i + 1][n - m]))
+                    };
+                    self.suffixes[nterm][j][i][n] = c;
+                }
+                total = log_add(total, self.suffixes[nterm][j][0][n]);
+            }
+            let old = &mut self.symbols[nterm][n];
+            if (total - *old).abs() > EPSILON * total.abs().max(1.0)
+                && !(total == ZERO && *old == ZERO) {
+                *old = total;
+                changed = true;
+            }
+        }
+
+        changed
+    }
+
+    /// ln of the number of trees of `symbol` with length `n`
+    pub fn trees(&self, symbol: Symbol, n: usize) -> f64 {
+        self.symbols.get(symbol)
+            .and_then(|c| c.get(n).copied())
+            .unwrap_or(ZERO)
+    }
+
+    /// the lengths between `min` and `max` for which `symbol`
+    /// has at least one tree
+    pub fn lengths(&self, symbol: Symbol, min: usize, max: usize)
+        -> Vec<usize> {
+        (min..=max.min(self.max_length))
+            .filter(|&n| self.trees(symbol, n) != ZERO)
+            .collect()
+    }
+
+    /// sample a tree of `symbol` with length `n`, every tree is
+    /// equally likely. `None` if there is no such tree.
+    pub fn sample(&self, rand: &mut XorShift64, g: &Grammar,
+        symbol: Symbol, n: usize) -> Option<SyntaxTree> {
+
+        let mut tree = SyntaxTree::new(symbol);
+        let mut stack = vec![(SyntaxTree::ROOT, n)];
+        while let Some((id, n)) = stack.pop() {
+            stack.extend(self.derive(rand, g, &mut tree, id, n)?);
+        }
+        Some(tree)
+    }
+
+    /// expand node `id` of `tree` to a tree of length `n`,
+    /// returns the children and their lengths
+    fn derive(&self, rand: &mut XorShift64, g: &Grammar,
+        tree: &mut SyntaxTree, id: NodeId, n: usize)
+        -> Option<Vec<(NodeId, usize)>> {
+
+        let nterm = tree.value(id);
+        if g.is_terminal(nterm) {
+            return Some(Vec::new());
+        }
+
+        let suffixes = &self.suffixes[nterm];
+        let weights: Vec<f64> = suffixes.iter().map(|s| s[0][n]).collect();
+        let j = pick(rand, &weights, self.trees(nterm, n))?;
+        let alternative = &g.productions[nterm][j];
+
+        // split the length among the symbols, left to right
+        let mut rest = n;
+        let mut lengths = Vec::with_capacity(alternative.len());
+        for (i, &symbol) in alternative.iter().enumerate() {
+            let weights: Vec<f64> = (0..=rest)
+                .map(|m| self.count(g, symbol, m) + suffixes[j][i + 1][rest - m])
+                .collect();
+            let m = pick(rand, &weights, suffixes[j][i][rest])?;
+            lengths.push(m);
+            rest -= m;
+        }
+
+        let children = tree.expand(id, alternative);
+        Some(children.zip(lengths).collect())
+    }
+
+}
+
+
+// TESTING
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_trees() {
+        let g = Grammar::new("grammars/expr.txt", 100).unwrap();
+        let counts = Counts::new(&g, 4);
+        let symbol = |name: &str| g.non_terminal(name).unwrap();
+        // ten digits and 100 two-digit integers
+        assert!((counts.trees(symbol("digit"), 1) - 10f64.ln()).abs() < 1e-9);
+        assert!((counts.trees(symbol("integer"), 2) - 100f64.ln()).abs() < 1e-9);
+        assert_eq!(counts.trees(symbol("digit"), 2), ZERO);
+    }
+
+    #[test]
+    fn test_sample_length() {
+        let g = Grammar::new("grammars/expr.txt", 100).unwrap();
+        let counts = Counts::new(&g, 16);
+        let mut rand = XorShift64::new(0x1234).unwrap();
+        let expr = g.non_terminal("expr").unwrap();
+        for n in counts.lengths(expr, 0, 16) {
+            let mut s = String::new();
+            counts.sample(&mut rand, &g, expr, n).unwrap()
+                .build(&g.symbols, &mut s);
+            assert_eq!(s.len(), n);
+        }
+    }
+}
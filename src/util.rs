@@ -29,18 +29,26 @@ use std::fs;
 use std::fs::File;
 use std::io::BufReader;
 use std::path::Path;
+use std::time::SystemTime;
 use std::io::prelude::*;
 
 use crate::grammar_parser::PRODUCTION;
+use crate::layout::DIRECTIVE;
 
 /// check if a file located at `filepath` exists
 pub fn file_exists(filepath: &String) -> bool {
     Path::new(filepath).exists()
 }
 
-/// check the first line of a grammar file `filename` and
-/// extract the start symbol. check if this start symbol 
-/// matches with the one `expected`.
+/// the time the file at `filepath` was last modified, `None`
+/// if the file does not exist or the time is not available
+pub fn modification_time(filepath: &str) -> Option<SystemTime> {
+    fs::metadata(filepath).and_then(|m| m.modified()).ok()
+}
+
+/// check if the grammar file `filename` has a rule for the start
+/// symbol `expected`. any rule will do, the first one is the usual
+/// start symbol, the others derive parts of the language.
 pub fn check_start_symbol(filepath: &String, expected: &String) -> bool {
 
     let file = match fs::File::open(filepath) {
@@ -48,36 +56,76 @@ pub fn check_start_symbol(filepath: &String, expected: &String) -> bool {
         Err(_) => panic!("to open file {}", filepath),
     };
 
-    let mut buffer = BufReader::new(file);
-    let mut line = String::new();
-
-    buffer.read_line(&mut line).expect("Unable to read line");
-
-    line = match line.split(PRODUCTION).next() {
-        Some(s) => s.to_string(),
-        None => return false,
-    };
-
-    // remove any whitespaces from the symbol
-    line.retain(|c| !c.is_whitespace());
-
-    if line == *expected {
-        return true;
+    for line in BufReader::new(file).lines() {
+        let line = line.expect("Unable to read line");
+        // the grammar ends at the first empty line
+        if line.trim().is_empty() {
+            break;
+        }
+        // skip directives
+        if line.starts_with(DIRECTIVE) {
+            continue;
+        }
+        // the symbol left of `::=`, without whitespace
+        if let Some(symbol) = line.split(PRODUCTION).next() {
+            if symbol.trim() == *expected {
+                return true;
+            }
+        }
     }
+
     false
 }
 
 
 
+/// the path of the file `name` of this process in the temp dir,
+/// so concurrent test runs do not clobber each other's files
+#[cfg(test)]
+pub(crate) fn test_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("grampus_{}_{}", std::process::id(),
+        name))
+}
+
 /// generates a seed for a RNG 
 /// WARNING: contains unsafe code
+#[cfg(target_arch = "x86_64")]
 pub fn generate_seed() -> u64 {
     unsafe { core::arch::x86_64::_rdtsc() }
 }
 
+/// generates a seed for a RNG from the system time and the
+/// randomly keyed hasher of the standard library
+#[cfg(not(target_arch = "x86_64"))]
+pub fn generate_seed() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+    use std::collections::hash_map::RandomState;
+
+    let mut hasher = RandomState::new().build_hasher();
+    if let Ok(time) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        hasher.write_u128(time.as_nanos());
+    }
+    hasher.finish()
+}
+
+/// the seed of the independent stream number `stream` derived
+/// from the master `seed` (splitmix64), it is never zero
+pub fn stream_seed(seed: u64, stream: u64) -> u64 {
+    let mut z = seed.wrapping_add(stream.wrapping_add(1)
+        .wrapping_mul(0x9e3779b97f4a7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^= z >> 31;
+    if z == 0 { 0x9e3779b97f4a7c15 } else { z }
+}
+
 /// write a crashfile with the input `fuzz` 
-/// to a file "crashes/hash(`fuzz`).txt"
-pub fn write_crashfile(fuzz: &Vec<u8>, hash: u64) -> std::io::Result<()> {
+/// to a file "crashes/hash(`fuzz`).txt", the master `seed` and
+/// the `thread_id` which found it are written to
+/// "crashes/hash(`fuzz`).seed" to replay the run
+#[allow(clippy::ptr_arg)]
+pub fn write_crashfile(fuzz: &Vec<u8>, hash: u64, seed: u64, thread_id: u32)
+    -> std::io::Result<()> {
 
     let filename = format!("crashes/{:x}.txt", hash);
     match File::create(filename) {
@@ -86,6 +134,10 @@ pub fn write_crashfile(fuzz: &Vec<u8>, hash: u64) -> std::io::Result<()> {
         },
         Err(e) => print!("error writing crashfile: {}", e),
     };
+
+    let filename = format!("crashes/{:x}.seed", hash);
+    let mut f = File::create(filename)?;
+    writeln!(f, "seed {:#x}\nthread {}", seed, thread_id)?;
     Ok(())
 }
 
@@ -93,6 +145,7 @@ pub fn write_crashfile(fuzz: &Vec<u8>, hash: u64) -> std::io::Result<()> {
 /// of the thread `thread_id` that created it.
 /// the filename is returned so it can be used as a parameter
 /// for the PUT.
+#[allow(clippy::ptr_arg)]
 pub fn write_input_file(content: &Vec<u8>, thread_id: u32) 
     -> std::io::Result<String> {
     let filename = format!("fuzz_inputs/{:02x}.txt", thread_id);
@@ -105,3 +158,20 @@ pub fn write_input_file(content: &Vec<u8>, thread_id: u32)
     };
     Ok(filename)
 }
+
+
+// TESTING
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_seed() {
+        // streams are reproducible and independent
+        assert_eq!(stream_seed(0x1234, 1), stream_seed(0x1234, 1));
+        assert_ne!(stream_seed(0x1234, 1), stream_seed(0x1234, 2));
+        assert_ne!(stream_seed(0x1234, 1), stream_seed(0x1235, 1));
+        assert_ne!(stream_seed(0, 0), 0);
+    }
+}
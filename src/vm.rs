@@ -0,0 +1,255 @@
+/*
+    grampus - a crappy grammar fuzzer
+    Copyright (C) 2022  0xca7
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+/*
+    Description:
+        a grammar compiled into a program for a small virtual machine,
+        as in "Building Fast Fuzzers" (Gopinath and Zeller). each rule
+        is a choice of the addresses of its alternatives, an
+        alternative emits its terminals and calls the rules of its
+        non-terminals. the machine writes the output directly into a
+        buffer, no syntax tree is built.
+
+    Notes:
+        the machine only supports random generation in the `none`
+        layout, without bounds on the depth or length, these need the
+        syntax trees of `corpus::derive`. the choices follow the
+        probabilities of the grammar and are counted in a coverage,
+        like those of `derive`.
+
+    Author: 0xca7
+*/
+
+extern crate xshift;
+use xshift::XorShift64;
+
+use crate::grammar::Grammar;
+use crate::symbol::Symbol;
+use crate::corpus::{choose, choose_weighted, MAX_DEPTH};
+use crate::coverage::GrammarCoverage;
+
+/// an instruction of the machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    /// choose an alternative of a rule and jump to it
+    Choice(Symbol),
+    /// write the text between two offsets of the text of the program
+    Emit(usize, usize),
+    /// call the code at an address
+    Call(usize),
+    /// return to the caller, the program ends if there is none
+    Return,
+}
+
+/// the alternatives of a rule
+#[derive(Debug, Clone, Default)]
+struct Rule {
+    /// the address of each alternative, unproductive ones have none
+    addresses:  Vec<Option<usize>>,
+    /// the productive alternatives
+    productive: Vec<usize>,
+    /// the alternatives of the least depth and length
+    cheapest:   Vec<usize>,
+}
+
+/// a grammar compiled for the machine
+#[derive(Debug, Clone)]
+pub struct Program {
+    /// the instructions
+    code:       Vec<Op>,
+    /// the text of all terminals, `Emit` refers to it
+    text:       String,
+    /// the rules by symbol, terminals have none
+    rules:      Vec<Rule>,
+    /// the address of the code of each rule
+    entries:    Vec<Option<usize>>,
+    /// the probabilities of the alternatives, see `%prob`
+    probabilities: Vec<Option<Vec<f64>>>,
+    /// number of choices after which only the cheapest
+    /// alternatives are chosen
+    max_choices: usize,
+    /// the return addresses, kept between runs
+    stack:      Vec<usize>,
+}
+
+impl Program {
+
+    /// compile the productive alternatives of the rules of `g`,
+    /// the grammar has to be validated
+    pub fn compile(g: &Grammar) -> Program {
+
+        let mut program = Program {
+            code:       Vec::new(),
+            text:       String::new(),
+            rules:      vec![Rule::default(); g.productions.len()],
+            entries:    vec![None; g.productions.len()],
+            probabilities: g.probabilities.clone(),
+            max_choices: g.max_non_terminals,
+            stack:      Vec::new(),
+        };
+
+        for &nterm in &g.order {
+            program.entries[nterm] = Some(program.code.len());
+            program.code.push(Op::Choice(nterm));
+
+            let bounds = &g.bounds[nterm];
+            let mut rule = Rule {
+                addresses: vec![None; bounds.len()],
+                ..Rule::default()
+            };
+            for (i, alternative) in g.productions[nterm].iter().enumerate() {
+                if bounds[i].is_none() {
+                    continue;
+                }
+                rule.addresses[i] = Some(program.code.len());
+                rule.productive.push(i);
+                for &s in alternative {
+                    if g.is_terminal(s) {
+                        program.emit(g.symbols.text(s));
+                    } else {
+                        // linked once all rules are placed
+                        program.code.push(Op::Call(s));
+                    }
+                }
+                program.code.push(Op::Return);
+            }
+
+            let cost = |i: &usize| bounds[*i].map(|b| (b.min.depth,
+                b.min.length));
+            let best = rule.productive.iter().filter_map(cost).min();
+            rule.cheapest = rule.productive.iter().copied()
+                .filter(|i| cost(i) == best)
+                .collect();
+            program.rules[nterm] = rule;
+        }
+
+        // calls refer to symbols until here, now to addresses.
+        // unwrap is safe, a productive alternative only has
+        // non-terminals with code
+        for i in 0..program.code.len() {
+            if let Op::Call(s) = program.code[i] {
+                program.code[i] = Op::Call(program.entries[s].unwrap());
+            }
+        }
+
+        program
+    }
+
+    /// emit `text`, it is merged with a directly preceding `Emit`
+    fn emit(&mut self, text: &str) {
+        let start = self.text.len();
+        self.text.push_str(text);
+        let end = self.text.len();
+        match self.code.last_mut() {
+            Some(Op::Emit(_, last)) if *last == start => *last = end,
+            _ => self.code.push(Op::Emit(start, end)),
+        }
+    }
+
+    /// the number of instructions
+    pub fn len(&self) -> usize {
+        self.code.len()
+    }
+
+    /// true if the program has no instructions
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty()
+    }
+
+    /// run the program from the rule of `start` and write a random
+    /// sentence to `out`, which is cleared first. the choices are
+    /// counted in `coverage`. returns the depth of the syntax tree
+    /// of the sentence. like `derive`, only the cheapest alternatives
+    /// are chosen after `max_choices` choices or below `MAX_DEPTH`.
+    pub fn run(&mut self, rand: &mut XorShift64, start: Symbol,
+        coverage: &mut GrammarCoverage, out: &mut String) -> usize {
+
+        out.clear();
+        self.stack.clear();
+
+        let mut pc = match self.entries.get(start).copied().flatten() {
+            Some(entry) => entry,
+            None        => return 0,
+        };
+        let mut choices = 0;
+        let mut depth = 0;
+
+        loop {
+            match self.code[pc] {
+                Op::Choice(nterm) => {
+                    choices += 1;
+                    depth = depth.max(self.stack.len() + 1);
+                    let random = choices < self.max_choices
+                        && self.stack.len() < MAX_DEPTH;
+                    let rule = &self.rules[nterm];
+                    // unwrap is safe, a rule with code is productive
+                    let alt = match self.probabilities[nterm].as_ref()
+                        .filter(|_| random) {
+                        Some(probabilities) => choose_weighted(rand,
+                            &rule.productive, probabilities),
+                        None if random => choose(rand, &rule.productive),
+                        None => choose(rand, &rule.cheapest),
+                    }.unwrap();
+                    coverage.record(nterm, alt, !random);
+                    // unwrap is safe, only productive ones are chosen
+                    pc = rule.addresses[alt].unwrap();
+                },
+                Op::Emit(start, end) => {
+                    out.push_str(&self.text[start..end]);
+                    pc += 1;
+                },
+                Op::Call(address) => {
+                    self.stack.push(pc + 1);
+                    pc = address;
+                },
+                Op::Return => match self.stack.pop() {
+                    Some(address) => pc = address,
+                    None          => return depth,
+                },
+            }
+        }
+    }
+
+}
+
+
+// TESTING
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_program() {
+        let g = Grammar::new("grammars/expr.txt", 1000).unwrap();
+        let mut program = Program::compile(&g);
+        let start = g.non_terminal("start").unwrap();
+        let mut rand = XorShift64::new(0x7).unwrap();
+        let mut coverage = GrammarCoverage::new();
+        let mut out = String::new();
+        for _ in 0..100 {
+            let depth = program.run(&mut rand, start, &mut coverage, &mut out);
+            // start expr term factor integer digit, at least
+            assert!(depth >= 6);
+            assert!(out.chars().all(|c| "+-*/().0123456789".contains(c)));
+            assert_eq!(out.matches('(').count(), out.matches(')').count());
+        }
+        // every rule of expr is reachable from start
+        assert_eq!(coverage.summary(&g).rules, g.order.len());
+    }
+}